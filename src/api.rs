@@ -1,82 +1,793 @@
-use reqwest::{Client, Method};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use reqwest::header::{ACCEPT, HeaderMap, RETRY_AFTER};
+use reqwest::{
+    Certificate, Client, Identity, Method, NoProxy, Proxy, RequestBuilder, Response, StatusCode,
+};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
+use tokio_util::sync::CancellationToken;
 use tracing::instrument;
 
+use crate::cassette::{Cassette, CassetteError, CassetteMode, RequestKey};
+use crate::keypool::KeyPool;
+use crate::ratelimit::RateLimiter;
+
 const DEFAULT_BASE_URL: &str = "https://api.x.ai/v1";
 
+/// Default per-request timeout, covering connect, upload and the full response body.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
 /// Errors returned by the xAI API client.
 #[derive(Error, Debug)]
 pub enum ApiError {
     #[error("HTTP request failed: {0}")]
     Reqwest(#[from] reqwest::Error),
 
-    #[error("xAI API error ({status}): {body}")]
+    #[error("xAI API error ({status}): {detail}")]
     Api {
         status: reqwest::StatusCode,
-        body: String,
+        kind: ApiErrorKind,
+        detail: XaiErrorDetail,
+        retry_after: Option<Duration>,
     },
+
+    #[error("Failed to decode stream event: {0}")]
+    StreamDecode(#[from] serde_json::Error),
+
+    #[error("Request cancelled")]
+    Cancelled,
+
+    #[error("Cassette error: {0}")]
+    Cassette(#[from] CassetteError),
+}
+
+impl ApiError {
+    /// Build an [`ApiError::Api`] from a failed response's status and body.
+    pub fn from_response(status: StatusCode, body: &str, retry_after: Option<Duration>) -> Self {
+        let detail = XaiErrorDetail::parse(body);
+        Self::Api {
+            status,
+            kind: ApiErrorKind::classify(status, &detail),
+            detail,
+            retry_after,
+        }
+    }
+
+    /// The broad category of this error.
+    pub fn kind(&self) -> ApiErrorKind {
+        match self {
+            Self::Api { kind, .. } => *kind,
+            Self::Reqwest(e) if e.is_timeout() => ApiErrorKind::Timeout,
+            Self::Reqwest(e) if e.is_decode() => ApiErrorKind::Upstream,
+            Self::Reqwest(_) => ApiErrorKind::Network,
+            Self::StreamDecode(_) => ApiErrorKind::Upstream,
+            Self::Cancelled => ApiErrorKind::Cancelled,
+            Self::Cassette(CassetteError::Body(_)) => ApiErrorKind::InvalidRequest,
+            // The cassette stands in for the network.
+            Self::Cassette(_) => ApiErrorKind::Network,
+        }
+    }
+}
+
+/// Classification of API failures, for deciding how to react to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorKind {
+    /// Too many requests (HTTP 429).
+    RateLimited,
+    /// Missing, invalid or under-privileged API key (HTTP 401/403).
+    Unauthorized,
+    /// The requested model does not exist or is not available to this key.
+    ModelNotFound,
+    /// The prompt plus requested output exceeds the model's context window.
+    ContextLengthExceeded,
+    /// Any other client-side error (HTTP 4xx).
+    InvalidRequest,
+    /// xAI failed or returned something we could not decode (HTTP 5xx, 408).
+    Upstream,
+    /// The xAI API could not be reached at all.
+    Network,
+    /// The request did not complete within its timeout.
+    Timeout,
+    /// The caller cancelled the request.
+    Cancelled,
+}
+
+impl ApiErrorKind {
+    /// Classify an error response by status code and decoded body.
+    pub fn classify(status: StatusCode, detail: &XaiErrorDetail) -> Self {
+        let code = detail
+            .code
+            .as_deref()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let message = detail.message.to_ascii_lowercase();
+        let mentions_model = message.contains("model")
+            || code.contains("model")
+            || detail.param.as_deref() == Some("model");
+
+        match status.as_u16() {
+            429 => Self::RateLimited,
+            401 | 403 => Self::Unauthorized,
+            400..=499
+                if code.contains("context_length")
+                    || message.contains("context length")
+                    || message.contains("maximum prompt length")
+                    || message.contains("too many tokens") =>
+            {
+                Self::ContextLengthExceeded
+            }
+            400 | 404
+                if mentions_model
+                    && (status == StatusCode::NOT_FOUND
+                        || message.contains("does not exist")
+                        || message.contains("not found")) =>
+            {
+                Self::ModelNotFound
+            }
+            408 => Self::Upstream,
+            400..=499 => Self::InvalidRequest,
+            _ => Self::Upstream,
+        }
+    }
+
+    /// Stable machine-readable identifier for this kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::RateLimited => "rate_limited",
+            Self::Unauthorized => "unauthorized",
+            Self::ModelNotFound => "model_not_found",
+            Self::ContextLengthExceeded => "context_length_exceeded",
+            Self::InvalidRequest => "invalid_request",
+            Self::Upstream => "upstream_error",
+            Self::Network => "network_error",
+            Self::Timeout => "timeout",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// Whether retrying the same request later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited | Self::Upstream | Self::Network | Self::Timeout
+        )
+    }
+}
+
+/// The error payload returned by xAI, decoded from either the OpenAI-style
+/// `{"error": {"message", "type", "param", "code"}}` envelope or xAI's flat
+/// `{"code", "error"}` form. Non-JSON bodies become the message verbatim.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XaiErrorDetail {
+    pub message: String,
+    pub code: Option<String>,
+    pub error_type: Option<String>,
+    pub param: Option<String>,
+}
+
+impl XaiErrorDetail {
+    /// Decode an error response body.
+    pub fn parse(body: &str) -> Self {
+        let body = body.trim();
+        let Ok(json) = serde_json::from_str::<Value>(body) else {
+            return Self {
+                message: body.to_string(),
+                ..Self::default()
+            };
+        };
+
+        let field = |v: &Value, key: &str| match v.get(key)? {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        };
+
+        let inner = match json.get("error") {
+            Some(e @ Value::Object(_)) => e,
+            _ => &json,
+        };
+        let message = field(inner, "message")
+            .or_else(|| field(&json, "error"))
+            .unwrap_or_else(|| body.to_string());
+
+        Self {
+            message,
+            code: field(inner, "code"),
+            error_type: field(inner, "type"),
+            param: field(inner, "param"),
+        }
+    }
+}
+
+impl fmt::Display for XaiErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(code) = &self.code {
+            write!(f, " (code: {code})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Retry policy for transient xAI API failures.
+///
+/// Connect errors and HTTP 408/429/500/502/503/504 are retried. A
+/// `Retry-After` header on the failed response takes precedence over the
+/// computed backoff. Clearing [`retry_unsafe`](Self::retry_unsafe) stops
+/// non-idempotent requests (POST) from being resent after failures xAI may
+/// already have acted on.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first. `1` disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled on every subsequent retry.
+    pub base_delay: Duration,
+    /// Upper bound on any single delay, including one requested by `Retry-After`.
+    pub max_delay: Duration,
+    /// Fraction (0.0 - 1.0) of each backoff delay that is randomised away.
+    pub jitter: f64,
+    /// Whether non-idempotent requests are retried on HTTP 408/500/502/504
+    /// and on 503 without `Retry-After`. When off they are only retried on
+    /// connect errors, 429, and 503 with `Retry-After`, where xAI did not
+    /// act on the request.
+    pub retry_unsafe: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+            retry_unsafe: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes exactly one attempt.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Exponential backoff before retry number `retry` (1-based), capped at `max_delay`.
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter * fastrand::f64())
+    }
+
+    /// Pick the delay before the next attempt: `Retry-After` if given, else
+    /// the backoff, either way capped at `max_delay`.
+    fn delay_for(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(d) => d.min(self.max_delay),
+            None => self.backoff(retry),
+        }
+    }
+
+    /// Whether a failed response is worth retrying.
+    fn retries(&self, method: &Method, status: StatusCode, retry_after: Option<Duration>) -> bool {
+        match status.as_u16() {
+            429 => true,
+            503 if retry_after.is_some() => true,
+            408 | 500 | 502 | 503 | 504 => self.retry_unsafe || method.is_idempotent(),
+            _ => false,
+        }
+    }
+}
+
+/// Parse a `Retry-After` header given either as delta-seconds or an HTTP date.
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return (secs.is_finite() && secs >= 0.0).then(|| Duration::from_secs_f64(secs));
+    }
+    let when = httpdate::parse_http_date(value).ok()?;
+    Some(
+        when.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Per-call overrides for a single API request.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    /// Overrides the client's default timeout for this request.
+    pub timeout: Option<Duration>,
+    /// Aborts the request, including any retry wait or open stream, when cancelled.
+    pub cancel: Option<CancellationToken>,
+}
+
+impl RequestOptions {
+    /// Run `fut` unless the cancellation token fires first.
+    async fn run<F: Future>(&self, fut: F) -> Result<F::Output, ApiError> {
+        match &self.cancel {
+            Some(ct) => ct.run_until_cancelled(fut).await.ok_or(ApiError::Cancelled),
+            None => Ok(fut.await),
+        }
+    }
+}
+
+/// Transport settings for the underlying HTTP client: proxy, extra trust
+/// roots, client certificate and connect timeout.
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    /// Proxy URL for all requests (`http://`, `https://` or `socks5://`).
+    /// When unset, the standard `HTTP(S)_PROXY` environment variables apply.
+    pub proxy: Option<String>,
+    /// Hosts, domains or CIDR ranges that bypass `proxy`.
+    pub no_proxy: Vec<String>,
+    /// PEM-encoded root certificates to trust in addition to the system store.
+    /// Each entry may contain several certificates.
+    pub ca_certs_pem: Vec<Vec<u8>>,
+    /// PEM certificate chain and PKCS#8 PEM private key for mutual TLS.
+    pub client_identity_pem: Option<(Vec<u8>, Vec<u8>)>,
+    /// Timeout for establishing the TCP/TLS connection only.
+    pub connect_timeout: Option<Duration>,
+}
+
+impl HttpOptions {
+    /// Build a reqwest client with these settings applied.
+    pub fn build_client(&self) -> Result<Client, ApiError> {
+        let mut builder = Client::builder();
+
+        if let Some(url) = &self.proxy {
+            let no_proxy = NoProxy::from_string(&self.no_proxy.join(","));
+            builder = builder.proxy(Proxy::all(url)?.no_proxy(no_proxy));
+        }
+        for pem in &self.ca_certs_pem {
+            for cert in Certificate::from_pem_bundle(pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = &self.client_identity_pem {
+            builder = builder.identity(Identity::from_pkcs8_pem(cert, key)?);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        Ok(builder.build()?)
+    }
 }
 
 /// Shared HTTP client for all xAI API calls.
 pub struct XaiClient {
-    api_key: String,
+    keys: KeyPool,
     base_url: String,
     http: Client,
+    retry: RetryPolicy,
+    timeout: Duration,
+    limiter: RateLimiter,
+    cassette: Option<Cassette>,
 }
 
 impl XaiClient {
     /// Create a new client pointing at the default xAI API base URL.
-    pub fn new(api_key: String) -> Self {
-        Self::with_base_url(api_key, DEFAULT_BASE_URL.to_string())
+    /// Accepts a single API key or a [`KeyPool`].
+    pub fn new(keys: impl Into<KeyPool>) -> Self {
+        Self::from_parts(keys.into(), DEFAULT_BASE_URL.to_string())
     }
 
     /// Create a new client with a custom base URL (useful for testing with mockito).
     pub fn with_base_url(api_key: String, base_url: String) -> Self {
+        Self::from_parts(KeyPool::single(api_key), base_url)
+    }
+
+    fn from_parts(keys: KeyPool, base_url: String) -> Self {
         Self {
-            api_key,
+            keys,
             base_url,
             http: Client::builder()
-                .timeout(Duration::from_secs(300))
                 .build()
                 .expect("Failed to build reqwest client"),
+            retry: RetryPolicy::default(),
+            timeout: DEFAULT_TIMEOUT,
+            limiter: RateLimiter::unlimited(),
+            cassette: None,
         }
     }
 
+    /// Rebuild the underlying HTTP client with proxy, TLS and connect-timeout settings.
+    pub fn with_http_options(mut self, opts: &HttpOptions) -> Result<Self, ApiError> {
+        self.http = opts.build_client()?;
+        Ok(self)
+    }
+
+    /// Record every exchange to, or replay every response from, a cassette file.
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// The cassette in use, if any.
+    pub fn cassette(&self) -> Option<&Cassette> {
+        self.cassette.as_ref()
+    }
+
+    /// Replace the API key(s) used for requests.
+    pub fn with_key_pool(mut self, keys: KeyPool) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Replace the client-side rate limiter shared by every request.
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Replace the default timeout applied to requests without an explicit one.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Replace the retry policy used for every request.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
     /// Unified HTTP request method — handles GET and POST with optional body.
-    #[instrument(skip(self, body), fields(path = %path))]
+    ///
+    /// Transient failures are retried according to the client's [`RetryPolicy`].
     pub async fn request<Req: Serialize, Resp: for<'de> Deserialize<'de>>(
         &self,
         method: Method,
         path: &str,
         body: Option<&Req>,
     ) -> Result<Resp, ApiError> {
+        self.request_with(method, path, body, &RequestOptions::default())
+            .await
+    }
+
+    /// [`request`](Self::request) with a per-call timeout and cancellation token.
+    #[instrument(skip(self, body, opts), fields(path = %path, key = tracing::field::Empty))]
+    pub async fn request_with<Req: Serialize, Resp: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Req>,
+        opts: &RequestOptions,
+    ) -> Result<Resp, ApiError> {
+        let response = self
+            .send(method, path, body, false, &self.retry, opts)
+            .await?;
+        Ok(opts.run(response.json::<Resp>()).await??)
+    }
+
+    /// Like [`request`](Self::request), but returns the body as a stream of
+    /// server-sent events. The caller is responsible for setting `stream: true`
+    /// on the request body.
+    ///
+    /// Retries only cover establishing the stream; once the first byte has
+    /// arrived, errors are surfaced through [`SseStream::next_event`].
+    pub async fn request_stream<Req: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Req>,
+    ) -> Result<SseStream, ApiError> {
+        self.request_stream_with(method, path, body, &RequestOptions::default())
+            .await
+    }
+
+    /// [`request_stream`](Self::request_stream) with a per-call timeout and
+    /// cancellation token. Cancelling also ends the returned stream.
+    #[instrument(skip(self, body, opts), fields(path = %path, key = tracing::field::Empty))]
+    pub async fn request_stream_with<Req: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Req>,
+        opts: &RequestOptions,
+    ) -> Result<SseStream, ApiError> {
+        let response = self
+            .send(method, path, body, true, &self.retry, opts)
+            .await?;
+        Ok(SseStream::new(response, opts.cancel.clone()))
+    }
+
+    /// Queue a chat completion to run in the background and return its request id.
+    ///
+    /// Never retried: a submission that failed or timed out may still have
+    /// been queued, and resending it would run the job twice.
+    #[instrument(skip(self, req, opts), fields(key = tracing::field::Empty))]
+    pub async fn submit_deferred(
+        &self,
+        mut req: ChatRequest,
+        opts: &RequestOptions,
+    ) -> Result<String, ApiError> {
+        req.deferred = Some(true);
+        req.stream = None;
+        let response = self
+            .send(
+                Method::POST,
+                "/chat/completions",
+                Some(&req),
+                false,
+                &RetryPolicy::none(),
+                opts,
+            )
+            .await?;
+        let resp: DeferredChatResponse = opts.run(response.json()).await??;
+        Ok(resp.request_id)
+    }
+
+    /// Fetch the result of a deferred chat completion: `None` while it is
+    /// still running. xAI serves each result for 24 hours.
+    #[instrument(skip(self, opts), fields(key = tracing::field::Empty))]
+    pub async fn deferred_result(
+        &self,
+        request_id: &str,
+        opts: &RequestOptions,
+    ) -> Result<Option<ChatResponse>, ApiError> {
+        let path = format!("/chat/deferred-completion/{request_id}");
+        let response = self
+            .send::<()>(Method::GET, &path, None, false, &self.retry, opts)
+            .await?;
+        if response.status() == StatusCode::ACCEPTED {
+            return Ok(None);
+        }
+        Ok(Some(opts.run(response.json()).await??))
+    }
+
+    /// Send a request, retrying transient failures under `retry`, and return
+    /// the first successful response.
+    ///
+    /// HTTP 401/429 fail over to the next untried key in the pool immediately,
+    /// without consuming a retry attempt.
+    async fn send<Req: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Req>,
+        stream: bool,
+        retry: &RetryPolicy,
+        opts: &RequestOptions,
+    ) -> Result<Response, ApiError> {
         let url = format!("{}{path}", self.base_url);
-        let mut builder = self
-            .http
-            .request(method, &url)
-            .header("Authorization", format!("Bearer {}", self.api_key));
+        let tokens = match body {
+            Some(b) if self.limiter.limits_tokens() => serde_json::to_vec(b)
+                .map(|v| RateLimiter::estimate_tokens(v.len()))
+                .unwrap_or(0),
+            _ => 0,
+        };
+        let cassette_key = match (&self.cassette, body) {
+            (None, _) => None,
+            (Some(_), Some(b)) => Some(RequestKey::new(
+                &method,
+                path,
+                Some(serde_json::to_value(b).map_err(CassetteError::Body)?),
+            )),
+            (Some(_), None) => Some(RequestKey::new(&method, path, None)),
+        };
+        let mut attempt = 1;
+        let mut tried_keys = Vec::new();
+
+        loop {
+            let key = self.keys.pick(&tried_keys);
+            tracing::Span::current().record("key", self.keys.label(key));
+
+            opts.run(self.limiter.acquire(key, tokens)).await?;
+
+            let mut builder = self
+                .http
+                .request(method.clone(), &url)
+                .header("Authorization", format!("Bearer {}", self.keys.secret(key)))
+                .timeout(opts.timeout.unwrap_or(self.timeout));
+
+            if stream {
+                builder = builder.header(ACCEPT, "text/event-stream");
+            }
+            if let Some(b) = body {
+                builder = builder.json(b);
+            }
+
+            let (err, retry_after) =
+                match self.dispatch(builder, cassette_key.as_ref(), opts).await? {
+                    Ok(response) => {
+                        self.limiter.observe(key, response.headers());
+                        let status = response.status();
+                        if status.is_success() {
+                            return Ok(response);
+                        }
+
+                        let retry_after = parse_retry_after(response.headers());
+                        let body = match opts.run(response.text()).await? {
+                            Ok(text) => text,
+                            Err(e) => format!("<failed to read response body: {e}>"),
+                        };
+                        tracing::warn!(status = %status, attempt, "API request failed");
+                        let err = ApiError::from_response(status, &body, retry_after);
+
+                        if matches!(status.as_u16(), 401 | 429) {
+                            if status == StatusCode::UNAUTHORIZED {
+                                self.keys.mark_rejected(key);
+                            } else {
+                                self.keys.mark_limited(key);
+                            }
+                            if !tried_keys.contains(&key) {
+                                tried_keys.push(key);
+                            }
+                            if tried_keys.len() < self.keys.len() {
+                                tracing::warn!(status = %status, "failing over to next API key");
+                                continue;
+                            }
+                        }
+                        if !retry.retries(&method, status, retry_after) {
+                            return Err(err);
+                        }
+                        (err, retry_after)
+                    }
+                    Err(e) if e.is_connect() => {
+                        tracing::warn!(error = %e, attempt, "API connection failed");
+                        (ApiError::Reqwest(e), None)
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+            if attempt >= retry.max_attempts {
+                return Err(err);
+            }
+            let delay = retry.delay_for(attempt, retry_after);
+
+            tracing::debug!(?delay, attempt, "retrying API request");
+            opts.run(tokio::time::sleep(delay)).await?;
+            attempt += 1;
+        }
+    }
+
+    /// Send one HTTP request, or serve it from / record it to the cassette.
+    async fn dispatch(
+        &self,
+        builder: RequestBuilder,
+        key: Option<&RequestKey>,
+        opts: &RequestOptions,
+    ) -> Result<reqwest::Result<Response>, ApiError> {
+        let (Some(cassette), Some(key)) = (&self.cassette, key) else {
+            return opts.run(builder.send()).await;
+        };
+
+        match cassette.mode() {
+            CassetteMode::Replay => Ok(Ok(cassette.replay(key)?)),
+            CassetteMode::Record => match opts.run(builder.send()).await? {
+                Ok(response) => Ok(Ok(opts.run(cassette.record(key, response)).await??)),
+                // Connection failures never produced a response to record.
+                Err(e) => Ok(Err(e)),
+            },
+        }
+    }
+}
 
-        if let Some(b) = body {
-            builder = builder.json(b);
+// ---------------------------------------------------------------------------
+// Server-sent events
+// ---------------------------------------------------------------------------
+
+/// A single server-sent event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// An incremental stream of server-sent events read from a streaming response.
+///
+/// The OpenAI-style `[DONE]` sentinel ends the stream.
+pub struct SseStream {
+    body: BoxStream<'static, reqwest::Result<bytes::Bytes>>,
+    cancel: Option<CancellationToken>,
+    buf: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+    done: bool,
+}
+
+impl SseStream {
+    fn new(response: Response, cancel: Option<CancellationToken>) -> Self {
+        Self {
+            body: response.bytes_stream().boxed(),
+            cancel,
+            buf: Vec::new(),
+            event: None,
+            data: Vec::new(),
+            done: false,
         }
+    }
 
-        let response = builder.send().await?;
+    /// Return the next event, or `None` once the stream is exhausted.
+    pub async fn next_event(&mut self) -> Option<Result<SseEvent, ApiError>> {
+        loop {
+            while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
+                if let Some(event) = self.feed_line(line.trim_end_matches(['\n', '\r'])) {
+                    return self.emit(event);
+                }
+            }
 
-        let status = response.status();
-        if !status.is_success() {
-            let body = match response.text().await {
-                Ok(text) => text,
-                Err(e) => format!("<failed to read response body: {e}>"),
+            if self.done {
+                return None;
+            }
+
+            let next = match &self.cancel {
+                Some(ct) => ct.run_until_cancelled(self.body.next()).await,
+                None => Some(self.body.next().await),
+            };
+            let Some(next) = next else {
+                self.done = true;
+                return Some(Err(ApiError::Cancelled));
             };
-            tracing::warn!(status = %status, "API request failed");
-            return Err(ApiError::Api { status, body });
+
+            match next {
+                Some(Ok(bytes)) => self.buf.extend_from_slice(&bytes),
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+                None => {
+                    // Flush a trailing line and event that lack a terminating blank line.
+                    self.done = true;
+                    let rest = String::from_utf8_lossy(&std::mem::take(&mut self.buf)).into_owned();
+                    let event = match self.feed_line(rest.trim_end_matches('\r')) {
+                        Some(event) => Some(event),
+                        None => self.feed_line(""),
+                    };
+                    return event.and_then(|e| self.emit(e));
+                }
+            }
         }
+    }
 
-        Ok(response.json::<Resp>().await?)
+    /// Return the next event with its data decoded as JSON.
+    pub async fn next_json<T: DeserializeOwned>(&mut self) -> Option<Result<T, ApiError>> {
+        let event = self.next_event().await?;
+        Some(event.and_then(|e| Ok(serde_json::from_str(&e.data)?)))
+    }
+
+    /// Apply one line of the SSE wire format, returning an event on a blank line.
+    fn feed_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            if self.data.is_empty() {
+                self.event = None;
+                return None;
+            }
+            return Some(SseEvent {
+                event: self.event.take(),
+                data: std::mem::take(&mut self.data).join("\n"),
+            });
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "data" => self.data.push(value.to_string()),
+            "event" => self.event = Some(value.to_string()),
+            // Comments (empty field name), `id` and `retry` are irrelevant here.
+            _ => {}
+        }
+        None
+    }
+
+    fn emit(&mut self, event: SseEvent) -> Option<Result<SseEvent, ApiError>> {
+        if event.data == "[DONE]" {
+            self.done = true;
+            self.buf.clear();
+            return None;
+        }
+        Some(Ok(event))
     }
 }
 
@@ -96,21 +807,280 @@ pub struct ChatRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub response_format: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tools: Option<Vec<Value>>,
+    pub tools: Option<Vec<ToolDefinition>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Queue the completion and return a request id instead of the result.
+    /// Set by [`XaiClient::submit_deferred`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deferred: Option<bool>,
 }
 
 /// A single message in a chat conversation.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
     pub role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<MessageContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_calls: Option<Vec<Value>>,
+    pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
 }
 
+/// The content of a [`ChatMessage`]: a plain string or a list of parts.
+///
+/// Deserializing checks the shape of every part, so malformed history is
+/// rejected with the offending part's index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "Value")]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The concatenated text of the content, ignoring non-text parts.
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| match p {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Whether any part is an image.
+    pub fn has_images(&self) -> bool {
+        matches!(self, Self::Parts(parts) if parts.iter().any(|p| matches!(p, ContentPart::ImageUrl { .. })))
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Text(text) => text.is_empty(),
+            Self::Parts(parts) => parts.is_empty(),
+        }
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        Self::Text(text.into())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl TryFrom<Value> for MessageContent {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(text) => Ok(Self::Text(text)),
+            Value::Array(items) => items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    serde_json::from_value(item).map_err(|e| format!("content part {i}: {e}"))
+                })
+                .collect::<Result<_, _>>()
+                .map(Self::Parts),
+            _ => Err("content must be a string or an array of content parts".into()),
+        }
+    }
+}
+
+/// One part of a multimodal message.
+///
+/// New part types may be added; unknown `type`s fail to deserialize.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "ContentPartRepr", from = "ContentPartRepr")]
+#[non_exhaustive]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    /// An image by URL (`https://` or a `data:` URI). `detail` is `low`,
+    /// `high` or `auto`.
+    ImageUrl {
+        url: String,
+        detail: Option<String>,
+    },
+}
+
+impl ContentPart {
+    pub fn text(text: &str) -> Self {
+        Self::Text { text: text.into() }
+    }
+
+    pub fn image_url(url: &str, detail: Option<&str>) -> Self {
+        Self::ImageUrl {
+            url: url.into(),
+            detail: detail.map(Into::into),
+        }
+    }
+}
+
+/// Wire format of [`ContentPart`]: image fields are nested under `image_url`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ContentPartRepr {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrlRepr },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImageUrlRepr {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl From<ContentPart> for ContentPartRepr {
+    fn from(part: ContentPart) -> Self {
+        match part {
+            ContentPart::Text { text } => Self::Text { text },
+            ContentPart::ImageUrl { url, detail } => Self::ImageUrl {
+                image_url: ImageUrlRepr { url, detail },
+            },
+        }
+    }
+}
+
+impl From<ContentPartRepr> for ContentPart {
+    fn from(repr: ContentPartRepr) -> Self {
+        match repr {
+            ContentPartRepr::Text { text } => Self::Text { text },
+            ContentPartRepr::ImageUrl { image_url } => Self::ImageUrl {
+                url: image_url.url,
+                detail: image_url.detail,
+            },
+        }
+    }
+}
+
+/// A tool the model may call, as sent in [`ChatRequest::tools`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    /// Always `"function"` for chat completions.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+/// The name, description and JSON Schema parameters of a callable function.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON Schema for the arguments object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// A function call requested by the model.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    /// Always `"function"` for chat completions.
+    #[serde(rename = "type", default = "function_kind")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+/// The function name and its arguments, which the model emits as a JSON string.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+fn function_kind() -> String {
+    "function".into()
+}
+
+/// Controls whether and which tool the model calls.
+///
+/// Serializes as `"none"`, `"auto"`, `"required"` or
+/// `{"type": "function", "function": {"name": ...}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "ToolChoiceRepr", try_from = "ToolChoiceRepr")]
+pub enum ToolChoice {
+    None,
+    Auto,
+    Required,
+    Function { name: String },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ToolChoiceRepr {
+    Mode(String),
+    Named {
+        #[serde(rename = "type")]
+        kind: String,
+        function: NamedFunction,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct NamedFunction {
+    name: String,
+}
+
+impl From<ToolChoice> for ToolChoiceRepr {
+    fn from(choice: ToolChoice) -> Self {
+        match choice {
+            ToolChoice::None => Self::Mode("none".into()),
+            ToolChoice::Auto => Self::Mode("auto".into()),
+            ToolChoice::Required => Self::Mode("required".into()),
+            ToolChoice::Function { name } => Self::Named {
+                kind: function_kind(),
+                function: NamedFunction { name },
+            },
+        }
+    }
+}
+
+impl TryFrom<ToolChoiceRepr> for ToolChoice {
+    type Error = String;
+
+    fn try_from(repr: ToolChoiceRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ToolChoiceRepr::Mode(mode) => match mode.as_str() {
+                "none" => Ok(Self::None),
+                "auto" => Ok(Self::Auto),
+                "required" => Ok(Self::Required),
+                other => Err(format!(
+                    "unknown tool_choice \"{other}\", expected none, auto, required or a function"
+                )),
+            },
+            ToolChoiceRepr::Named { kind, function } if kind == "function" => Ok(Self::Function {
+                name: function.name,
+            }),
+            ToolChoiceRepr::Named { kind, .. } => {
+                Err(format!("unknown tool_choice type \"{kind}\""))
+            }
+        }
+    }
+}
+
 /// The response from a chat completion request.
 #[derive(Debug, Deserialize)]
 pub struct ChatResponse {
@@ -130,15 +1100,170 @@ pub struct ChatChoice {
 pub struct ChatResponseMessage {
     pub role: String,
     pub content: Option<String>,
-    pub tool_calls: Option<Vec<Value>>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+/// Token usage statistics.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl Usage {
+    /// Add another request's usage to this running total.
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens = self.prompt_tokens.saturating_add(other.prompt_tokens);
+        self.completion_tokens = self
+            .completion_tokens
+            .saturating_add(other.completion_tokens);
+        self.total_tokens = self.total_tokens.saturating_add(other.total_tokens);
+    }
+}
+
+/// One server-sent chunk of a streamed chat completion.
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionChunk {
+    #[serde(default)]
+    pub choices: Vec<ChatChunkChoice>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// A single choice delta within a streamed chunk.
+#[derive(Debug, Deserialize)]
+pub struct ChatChunkChoice {
+    #[serde(default)]
+    pub index: usize,
+    pub delta: ChatDelta,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+/// The incremental message fields carried by a chunk.
+#[derive(Debug, Default, Deserialize)]
+pub struct ChatDelta {
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+/// A fragment of a streamed tool call. Fragments sharing an `index` are
+/// merged into one [`ToolCall`], concatenating `arguments`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ToolCallDelta {
+    #[serde(default)]
+    pub index: usize,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(rename = "type", default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub function: Option<FunctionCallDelta>,
+}
+
+/// The function fields carried by a [`ToolCallDelta`].
+#[derive(Debug, Default, Deserialize)]
+pub struct FunctionCallDelta {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: Option<String>,
+}
+
+/// The response to a deferred chat completion submission.
+#[derive(Debug, Deserialize)]
+pub struct DeferredChatResponse {
+    pub request_id: String,
+}
+
+/// Reassembles streamed chunks into a complete [`ChatResponse`].
+#[derive(Debug, Default)]
+pub struct ChatStreamAccumulator {
+    choices: Vec<ChatChoice>,
+    usage: Option<Usage>,
+}
+
+impl ChatStreamAccumulator {
+    /// Merge a chunk and return the new content text it carried.
+    pub fn push(&mut self, chunk: ChatCompletionChunk) -> String {
+        let mut text = String::new();
+        for c in chunk.choices {
+            while self.choices.len() <= c.index {
+                self.choices.push(ChatChoice {
+                    message: ChatResponseMessage {
+                        role: "assistant".into(),
+                        content: None,
+                        tool_calls: None,
+                    },
+                    finish_reason: None,
+                });
+            }
+            let choice = &mut self.choices[c.index];
+            if let Some(role) = c.delta.role {
+                choice.message.role = role;
+            }
+            if let Some(content) = c.delta.content {
+                text.push_str(&content);
+                choice
+                    .message
+                    .content
+                    .get_or_insert_with(String::new)
+                    .push_str(&content);
+            }
+            if let Some(deltas) = c.delta.tool_calls {
+                let calls = choice.message.tool_calls.get_or_insert_with(Vec::new);
+                for delta in deltas {
+                    merge_tool_call_delta(calls, delta);
+                }
+            }
+            if c.finish_reason.is_some() {
+                choice.finish_reason = c.finish_reason;
+            }
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        text
+    }
+
+    /// Produce the assembled response.
+    pub fn finish(self) -> ChatResponse {
+        ChatResponse {
+            choices: self.choices,
+            usage: self.usage,
+        }
+    }
 }
 
-/// Token usage statistics.
-#[derive(Debug, Deserialize)]
-pub struct Usage {
-    pub prompt_tokens: u32,
-    pub completion_tokens: u32,
-    pub total_tokens: u32,
+/// Fold a streamed tool call fragment into the call at the same `index`,
+/// concatenating `function.arguments`.
+fn merge_tool_call_delta(calls: &mut Vec<ToolCall>, delta: ToolCallDelta) {
+    while calls.len() <= delta.index {
+        calls.push(ToolCall {
+            kind: function_kind(),
+            ..ToolCall::default()
+        });
+    }
+    let call = &mut calls[delta.index];
+    if let Some(id) = delta.id {
+        call.id = id;
+    }
+    if let Some(kind) = delta.kind {
+        call.kind = kind;
+    }
+    if let Some(function) = delta.function {
+        if let Some(name) = function.name {
+            call.function.name = name;
+        }
+        if let Some(arguments) = function.arguments {
+            call.function.arguments.push_str(&arguments);
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -158,6 +1283,30 @@ pub struct ResponsesRequest {
     pub tools: Option<Vec<Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning: Option<ReasoningConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Continue from a stored response, keeping its context server-side.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+    /// Whether xAI keeps the response for continuation and retrieval (default: true).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+}
+
+impl ResponsesRequest {
+    pub fn new(model: impl Into<String>, input: Vec<ResponsesMessage>) -> Self {
+        Self {
+            model: model.into(),
+            input,
+            temperature: None,
+            max_output_tokens: None,
+            tools: None,
+            reasoning: None,
+            stream: None,
+            previous_response_id: None,
+            store: None,
+        }
+    }
 }
 
 /// Reasoning configuration for the Responses API.
@@ -193,6 +1342,9 @@ impl ResponsesMessage {
 /// The response from the /v1/responses endpoint.
 #[derive(Debug, Deserialize)]
 pub struct ResponsesResponse {
+    /// Identifies a stored response for `previous_response_id`, retrieval and deletion.
+    #[serde(default)]
+    pub id: Option<String>,
     pub output: Vec<ResponsesOutput>,
     pub usage: Option<ResponsesUsage>,
 }
@@ -227,25 +1379,78 @@ pub struct ResponsesUsage {
     pub output_tokens: u32,
 }
 
-impl fmt::Display for ResponsesResponse {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut first = true;
-        for output in &self.output {
-            // Only display message-type outputs with content
-            if let Some(content) = &output.content {
-                for block in content {
-                    if block.content_type == "output_text"
-                        && let Some(text) = &block.text
-                    {
-                        if !first {
-                            writeln!(f)?;
-                        }
-                        first = false;
-                        write!(f, "{text}")?;
-                    }
-                }
+/// A server-sent event from a streamed Responses API call.
+/// Only the events needed to reassemble the final response are modelled.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponsesStreamEvent {
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta { delta: String },
+    #[serde(rename = "response.completed")]
+    Completed { response: ResponsesResponse },
+    #[serde(other)]
+    Other,
+}
+
+/// Reassembles streamed events into a complete [`ResponsesResponse`].
+#[derive(Debug, Default)]
+pub struct ResponsesStreamAccumulator {
+    text: String,
+    completed: Option<ResponsesResponse>,
+}
+
+impl ResponsesStreamAccumulator {
+    /// Merge an event and return the new output text it carried.
+    pub fn push(&mut self, event: ResponsesStreamEvent) -> String {
+        match event {
+            ResponsesStreamEvent::OutputTextDelta { delta } => {
+                self.text.push_str(&delta);
+                delta
             }
+            ResponsesStreamEvent::Completed { response } => {
+                self.completed = Some(response);
+                String::new()
+            }
+            ResponsesStreamEvent::Other => String::new(),
         }
+    }
+
+    /// Produce the final response: the server's `response.completed` payload
+    /// if one arrived, otherwise a response built from the streamed text.
+    pub fn finish(self) -> ResponsesResponse {
+        self.completed.unwrap_or_else(|| ResponsesResponse {
+            id: None,
+            output: vec![ResponsesOutput {
+                role: Some("assistant".into()),
+                content: Some(vec![ResponsesContent {
+                    content_type: "output_text".into(),
+                    text: Some(self.text),
+                }]),
+                output_type: Some("message".into()),
+                status: None,
+            }],
+            usage: None,
+        })
+    }
+}
+
+impl ResponsesResponse {
+    /// The text of all `output_text` blocks, one per line.
+    pub fn output_text(&self) -> String {
+        self.output
+            .iter()
+            .filter_map(|output| output.content.as_ref())
+            .flatten()
+            .filter(|block| block.content_type == "output_text")
+            .filter_map(|block| block.text.as_deref())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for ResponsesResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.output_text())?;
 
         if let Some(usage) = &self.usage {
             write!(
@@ -257,10 +1462,22 @@ impl fmt::Display for ResponsesResponse {
             )?;
         }
 
+        if let Some(id) = &self.id {
+            write!(f, "\n[response_id: {id}]")?;
+        }
+
         Ok(())
     }
 }
 
+/// The response to deleting a stored response.
+#[derive(Debug, Deserialize)]
+pub struct ResponseDeleted {
+    pub id: String,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
 // ---------------------------------------------------------------------------
 // Embeddings API types
 // ---------------------------------------------------------------------------
@@ -270,6 +1487,23 @@ impl fmt::Display for ResponsesResponse {
 pub struct EmbeddingRequest {
     pub model: String,
     pub input: Value,
+    /// `"float"` or `"base64"`. Either way the response decodes to `f32`s.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<String>,
+    /// Truncate the vectors to this many dimensions, on models that support it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+}
+
+impl EmbeddingRequest {
+    pub fn new(model: impl Into<String>, input: Value) -> Self {
+        Self {
+            model: model.into(),
+            input,
+            encoding_format: None,
+            dimensions: None,
+        }
+    }
 }
 
 /// The response from an embedding request.
@@ -282,10 +1516,25 @@ pub struct EmbeddingResponse {
 /// A single embedding vector in the response.
 #[derive(Deserialize)]
 pub struct EmbeddingData {
+    #[serde(deserialize_with = "float_or_base64")]
     pub embedding: Vec<f32>,
     pub index: usize,
 }
 
+/// Accept an embedding as a float array or as base64-packed little-endian `f32`s.
+fn float_or_base64<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Vec<f32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Floats(Vec<f32>),
+        Base64(String),
+    }
+    match Repr::deserialize(d)? {
+        Repr::Floats(v) => Ok(v),
+        Repr::Base64(s) => crate::vectors::from_base64(&s).map_err(serde::de::Error::custom),
+    }
+}
+
 /// Token usage for an embedding request.
 #[derive(Deserialize)]
 pub struct EmbeddingUsage {
@@ -293,6 +1542,102 @@ pub struct EmbeddingUsage {
     pub total_tokens: u32,
 }
 
+// ---------------------------------------------------------------------------
+// Image generation API types
+// ---------------------------------------------------------------------------
+
+/// An image generation request to the xAI API.
+#[derive(Serialize)]
+pub struct ImageGenerationRequest {
+    pub model: String,
+    pub prompt: String,
+    /// Number of images to generate (1-10).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// `"url"` or `"b64_json"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+}
+
+/// The response from an image generation request.
+#[derive(Debug, Deserialize)]
+pub struct ImageGenerationResponse {
+    pub data: Vec<GeneratedImage>,
+}
+
+/// A single generated image, as a URL or base64 data depending on `response_format`.
+#[derive(Debug, Deserialize)]
+pub struct GeneratedImage {
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub b64_json: Option<String>,
+    /// The prompt the model actually used, after its own rewriting.
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Tokenizer API types
+// ---------------------------------------------------------------------------
+
+/// A request to tokenize text with a model's tokenizer.
+#[derive(Serialize)]
+pub struct TokenizeRequest {
+    pub text: String,
+    pub model: String,
+}
+
+/// The tokens produced by a [`TokenizeRequest`].
+#[derive(Debug, Deserialize)]
+pub struct TokenizeResponse {
+    pub token_ids: Vec<TokenInfo>,
+}
+
+/// A single token.
+#[derive(Debug, Deserialize)]
+pub struct TokenInfo {
+    pub token_id: u32,
+    #[serde(default)]
+    pub string_token: String,
+    #[serde(default)]
+    pub token_bytes: Vec<u8>,
+}
+
+impl TokenizeResponse {
+    /// The number of tokens.
+    pub fn count(&self) -> usize {
+        self.token_ids.len()
+    }
+
+    /// The byte offset in `text` where each token starts, followed by
+    /// `text.len()`, or `None` if the tokens do not spell out `text` exactly.
+    /// Offsets inside a multi-byte character are left out, so such a
+    /// character's tokens count as one.
+    pub fn token_bounds(&self, text: &str) -> Option<Vec<usize>> {
+        let mut bounds = Vec::with_capacity(self.token_ids.len() + 1);
+        let mut offset = 0;
+        for token in &self.token_ids {
+            let bytes = if token.token_bytes.is_empty() {
+                token.string_token.as_bytes()
+            } else {
+                &token.token_bytes
+            };
+            if text.as_bytes().get(offset..offset + bytes.len())? != bytes {
+                return None;
+            }
+            if text.is_char_boundary(offset) {
+                bounds.push(offset);
+            }
+            offset += bytes.len();
+        }
+        (offset == text.len()).then(|| {
+            bounds.push(offset);
+            bounds
+        })
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Models API types
 // ---------------------------------------------------------------------------
@@ -325,43 +1670,118 @@ impl ChatRequest {
             max_tokens: None,
             response_format: None,
             tools: None,
+            tool_choice: None,
+            stream: None,
+            deferred: None,
+        }
+    }
+}
+
+impl ToolDefinition {
+    /// Define a function tool. `parameters` is the JSON Schema of its arguments.
+    pub fn function(name: &str, description: Option<&str>, parameters: Option<Value>) -> Self {
+        Self {
+            kind: function_kind(),
+            function: FunctionDefinition {
+                name: name.into(),
+                description: description.map(Into::into),
+                parameters,
+                strict: None,
+            },
         }
     }
 }
 
+impl FunctionCall {
+    /// Decode the JSON `arguments` string into `T`.
+    pub fn parse_arguments<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_str(&self.arguments)
+    }
+}
+
 impl ChatMessage {
     /// Create a system message.
     pub fn system(text: &str) -> Self {
-        Self {
-            role: "system".into(),
-            content: Some(Value::String(text.into())),
-            tool_calls: None,
-            tool_call_id: None,
-        }
+        Self::with_content("system", text.into())
     }
 
     /// Create a user text message.
     pub fn user(text: &str) -> Self {
+        Self::with_content("user", text.into())
+    }
+
+    /// Create an assistant text message, e.g. an earlier reply in a history.
+    pub fn assistant(text: &str) -> Self {
+        Self::with_content("assistant", text.into())
+    }
+
+    /// Create a user message with both text and an image URL.
+    pub fn user_with_image(text: &str, image_url: &str, detail: &str) -> Self {
+        Self::with_content(
+            "user",
+            MessageContent::Parts(vec![
+                ContentPart::text(text),
+                ContentPart::image_url(image_url, Some(detail)),
+            ]),
+        )
+    }
+
+    /// Create a user message from content parts, e.g. text followed by several images.
+    pub fn user_with_parts(parts: Vec<ContentPart>) -> Self {
+        Self::with_content("user", MessageContent::Parts(parts))
+    }
+
+    fn with_content(role: &str, content: MessageContent) -> Self {
         Self {
-            role: "user".into(),
-            content: Some(Value::String(text.into())),
+            role: role.into(),
+            content: Some(content),
             tool_calls: None,
             tool_call_id: None,
         }
     }
 
-    /// Create a user message with both text and an image URL.
-    pub fn user_with_image(text: &str, image_url: &str, detail: &str) -> Self {
+    /// Check that the fields present fit the message's role: content where
+    /// it is required, images only from the user, `tool_calls` only from the
+    /// assistant and `tool_call_id` only (and always) on tool results.
+    pub fn validate(&self) -> Result<(), String> {
+        let role = self.role.as_str();
+        let has_content = self.content.as_ref().is_some_and(|c| !c.is_empty());
+
+        match role {
+            "assistant" if !has_content && self.tool_calls.is_none() => {
+                return Err("assistant message needs content or tool_calls".into());
+            }
+            "system" | "user" | "tool" if !has_content => {
+                return Err(format!("{role} message needs content"));
+            }
+            _ => {}
+        }
+        if role != "user"
+            && self
+                .content
+                .as_ref()
+                .is_some_and(MessageContent::has_images)
+        {
+            return Err(format!("{role} message cannot contain images"));
+        }
+        if role != "assistant" && self.tool_calls.is_some() {
+            return Err(format!("{role} message cannot have tool_calls"));
+        }
+        match (role, &self.tool_call_id) {
+            ("tool", None) => Err("tool message needs tool_call_id".into()),
+            ("tool", Some(_)) | (_, None) => Ok(()),
+            (_, Some(_)) => Err(format!("{role} message cannot have tool_call_id")),
+        }
+    }
+}
+
+impl From<&ChatResponseMessage> for ChatMessage {
+    /// The reply as a message to send back in a later request's history.
+    fn from(msg: &ChatResponseMessage) -> Self {
         Self {
-            role: "user".into(),
-            content: Some(serde_json::json!([
-                { "type": "text", "text": text },
-                {
-                    "type": "image_url",
-                    "image_url": { "url": image_url, "detail": detail }
-                }
-            ])),
-            tool_calls: None,
+            role: msg.role.clone(),
+            content: msg.content.as_deref().map(MessageContent::from),
+            tool_calls: msg.tool_calls.clone(),
             tool_call_id: None,
         }
     }
@@ -387,9 +1807,10 @@ impl fmt::Display for ChatResponse {
                 write!(f, "{content}")?;
             }
             if let Some(tool_calls) = &choice.message.tool_calls {
-                let formatted = serde_json::to_string_pretty(tool_calls)
-                    .unwrap_or_else(|e| format!("<failed to format tool calls: {e}>"));
-                write!(f, "\nTool calls: {formatted}")?;
+                write!(f, "\nTool calls:")?;
+                for call in tool_calls {
+                    write!(f, "\n- {call}")?;
+                }
             }
             if let Some(reason) = &choice.finish_reason {
                 write!(f, "\n[finish_reason: {reason}]")?;
@@ -408,6 +1829,30 @@ impl fmt::Display for ChatResponse {
     }
 }
 
+impl fmt::Display for ToolCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) [id: {}]",
+            self.function.name, self.function.arguments, self.id
+        )
+    }
+}
+
+/// `{}` prints the token count; the alternate form `{:#}` also lists each
+/// token as `id "text"`.
+impl fmt::Display for TokenizeResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} tokens", self.count())?;
+        if f.alternate() {
+            for token in &self.token_ids {
+                write!(f, "\n{} {:?}", token.token_id, token.string_token)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for EmbeddingResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for (i, item) in self.data.iter().enumerate() {
@@ -445,6 +1890,161 @@ impl fmt::Display for EmbeddingResponse {
 mod tests {
     use super::*;
 
+    #[test]
+    fn error_detail_parses_openai_envelope() {
+        let detail = XaiErrorDetail::parse(
+            r#"{"error": {"message": "bad temp", "type": "invalid_request_error",
+                "param": "temperature", "code": 400}}"#,
+        );
+        assert_eq!(detail.message, "bad temp");
+        assert_eq!(detail.error_type.as_deref(), Some("invalid_request_error"));
+        assert_eq!(detail.param.as_deref(), Some("temperature"));
+        assert_eq!(detail.code.as_deref(), Some("400"));
+    }
+
+    #[test]
+    fn error_detail_parses_flat_xai_form() {
+        let detail = XaiErrorDetail::parse(
+            r#"{"code": "Client specified an invalid argument", "error": "Incorrect API key"}"#,
+        );
+        assert_eq!(detail.message, "Incorrect API key");
+        assert_eq!(
+            detail.code.as_deref(),
+            Some("Client specified an invalid argument")
+        );
+        assert_eq!(
+            detail.to_string(),
+            "Incorrect API key (code: Client specified an invalid argument)"
+        );
+    }
+
+    #[test]
+    fn error_detail_keeps_non_json_body() {
+        let detail = XaiErrorDetail::parse("  upstream connect error \n");
+        assert_eq!(detail.message, "upstream connect error");
+        assert!(detail.code.is_none());
+    }
+
+    #[test]
+    fn classify_error_kinds() {
+        let classify = |status: u16, body: &str| {
+            ApiErrorKind::classify(
+                StatusCode::from_u16(status).unwrap(),
+                &XaiErrorDetail::parse(body),
+            )
+        };
+        assert_eq!(classify(429, "slow down"), ApiErrorKind::RateLimited);
+        assert_eq!(classify(401, "{}"), ApiErrorKind::Unauthorized);
+        assert_eq!(classify(403, "{}"), ApiErrorKind::Unauthorized);
+        assert_eq!(
+            classify(
+                404,
+                r#"{"error": "The model grok-9 does not exist or your team does not have access to it."}"#
+            ),
+            ApiErrorKind::ModelNotFound
+        );
+        assert_eq!(
+            classify(400, r#"{"error": "The model grok-9 does not exist"}"#),
+            ApiErrorKind::ModelNotFound
+        );
+        assert_eq!(
+            classify(
+                400,
+                r#"{"error": {"message": "This model's maximum prompt length is 131072", "code": "context_length_exceeded"}}"#
+            ),
+            ApiErrorKind::ContextLengthExceeded
+        );
+        assert_eq!(
+            classify(400, "bad temperature"),
+            ApiErrorKind::InvalidRequest
+        );
+        assert_eq!(
+            classify(404, "no such response"),
+            ApiErrorKind::InvalidRequest
+        );
+        assert_eq!(classify(408, ""), ApiErrorKind::Upstream);
+        assert_eq!(classify(503, ""), ApiErrorKind::Upstream);
+    }
+
+    #[test]
+    fn retry_backoff_doubles_and_caps() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            jitter: 0.0,
+            retry_unsafe: true,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(40), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn retry_backoff_jitter_stays_in_range() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            base_delay: Duration::from_millis(100),
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let d = policy.backoff(1);
+            assert!(d >= Duration::from_millis(50) && d <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn retry_after_is_capped_at_max_delay() {
+        let policy = RetryPolicy::default();
+        assert_eq!(
+            policy.delay_for(1, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay_for(1, Some(Duration::from_secs(31))),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn retry_unsafe_controls_post_server_errors() {
+        let wait = Some(Duration::from_secs(1));
+        let status = |code| StatusCode::from_u16(code).unwrap();
+        let policy = RetryPolicy::default();
+        assert!(policy.retries(&Method::POST, status(500), None));
+        assert!(policy.retries(&Method::POST, status(429), None));
+        assert!(!policy.retries(&Method::GET, status(400), None));
+
+        let safe = RetryPolicy {
+            retry_unsafe: false,
+            ..RetryPolicy::default()
+        };
+        assert!(safe.retries(&Method::GET, status(500), None));
+        assert!(!safe.retries(&Method::POST, status(500), wait));
+        assert!(!safe.retries(&Method::POST, status(503), None));
+        assert!(safe.retries(&Method::POST, status(503), wait));
+        assert!(safe.retries(&Method::POST, status(429), None));
+    }
+
+    #[test]
+    fn parse_retry_after_seconds_and_date() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, "3".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(3)));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+
     #[test]
     fn display_chat_response_basic() {
         let resp = ChatResponse {
@@ -468,6 +2068,39 @@ mod tests {
         assert!(output.contains("[tokens: 10 prompt + 5 completion = 15 total]"));
     }
 
+    #[test]
+    fn display_tokenize_response() {
+        let resp: TokenizeResponse = serde_json::from_str(
+            r#"{"token_ids": [
+                {"token_id": 15339, "string_token": "Hello", "token_bytes": [72, 101, 108, 108, 111]},
+                {"token_id": 0, "string_token": " world"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(resp.to_string(), "2 tokens");
+        assert_eq!(
+            format!("{resp:#}"),
+            "2 tokens\n15339 \"Hello\"\n0 \" world\""
+        );
+    }
+
+    #[test]
+    fn token_bounds_map_tokens_onto_text() {
+        let resp: TokenizeResponse = serde_json::from_str(
+            r#"{"token_ids": [
+                {"token_id": 1, "string_token": "caf"},
+                {"token_id": 2, "token_bytes": [195]},
+                {"token_id": 3, "token_bytes": [169]},
+                {"token_id": 4, "string_token": " ok"}
+            ]}"#,
+        )
+        .unwrap();
+        // The split "é" counts as one unit.
+        assert_eq!(resp.token_bounds("café ok"), Some(vec![0, 3, 5, 8]));
+        assert_eq!(resp.token_bounds("cafe ok"), None);
+        assert_eq!(resp.token_bounds("café ok!"), None);
+    }
+
     #[test]
     fn display_chat_response_empty_choices() {
         let resp = ChatResponse {
@@ -484,17 +2117,264 @@ mod tests {
                 message: ChatResponseMessage {
                     role: "assistant".into(),
                     content: None,
-                    tool_calls: Some(vec![
-                        serde_json::json!({"id": "call_1", "type": "function"}),
-                    ]),
+                    tool_calls: Some(vec![ToolCall {
+                        id: "call_1".into(),
+                        kind: "function".into(),
+                        function: FunctionCall {
+                            name: "lookup".into(),
+                            arguments: r#"{"q":"rust"}"#.into(),
+                        },
+                    }]),
                 },
                 finish_reason: None,
             }],
             usage: None,
         };
         let output = resp.to_string();
-        assert!(output.contains("Tool calls:"));
-        assert!(output.contains("call_1"));
+        assert_eq!(
+            output,
+            "\nTool calls:\n- lookup({\"q\":\"rust\"}) [id: call_1]"
+        );
+    }
+
+    #[test]
+    fn tool_definition_round_trip() {
+        let tool = ToolDefinition::function(
+            "lookup",
+            Some("Search the docs"),
+            Some(serde_json::json!({"type": "object", "properties": {"q": {"type": "string"}}})),
+        );
+        let json = serde_json::to_value(&tool).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "function",
+                "function": {
+                    "name": "lookup",
+                    "description": "Search the docs",
+                    "parameters": {"type": "object", "properties": {"q": {"type": "string"}}}
+                }
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<ToolDefinition>(json).unwrap(),
+            tool
+        );
+    }
+
+    #[test]
+    fn tool_call_round_trip_and_arguments() {
+        let json = r#"{"id":"call_1","type":"function","function":{"name":"lookup","arguments":"{\"q\":\"rust\"}"}}"#;
+        let call: ToolCall = serde_json::from_str(json).unwrap();
+        assert_eq!(call.function.name, "lookup");
+        assert_eq!(serde_json::to_string(&call).unwrap(), json);
+
+        #[derive(Deserialize)]
+        struct Args {
+            q: String,
+        }
+        let args: Args = call.function.parse_arguments().unwrap();
+        assert_eq!(args.q, "rust");
+
+        // `type` is optional on input.
+        let call: ToolCall = serde_json::from_str(r#"{"id":"c","function":{"name":"f"}}"#).unwrap();
+        assert_eq!(call.kind, "function");
+        assert_eq!(call.function.arguments, "");
+    }
+
+    #[test]
+    fn tool_choice_round_trip() {
+        for (choice, json) in [
+            (ToolChoice::None, serde_json::json!("none")),
+            (ToolChoice::Auto, serde_json::json!("auto")),
+            (ToolChoice::Required, serde_json::json!("required")),
+            (
+                ToolChoice::Function {
+                    name: "lookup".into(),
+                },
+                serde_json::json!({"type": "function", "function": {"name": "lookup"}}),
+            ),
+        ] {
+            assert_eq!(serde_json::to_value(&choice).unwrap(), json);
+            assert_eq!(serde_json::from_value::<ToolChoice>(json).unwrap(), choice);
+        }
+        assert!(serde_json::from_str::<ToolChoice>(r#""sometimes""#).is_err());
+    }
+
+    #[test]
+    fn user_with_image_keeps_wire_format() {
+        let msg = ChatMessage::user_with_image("what is this?", "https://x.test/a.png", "low");
+        let expected = serde_json::json!({
+            "role": "user",
+            "content": [
+                {"type": "text", "text": "what is this?"},
+                {"type": "image_url", "image_url": {"url": "https://x.test/a.png", "detail": "low"}}
+            ]
+        });
+        assert_eq!(serde_json::to_value(&msg).unwrap(), expected);
+
+        let parsed: ChatMessage = serde_json::from_value(expected).unwrap();
+        assert_eq!(parsed.content, msg.content);
+        assert_eq!(
+            serde_json::to_value(ChatMessage::user("hi")).unwrap(),
+            serde_json::json!({"role": "user", "content": "hi"})
+        );
+    }
+
+    #[test]
+    fn message_content_rejects_malformed_parts() {
+        let err = |json: Value| {
+            serde_json::from_value::<MessageContent>(json)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(err(serde_json::json!(42)).contains("string or an array"));
+        assert!(
+            err(serde_json::json!([{"type": "text", "text": "a"}, {"type": "video"}]))
+                .contains("content part 1")
+        );
+        assert!(err(serde_json::json!([{"type": "image_url", "image_url": {}}])).contains("url"));
+        assert!(
+            err(serde_json::json!([{"type": "text", "txt": "typo"}])).contains("content part 0")
+        );
+    }
+
+    #[test]
+    fn chat_message_validate_checks_role_fields() {
+        let parse = |json: Value| serde_json::from_value::<ChatMessage>(json).unwrap();
+
+        assert!(
+            parse(serde_json::json!({"role": "user", "content": "hi"}))
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            parse(serde_json::json!({"role": "tool", "tool_call_id": "c1", "content": "42"}))
+                .validate()
+                .is_ok()
+        );
+
+        let cases = [
+            (serde_json::json!({"role": "user"}), "needs content"),
+            (
+                serde_json::json!({"role": "assistant", "content": ""}),
+                "content or tool_calls",
+            ),
+            (
+                serde_json::json!({"role": "tool", "content": "42"}),
+                "needs tool_call_id",
+            ),
+            (
+                serde_json::json!({"role": "user", "content": "x", "tool_call_id": "c1"}),
+                "cannot have tool_call_id",
+            ),
+            (
+                serde_json::json!({"role": "system", "content": [
+                    {"type": "image_url", "image_url": {"url": "https://x.test/a.png"}}
+                ]}),
+                "cannot contain images",
+            ),
+        ];
+        for (json, expected) in cases {
+            let err = parse(json).validate().unwrap_err();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn chat_message_with_tool_calls_round_trip() {
+        let json = serde_json::json!({
+            "role": "assistant",
+            "tool_calls": [{"id": "call_1", "type": "function",
+                            "function": {"name": "lookup", "arguments": "{}"}}]
+        });
+        let msg: ChatMessage = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(msg.tool_calls.as_ref().unwrap()[0].id, "call_1");
+        assert_eq!(serde_json::to_value(&msg).unwrap(), json);
+    }
+
+    #[test]
+    fn chat_stream_accumulator_merges_chunks() {
+        let chunks = [
+            r#"{"choices":[{"index":0,"delta":{"role":"assistant","content":"Hel"}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"content":"lo"}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{},"finish_reason":"stop"}],
+                "usage":{"prompt_tokens":3,"completion_tokens":2,"total_tokens":5}}"#,
+        ];
+        let mut acc = ChatStreamAccumulator::default();
+        let deltas: Vec<String> = chunks
+            .iter()
+            .map(|c| acc.push(serde_json::from_str(c).unwrap()))
+            .collect();
+        assert_eq!(deltas, ["Hel", "lo", ""]);
+
+        let resp = acc.finish();
+        assert_eq!(resp.choices[0].message.content.as_deref(), Some("Hello"));
+        assert_eq!(resp.choices[0].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(resp.usage.unwrap().total_tokens, 5);
+    }
+
+    #[test]
+    fn chat_stream_accumulator_concatenates_tool_call_arguments() {
+        let chunks = [
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[
+                {"index":0,"id":"call_1","type":"function",
+                 "function":{"name":"lookup","arguments":"{\"q\":"}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[
+                {"index":0,"function":{"arguments":"\"rust\"}"}}]}}]}"#,
+        ];
+        let mut acc = ChatStreamAccumulator::default();
+        for c in chunks {
+            acc.push(serde_json::from_str(c).unwrap());
+        }
+        let resp = acc.finish();
+        let calls = resp.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.name, "lookup");
+        assert_eq!(calls[0].function.arguments, r#"{"q":"rust"}"#);
+    }
+
+    #[test]
+    fn responses_stream_accumulator_prefers_completed_payload() {
+        let mut acc = ResponsesStreamAccumulator::default();
+        let delta = acc.push(
+            serde_json::from_str(r#"{"type":"response.output_text.delta","delta":"partial"}"#)
+                .unwrap(),
+        );
+        assert_eq!(delta, "partial");
+        acc.push(serde_json::from_str(r#"{"type":"response.in_progress"}"#).unwrap());
+        acc.push(
+            serde_json::from_str(
+                r#"{"type":"response.completed","response":{"output":[{"type":"message",
+                    "content":[{"type":"output_text","text":"final"}]}]}}"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(acc.finish().to_string(), "final");
+    }
+
+    #[test]
+    fn responses_response_shows_id_after_usage() {
+        let resp: ResponsesResponse = serde_json::from_str(
+            r#"{"id":"resp_1","output":[{"type":"message",
+                "content":[{"type":"output_text","text":"hi"}]}],
+                "usage":{"input_tokens":3,"output_tokens":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            resp.to_string(),
+            "hi\n[tokens: 3 input + 1 output = 4 total]\n[response_id: resp_1]"
+        );
+    }
+
+    #[test]
+    fn responses_stream_accumulator_falls_back_to_text() {
+        let mut acc = ResponsesStreamAccumulator::default();
+        acc.push(ResponsesStreamEvent::OutputTextDelta {
+            delta: "only deltas".into(),
+        });
+        assert_eq!(acc.finish().to_string(), "only deltas");
     }
 
     #[test]
@@ -0,0 +1,425 @@
+use reqwest::header::{CONTENT_TYPE, HeaderMap};
+use reqwest::{Method, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use thiserror::Error;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::api::ApiError;
+
+/// Response headers worth keeping in a cassette. Everything else, including
+/// anything that could identify the account, is dropped.
+const RECORDED_HEADERS: &[&str] = &[
+    "content-type",
+    "retry-after",
+    "x-ratelimit-limit-requests",
+    "x-ratelimit-limit-tokens",
+    "x-ratelimit-remaining-requests",
+    "x-ratelimit-remaining-tokens",
+];
+
+/// Errors from loading, writing or replaying a cassette.
+#[derive(Error, Debug)]
+pub enum CassetteError {
+    #[error("cassette {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("cassette {path} is not valid: {source}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("no recorded interaction for {method} {path} with this request body")]
+    NoMatch { method: String, path: String },
+
+    #[error("request body could not be serialized for the cassette: {0}")]
+    Body(#[source] serde_json::Error),
+}
+
+/// Whether a [`Cassette`] captures live traffic or serves it back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+    /// Send requests to xAI and append every exchange to the cassette file,
+    /// replacing whatever it held before.
+    Record,
+    /// Serve responses from the cassette file without touching the network.
+    Replay,
+}
+
+/// One recorded request/response pair.
+///
+/// Request headers are never stored, so the cassette holds no API keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub path: String,
+    /// The normalized JSON request body, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// The response body: parsed JSON when it is JSON, otherwise the raw text
+    /// (e.g. a server-sent event stream).
+    pub response: Value,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+/// The parts of a request a cassette matches on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestKey {
+    method: String,
+    path: String,
+    body: Option<Value>,
+}
+
+impl RequestKey {
+    pub fn new(method: &Method, path: &str, body: Option<Value>) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.map(normalize),
+        }
+    }
+
+    fn matches(&self, interaction: &Interaction) -> bool {
+        self.method == interaction.method
+            && self.path == interaction.path
+            && self.body == interaction.body
+    }
+}
+
+/// Drop `null` object members so that an omitted field and an explicit
+/// `null` match. Object key order never matters when comparing `Value`s.
+fn normalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, normalize(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(normalize).collect()),
+        other => other,
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    interactions: Vec<Interaction>,
+    /// Which interactions have already been served in replay mode.
+    used: Vec<bool>,
+}
+
+/// The file opening and closing an empty record-mode cassette. Interactions
+/// are written one per line between the two.
+const FILE_HEAD: &str = "{\n  \"interactions\": [";
+const FILE_TAIL: &str = "\n  ]\n}\n";
+
+/// The open cassette file in record mode.
+#[derive(Debug)]
+struct Writer {
+    file: tokio::fs::File,
+    /// Byte offset of [`FILE_TAIL`], where the next interaction goes.
+    tail: u64,
+    written: usize,
+}
+
+impl Writer {
+    /// Append one interaction by overwriting the closing tail, so each
+    /// exchange costs one small write and the file is valid JSON after it.
+    async fn append(&mut self, interaction: &Interaction) -> std::io::Result<()> {
+        let separator = if self.written == 0 { "" } else { "," };
+        let json = serde_json::to_string(interaction).expect("interaction is always serializable");
+        let entry = format!("{separator}\n    {json}");
+
+        self.file.seek(SeekFrom::Start(self.tail)).await?;
+        self.file.write_all(entry.as_bytes()).await?;
+        self.file.write_all(FILE_TAIL.as_bytes()).await?;
+        self.file.flush().await?;
+        self.tail += entry.len() as u64;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+/// A file of recorded xAI exchanges for deterministic, offline runs.
+///
+/// In replay mode each request is matched on method, path and normalized
+/// body. Matching interactions are served in recorded order, so a recorded
+/// 429 followed by a 200 replays the same retry; once all matches are used,
+/// the last one is repeated.
+#[derive(Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    state: Mutex<State>,
+    writer: Option<tokio::sync::Mutex<Writer>>,
+}
+
+impl Cassette {
+    /// Open a cassette. Replay mode reads `path`; record mode truncates it to
+    /// an empty cassette and appends to it after every exchange.
+    pub fn open(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self, CassetteError> {
+        let path = path.into();
+        let mut state = State::default();
+        let mut writer = None;
+
+        if mode == CassetteMode::Record {
+            let io_err = |source| CassetteError::Io {
+                path: path.clone(),
+                source,
+            };
+            let mut file = std::fs::File::create(&path).map_err(io_err)?;
+            write!(file, "{FILE_HEAD}{FILE_TAIL}").map_err(io_err)?;
+            writer = Some(tokio::sync::Mutex::new(Writer {
+                file: tokio::fs::File::from_std(file),
+                tail: FILE_HEAD.len() as u64,
+                written: 0,
+            }));
+        } else {
+            let content = std::fs::read_to_string(&path).map_err(|source| CassetteError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            let file: CassetteFile =
+                serde_json::from_str(&content).map_err(|source| CassetteError::Parse {
+                    path: path.clone(),
+                    source,
+                })?;
+            state.used = vec![false; file.interactions.len()];
+            state.interactions = file.interactions;
+        }
+
+        Ok(Self {
+            mode,
+            path,
+            state: Mutex::new(state),
+            writer,
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A copy of the interactions recorded or loaded so far.
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.lock().interactions.clone()
+    }
+
+    /// Serve the recorded response for `key`.
+    pub fn replay(&self, key: &RequestKey) -> Result<Response, CassetteError> {
+        let mut state = self.lock();
+        let matching: Vec<usize> = (0..state.interactions.len())
+            .filter(|&i| key.matches(&state.interactions[i]))
+            .collect();
+
+        let index = matching
+            .iter()
+            .copied()
+            .find(|&i| !state.used[i])
+            .or_else(|| matching.last().copied())
+            .ok_or_else(|| CassetteError::NoMatch {
+                method: key.method.clone(),
+                path: key.path.clone(),
+            })?;
+        state.used[index] = true;
+
+        let interaction = &state.interactions[index];
+        tracing::debug!(path = %interaction.path, status = interaction.status, "cassette replay");
+        Ok(interaction.to_response())
+    }
+
+    /// Buffer `response`, append it to the cassette file and return an
+    /// equivalent response for the caller to consume.
+    ///
+    /// Streaming responses are buffered whole, so they arrive all at once
+    /// while recording.
+    pub async fn record(&self, key: &RequestKey, response: Response) -> Result<Response, ApiError> {
+        let status = response.status().as_u16();
+        let headers = recorded_headers(response.headers());
+        let text = response.text().await?;
+
+        let interaction = Interaction {
+            method: key.method.clone(),
+            path: key.path.clone(),
+            body: key.body.clone(),
+            status,
+            headers,
+            response: serde_json::from_str(&text).unwrap_or(Value::String(text)),
+        };
+        let replayed = interaction.to_response();
+
+        if let Some(writer) = &self.writer {
+            writer
+                .lock()
+                .await
+                .append(&interaction)
+                .await
+                .map_err(|source| CassetteError::Io {
+                    path: self.path.clone(),
+                    source,
+                })?;
+        }
+
+        let mut state = self.lock();
+        state.interactions.push(interaction);
+        state.used.push(false);
+        Ok(replayed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // Interactions are only appended or flagged; a poisoned lock is harmless.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Interaction {
+    fn to_response(&self) -> Response {
+        let body = match &self.response {
+            Value::String(text) => text.clone().into_bytes(),
+            json => json.to_string().into_bytes(),
+        };
+        let mut builder = http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        if !self.headers.contains_key(CONTENT_TYPE.as_str()) && self.response.is_object() {
+            builder = builder.header(CONTENT_TYPE, "application/json");
+        }
+        builder
+            .body(body)
+            .map(Response::from)
+            .expect("recorded status and headers are valid")
+    }
+}
+
+fn recorded_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    RECORDED_HEADERS
+        .iter()
+        .filter_map(|&name| {
+            let value = headers.get(name)?.to_str().ok()?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn interaction(body: Value, response: &str) -> Interaction {
+        Interaction {
+            method: "POST".into(),
+            path: "/chat/completions".into(),
+            body: Some(body),
+            status: 200,
+            headers: BTreeMap::new(),
+            response: json!({ "id": response }),
+        }
+    }
+
+    fn replaying(interactions: Vec<Interaction>) -> Cassette {
+        Cassette {
+            mode: CassetteMode::Replay,
+            path: PathBuf::new(),
+            state: Mutex::new(State {
+                used: vec![false; interactions.len()],
+                interactions,
+            }),
+            writer: None,
+        }
+    }
+
+    #[test]
+    fn normalize_ignores_nulls_and_key_order() {
+        let a = RequestKey::new(
+            &Method::POST,
+            "/x",
+            Some(
+                json!({"model": "m", "temperature": null, "messages": [{"role": "user", "name": null}]}),
+            ),
+        );
+        let b = RequestKey::new(
+            &Method::POST,
+            "/x",
+            Some(json!({"messages": [{"role": "user"}], "model": "m"})),
+        );
+        assert_eq!(a, b);
+    }
+
+    #[tokio::test]
+    async fn replays_matches_in_order_then_repeats_last() {
+        let body = json!({"model": "m"});
+        let cassette = replaying(vec![
+            interaction(body.clone(), "first"),
+            interaction(json!({"model": "other"}), "other"),
+            interaction(body.clone(), "second"),
+        ]);
+        let key = RequestKey::new(&Method::POST, "/chat/completions", Some(body));
+
+        for expected in ["first", "second", "second"] {
+            let resp = cassette.replay(&key).unwrap();
+            let json: Value = resp.json().await.unwrap();
+            assert_eq!(json["id"], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn record_appends_a_valid_cassette() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rec.json");
+        let cassette = Cassette::open(&path, CassetteMode::Record).unwrap();
+        assert!(
+            Cassette::open(&path, CassetteMode::Replay)
+                .unwrap()
+                .interactions()
+                .is_empty()
+        );
+
+        for id in ["a", "b", "c"] {
+            let key = RequestKey::new(&Method::POST, "/chat/completions", Some(json!({"id": id})));
+            let response = http::Response::builder()
+                .status(200)
+                .body(json!({ "id": id }).to_string())
+                .unwrap();
+            cassette
+                .record(&key, Response::from(response))
+                .await
+                .unwrap();
+        }
+
+        let replayed = Cassette::open(&path, CassetteMode::Replay).unwrap();
+        assert_eq!(replayed.interactions(), cassette.interactions());
+        assert_eq!(replayed.interactions()[2].response, json!({"id": "c"}));
+    }
+
+    #[test]
+    fn replay_miss_is_an_error() {
+        let cassette = replaying(vec![interaction(json!({"model": "m"}), "x")]);
+        let key = RequestKey::new(&Method::GET, "/chat/completions", None);
+        assert!(matches!(
+            cassette.replay(&key),
+            Err(CassetteError::NoMatch { .. })
+        ));
+    }
+}
@@ -1,11 +1,296 @@
 use anyhow::{Context, Result, bail};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::api::{DEFAULT_TIMEOUT, HttpOptions, RetryPolicy};
+use crate::cassette::{Cassette, CassetteMode};
+use crate::files::{DEFAULT_MAX_IMAGE_BYTES, FileAccess};
+use crate::index::{self, VectorIndex};
+use crate::keypool::{KeyPool, KeySelection};
+use crate::ratelimit::RateLimiter;
+use crate::session::{DEFAULT_MAX_MESSAGES, DEFAULT_MAX_SESSIONS, SessionStore};
 
 /// Configuration loaded from the TOML config file.
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
+    /// A single API key. May be combined with, or replaced by, `api_keys`.
+    #[serde(default)]
     pub api_key: String,
+    /// Additional API keys to rotate through, optionally weighted.
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    /// How keys are chosen when more than one is configured.
+    #[serde(default)]
+    pub key_selection: KeySelection,
+    /// Default timeout for each xAI request, in seconds. Tools can override it per call.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Record or replay xAI traffic, for offline and deterministic runs.
+    pub cassette: Option<CassetteConfig>,
+    #[serde(default)]
+    pub files: FilesConfig,
+    /// Context window per model ID, for `chat`'s token preflight.
+    #[serde(default)]
+    pub context_lengths: HashMap<String, u32>,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default)]
+    pub index: IndexConfig,
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT.as_secs()
+}
+
+/// An entry in `api_keys`: either a bare key string or `{ key, weight }`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ApiKeyConfig {
+    Plain(String),
+    Weighted {
+        key: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl ApiKeyConfig {
+    fn parts(&self) -> (&str, u32) {
+        match self {
+            Self::Plain(key) => (key, 1),
+            Self::Weighted { key, weight } => (key, *weight),
+        }
+    }
+}
+
+impl Config {
+    /// All configured keys as `(key, weight)`, `api_key` first.
+    fn keys(&self) -> Vec<(String, u32)> {
+        let single = (!self.api_key.trim().is_empty()).then(|| (self.api_key.trim(), 1));
+        single
+            .into_iter()
+            .chain(self.api_keys.iter().map(ApiKeyConfig::parts))
+            .map(|(key, weight)| (key.trim().to_string(), weight))
+            .collect()
+    }
+
+    /// Build the key pool used by the xAI client.
+    pub fn key_pool(&self) -> KeyPool {
+        let keys = self.keys();
+        if keys.is_empty() {
+            // Only allowed when replaying a cassette, which never sends the key.
+            return KeyPool::single(String::new());
+        }
+        KeyPool::new(keys, self.key_selection)
+    }
+
+    fn replaying(&self) -> bool {
+        self.cassette
+            .as_ref()
+            .is_some_and(|c| c.mode == CassetteMode::Replay)
+    }
+}
+
+/// The optional `[retry]` table controlling retries of transient API failures.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Total attempts per request, including the first (1 disables retries).
+    pub max_attempts: u32,
+    /// Delay before the first retry, in milliseconds.
+    pub base_delay_ms: u64,
+    /// Upper bound on a single retry delay, in milliseconds.
+    pub max_delay_ms: u64,
+    /// Fraction (0.0 - 1.0) of each backoff delay that is randomised away.
+    pub jitter: f64,
+    /// Whether POST requests are retried on 408/5xx, which xAI may already
+    /// have acted on. 429s are always retried.
+    pub retry_unsafe: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+        Self {
+            max_attempts: policy.max_attempts,
+            base_delay_ms: policy.base_delay.as_millis() as u64,
+            max_delay_ms: policy.max_delay.as_millis() as u64,
+            jitter: policy.jitter,
+            retry_unsafe: policy.retry_unsafe,
+        }
+    }
+}
+
+/// The optional `[rate_limit]` table. Unset limits are not enforced locally.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Maximum requests per minute across all tools.
+    pub requests_per_minute: Option<u32>,
+    /// Maximum estimated tokens per minute across all tools.
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl From<&RateLimitConfig> for RateLimiter {
+    fn from(cfg: &RateLimitConfig) -> Self {
+        Self::new(cfg.requests_per_minute, cfg.tokens_per_minute)
+    }
+}
+
+impl From<&RetryConfig> for RetryPolicy {
+    fn from(cfg: &RetryConfig) -> Self {
+        Self {
+            max_attempts: cfg.max_attempts,
+            base_delay: Duration::from_millis(cfg.base_delay_ms),
+            max_delay: Duration::from_millis(cfg.max_delay_ms),
+            jitter: cfg.jitter,
+            retry_unsafe: cfg.retry_unsafe,
+        }
+    }
+}
+
+/// The optional `[http]` table: proxy, extra trust roots, client certificate
+/// and connect timeout.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Proxy URL for all xAI requests, e.g. `http://proxy.corp:3128`.
+    pub proxy: Option<String>,
+    /// Hosts or domains that bypass `proxy`.
+    pub no_proxy: Vec<String>,
+    /// PEM files with extra root certificates to trust.
+    pub ca_certs: Vec<PathBuf>,
+    /// PEM client certificate for mutual TLS. Requires `client_key`.
+    pub client_cert: Option<PathBuf>,
+    /// PKCS#8 PEM private key for `client_cert`.
+    pub client_key: Option<PathBuf>,
+    /// Timeout for establishing a connection, in seconds.
+    pub connect_timeout_secs: Option<u64>,
+}
+
+impl HttpConfig {
+    /// Read the referenced certificate files and build the client transport options.
+    pub fn options(&self) -> Result<HttpOptions> {
+        let read = |path: &PathBuf| {
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+        };
+
+        let ca_certs_pem = self.ca_certs.iter().map(read).collect::<Result<_>>()?;
+        let client_identity_pem = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some((read(cert)?, read(key)?)),
+            (None, None) => None,
+            _ => bail!("http.client_cert and http.client_key must be set together"),
+        };
+
+        Ok(HttpOptions {
+            proxy: self.proxy.clone(),
+            no_proxy: self.no_proxy.clone(),
+            ca_certs_pem,
+            client_identity_pem,
+            connect_timeout: self.connect_timeout_secs.map(Duration::from_secs),
+        })
+    }
+}
+
+/// The optional `[cassette]` table.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    /// Cassette file to write (record) or read (replay).
+    pub path: PathBuf,
+}
+
+impl CassetteConfig {
+    /// Open the configured cassette.
+    pub fn open(&self) -> Result<Cassette> {
+        Ok(Cassette::open(&self.path, self.mode)?)
+    }
+}
+
+/// The optional `[files]` table: local directories tools may read from.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct FilesConfig {
+    /// Directories (and their subdirectories) tools may access. Empty disables local files.
+    pub allowed_dirs: Vec<PathBuf>,
+    /// Largest image, local or `data:` URI, accepted by `chat_with_vision`.
+    pub max_image_bytes: u64,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            allowed_dirs: Vec::new(),
+            max_image_bytes: DEFAULT_MAX_IMAGE_BYTES,
+        }
+    }
+}
+
+impl FilesConfig {
+    /// Resolve the allowed directories, which must exist.
+    pub fn access(&self) -> Result<FileAccess> {
+        FileAccess::new(&self.allowed_dirs, self.max_image_bytes)
+            .context("Invalid files.allowed_dirs")
+    }
+}
+
+/// The optional `[sessions]` table bounding server-side conversation history.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SessionsConfig {
+    /// Messages kept per session; the oldest turns are dropped beyond this.
+    pub max_messages: usize,
+    /// Live sessions; creating one more evicts the least recently used.
+    pub max_sessions: usize,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: DEFAULT_MAX_MESSAGES,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+        }
+    }
+}
+
+impl From<&SessionsConfig> for SessionStore {
+    fn from(cfg: &SessionsConfig) -> Self {
+        Self::new(cfg.max_messages, cfg.max_sessions)
+    }
+}
+
+/// The optional `[index]` table locating the local vector index.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct IndexConfig {
+    /// Directory holding one JSON file per collection, created on first use.
+    pub dir: PathBuf,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            dir: index::default_dir(),
+        }
+    }
+}
+
+impl From<&IndexConfig> for VectorIndex {
+    fn from(cfg: &IndexConfig) -> Self {
+        Self::new(cfg.dir.clone())
+    }
 }
 
 /// Returns the path to the config file, using `dirs::config_dir()` for cross-platform support.
@@ -34,9 +319,75 @@ pub fn load() -> Result<Config> {
     let config: Config =
         toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
 
-    if config.api_key.trim().is_empty() {
+    if config.keys().is_empty() && !config.replaying() {
+        bail!(
+            "no API key in {} — set api_key (or api_keys) to your xAI API key",
+            path.display()
+        );
+    }
+
+    for entry in &config.api_keys {
+        let (key, weight) = entry.parts();
+        if key.trim().is_empty() {
+            bail!("api_keys in {} contains an empty key", path.display());
+        }
+        if weight == 0 {
+            bail!("api_keys weights in {} must be at least 1", path.display());
+        }
+    }
+
+    if config.timeout_secs == 0 {
+        bail!("timeout_secs in {} must be greater than 0", path.display());
+    }
+
+    if config.retry.max_attempts == 0 {
+        bail!(
+            "retry.max_attempts in {} must be at least 1",
+            path.display()
+        );
+    }
+
+    if config.retry.base_delay_ms > config.retry.max_delay_ms {
+        bail!(
+            "retry.base_delay_ms in {} must not exceed retry.max_delay_ms",
+            path.display()
+        );
+    }
+
+    if config.rate_limit.requests_per_minute == Some(0)
+        || config.rate_limit.tokens_per_minute == Some(0)
+    {
+        bail!(
+            "rate_limit values in {} must be greater than 0 (omit them to disable)",
+            path.display()
+        );
+    }
+
+    if !(0.0..=1.0).contains(&config.retry.jitter) {
+        bail!(
+            "retry.jitter in {} must be between 0.0 and 1.0, got {}",
+            path.display(),
+            config.retry.jitter
+        );
+    }
+
+    if config.files.max_image_bytes == 0 {
+        bail!(
+            "files.max_image_bytes in {} must be greater than 0",
+            path.display()
+        );
+    }
+
+    if config.sessions.max_messages == 0 || config.sessions.max_sessions == 0 {
+        bail!(
+            "sessions.max_messages and sessions.max_sessions in {} must be greater than 0",
+            path.display()
+        );
+    }
+
+    if config.http.connect_timeout_secs == Some(0) {
         bail!(
-            "api_key in {} is empty — set it to your xAI API key",
+            "http.connect_timeout_secs in {} must be greater than 0",
             path.display()
         );
     }
@@ -0,0 +1,415 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Default cap on a single local or inline image, matching xAI's upload limit.
+pub const DEFAULT_MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// Largest text file tools will read, e.g. for indexing.
+pub const MAX_TEXT_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Image types xAI accepts for vision input.
+const SUPPORTED_IMAGE_TYPES: &[&str] = &["image/jpeg", "image/png"];
+
+/// Errors from resolving or reading local files on behalf of a tool.
+#[derive(Error, Debug)]
+pub enum FileError {
+    #[error("local file access is disabled — add directories to files.allowed_dirs in config.toml")]
+    Disabled,
+
+    #[error("{0} is not inside an allowed directory")]
+    NotAllowed(PathBuf),
+
+    #[error("file paths must be absolute, got {0}")]
+    Relative(PathBuf),
+
+    #[error("{path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("{what} is {size} bytes, over the {limit} byte limit")]
+    TooLarge { what: String, size: u64, limit: u64 },
+
+    #[error("unsupported image type {0} — use JPEG or PNG")]
+    UnsupportedType(String),
+
+    #[error("invalid data URI: {0}")]
+    InvalidDataUri(String),
+
+    #[error("{0} is not UTF-8 text")]
+    NotText(PathBuf),
+}
+
+/// Local filesystem access granted to tools, confined to a set of directories.
+#[derive(Debug, Clone)]
+pub struct FileAccess {
+    /// Canonicalized allowed directories. Empty disables local file access.
+    allowed_dirs: Vec<PathBuf>,
+    max_image_bytes: u64,
+}
+
+impl Default for FileAccess {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl FileAccess {
+    /// Allow access below `dirs`, which are canonicalized (and so must exist).
+    pub fn new(
+        dirs: impl IntoIterator<Item = impl AsRef<Path>>,
+        max_image_bytes: u64,
+    ) -> Result<Self, FileError> {
+        let allowed_dirs = dirs
+            .into_iter()
+            .map(|dir| {
+                let dir = dir.as_ref();
+                dir.canonicalize().map_err(|source| FileError::Io {
+                    path: dir.to_path_buf(),
+                    source,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            allowed_dirs,
+            max_image_bytes,
+        })
+    }
+
+    /// No local file access at all.
+    pub fn disabled() -> Self {
+        Self {
+            allowed_dirs: Vec::new(),
+            max_image_bytes: DEFAULT_MAX_IMAGE_BYTES,
+        }
+    }
+
+    /// Resolve `path` (absolute, optionally as a `file://` URI) and check it
+    /// lies inside an allowed directory once symlinks are followed.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, FileError> {
+        if self.allowed_dirs.is_empty() {
+            return Err(FileError::Disabled);
+        }
+        let path = Path::new(path.strip_prefix("file://").unwrap_or(path));
+        if !path.is_absolute() {
+            return Err(FileError::Relative(path.to_path_buf()));
+        }
+        let canonical = path.canonicalize().map_err(|source| FileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if !self
+            .allowed_dirs
+            .iter()
+            .any(|dir| canonical.starts_with(dir))
+        {
+            return Err(FileError::NotAllowed(path.to_path_buf()));
+        }
+        Ok(canonical)
+    }
+
+    /// Resolve a file to be written: like [`resolve`](Self::resolve), but the
+    /// file itself may not exist yet, so its directory is checked instead.
+    pub fn resolve_output(&self, path: &str) -> Result<PathBuf, FileError> {
+        if self.allowed_dirs.is_empty() {
+            return Err(FileError::Disabled);
+        }
+        let path = Path::new(path.strip_prefix("file://").unwrap_or(path));
+        if !path.is_absolute() {
+            return Err(FileError::Relative(path.to_path_buf()));
+        }
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(FileError::NotAllowed(path.to_path_buf()));
+        };
+        let dir = dir.canonicalize().map_err(|source| FileError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        let target = dir.join(name);
+        // The file may be a symlink pointing elsewhere, even to a file that
+        // does not exist yet.
+        let resolved = match target.symlink_metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => target
+                .canonicalize()
+                .map_err(|_| FileError::NotAllowed(path.to_path_buf()))?,
+            _ => target.clone(),
+        };
+        if !self
+            .allowed_dirs
+            .iter()
+            .any(|allowed| dir.starts_with(allowed) && resolved.starts_with(allowed))
+        {
+            return Err(FileError::NotAllowed(path.to_path_buf()));
+        }
+        Ok(target)
+    }
+
+    /// Write `bytes` to a file inside an allowed directory, replacing it if it
+    /// exists, and return the path written.
+    pub async fn write(&self, path: &str, bytes: &[u8]) -> Result<PathBuf, FileError> {
+        let target = self.resolve_output(path)?;
+        tokio::fs::write(&target, bytes)
+            .await
+            .map_err(|source| FileError::Io {
+                path: target.clone(),
+                source,
+            })?;
+        Ok(target)
+    }
+
+    /// Read a UTF-8 text file inside an allowed directory, up to
+    /// [`MAX_TEXT_BYTES`].
+    pub async fn read_text(&self, path: &str) -> Result<String, FileError> {
+        let path = self.resolve(path)?;
+        let io_err = |source| FileError::Io {
+            path: path.clone(),
+            source,
+        };
+        let size = tokio::fs::metadata(&path).await.map_err(io_err)?.len();
+        if size > MAX_TEXT_BYTES {
+            return Err(FileError::TooLarge {
+                what: path.display().to_string(),
+                size,
+                limit: MAX_TEXT_BYTES,
+            });
+        }
+        let bytes = tokio::fs::read(&path).await.map_err(io_err)?;
+        String::from_utf8(bytes).map_err(|_| FileError::NotText(path))
+    }
+
+    /// Turn an image reference into a URL xAI can fetch: `http(s)://` URLs
+    /// pass through, while `data:` URIs and local files are checked and
+    /// returned as base64 `data:` URIs.
+    pub async fn image_url(&self, source: &str) -> Result<String, FileError> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            return Ok(source.to_string());
+        }
+        if let Some(uri) = source.strip_prefix("data:") {
+            return self.check_data_uri(uri).map(|()| source.to_string());
+        }
+
+        let path = self.resolve(source)?;
+        let io_err = |source| FileError::Io {
+            path: path.clone(),
+            source,
+        };
+        let size = tokio::fs::metadata(&path).await.map_err(io_err)?.len();
+        self.check_size(&path.display().to_string(), size)?;
+        let bytes = tokio::fs::read(&path).await.map_err(io_err)?;
+        let mime = supported_image_type(&bytes)?;
+        Ok(format!("data:{mime};base64,{}", BASE64.encode(&bytes)))
+    }
+
+    /// Validate the part of a `data:` URI after the scheme.
+    fn check_data_uri(&self, uri: &str) -> Result<(), FileError> {
+        let (header, data) = uri
+            .split_once(',')
+            .ok_or_else(|| FileError::InvalidDataUri("missing ','".into()))?;
+        let declared = header
+            .strip_suffix(";base64")
+            .ok_or_else(|| FileError::InvalidDataUri("only base64 data is supported".into()))?;
+
+        // Bound the work before decoding: 4 base64 chars carry 3 bytes.
+        self.check_size("data URI", data.len() as u64 / 4 * 3)?;
+        let bytes = BASE64
+            .decode(data)
+            .map_err(|e| FileError::InvalidDataUri(e.to_string()))?;
+        let actual = supported_image_type(&bytes)?;
+        if !declared.is_empty() && declared != actual {
+            return Err(FileError::InvalidDataUri(format!(
+                "declared {declared} but data is {actual}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_size(&self, what: &str, size: u64) -> Result<(), FileError> {
+        if size > self.max_image_bytes {
+            return Err(FileError::TooLarge {
+                what: what.into(),
+                size,
+                limit: self.max_image_bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Detect an image MIME type from its leading bytes.
+pub fn sniff_image_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"\x89PNG") {
+        Some("image/png")
+    } else if bytes.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+fn supported_image_type(bytes: &[u8]) -> Result<&'static str, FileError> {
+    match sniff_image_mime(bytes) {
+        Some(mime) if SUPPORTED_IMAGE_TYPES.contains(&mime) => Ok(mime),
+        Some(mime) => Err(FileError::UnsupportedType(mime.into())),
+        None => Err(FileError::UnsupportedType("unknown".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+    fn access(dir: &Path, limit: u64) -> FileAccess {
+        FileAccess::new([dir], limit).unwrap()
+    }
+
+    #[tokio::test]
+    async fn local_file_becomes_data_uri() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shot.png");
+        std::fs::write(&path, PNG).unwrap();
+
+        let url = access(dir.path(), 1024)
+            .image_url(path.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(url, format!("data:image/png;base64,{}", BASE64.encode(PNG)));
+    }
+
+    #[tokio::test]
+    async fn files_outside_allowed_dirs_are_rejected() {
+        let allowed = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let path = other.path().join("secret.png");
+        std::fs::write(&path, PNG).unwrap();
+
+        let files = access(allowed.path(), 1024);
+        let err = files.image_url(path.to_str().unwrap()).await.unwrap_err();
+        assert!(matches!(err, FileError::NotAllowed(_)), "{err}");
+
+        // `..` cannot climb out of an allowed directory.
+        let sneaky = allowed
+            .path()
+            .join("..")
+            .join(other.path().file_name().unwrap());
+        let err = files.resolve(sneaky.join("secret.png").to_str().unwrap());
+        assert!(matches!(err, Err(FileError::NotAllowed(_))));
+
+        assert!(matches!(
+            files.resolve("relative/shot.png"),
+            Err(FileError::Relative(_))
+        ));
+        assert!(matches!(
+            FileAccess::disabled().resolve(path.to_str().unwrap()),
+            Err(FileError::Disabled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn text_files_must_be_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = access(dir.path(), 1024);
+        let notes = dir.path().join("notes.md");
+        std::fs::write(&notes, "# Notes").unwrap();
+        assert_eq!(
+            files.read_text(notes.to_str().unwrap()).await.unwrap(),
+            "# Notes"
+        );
+
+        let png = dir.path().join("shot.png");
+        std::fs::write(&png, PNG).unwrap();
+        let err = files.read_text(png.to_str().unwrap()).await.unwrap_err();
+        assert!(matches!(err, FileError::NotText(_)), "{err}");
+    }
+
+    #[tokio::test]
+    async fn writes_stay_inside_allowed_dirs() {
+        let allowed = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let files = access(allowed.path(), 1024);
+
+        let out = allowed.path().join("vectors.npy");
+        let written = files.write(out.to_str().unwrap(), b"data").await.unwrap();
+        assert_eq!(std::fs::read(&written).unwrap(), b"data");
+
+        let outside = other.path().join("vectors.npy");
+        assert!(matches!(
+            files.write(outside.to_str().unwrap(), b"x").await,
+            Err(FileError::NotAllowed(_))
+        ));
+        assert!(matches!(
+            files.resolve_output(allowed.path().join("missing/v.npy").to_str().unwrap()),
+            Err(FileError::Io { .. })
+        ));
+
+        #[cfg(unix)]
+        {
+            let link = allowed.path().join("link.npy");
+            std::os::unix::fs::symlink(&outside, &link).unwrap();
+            assert!(matches!(
+                files.resolve_output(link.to_str().unwrap()),
+                Err(FileError::NotAllowed(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn size_and_type_limits_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let big = dir.path().join("big.png");
+        std::fs::write(&big, [PNG, &[0u8; 64]].concat()).unwrap();
+        let text = dir.path().join("notes.png");
+        std::fs::write(&text, b"just some text").unwrap();
+
+        let files = access(dir.path(), 32);
+        assert!(matches!(
+            files.image_url(big.to_str().unwrap()).await,
+            Err(FileError::TooLarge { size: 76, .. })
+        ));
+        assert!(matches!(
+            files.image_url(text.to_str().unwrap()).await,
+            Err(FileError::UnsupportedType(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn data_uris_are_validated() {
+        let files = FileAccess::disabled();
+        let png = format!("data:image/png;base64,{}", BASE64.encode(PNG));
+        assert_eq!(files.image_url(&png).await.unwrap(), png);
+
+        let mislabelled = format!("data:image/jpeg;base64,{}", BASE64.encode(PNG));
+        let gif = format!("data:image/gif;base64,{}", BASE64.encode(b"GIF89a......"));
+        for (uri, expected) in [
+            (mislabelled.as_str(), "declared image/jpeg"),
+            ("data:image/png,rawdata", "only base64"),
+            ("data:image/png;base64,@@@@", "invalid data URI"),
+            (gif.as_str(), "unsupported image type image/gif"),
+        ] {
+            let err = files.image_url(uri).await.unwrap_err().to_string();
+            assert!(err.contains(expected), "{uri}: {err}");
+        }
+    }
+
+    #[test]
+    fn sniffs_common_formats() {
+        assert_eq!(sniff_image_mime(PNG), Some("image/png"));
+        assert_eq!(
+            sniff_image_mime(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some("image/jpeg")
+        );
+        assert_eq!(
+            sniff_image_mime(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_image_mime(b"hello"), None);
+    }
+}
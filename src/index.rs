@@ -0,0 +1,629 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::vectors;
+
+/// Default chunk size for indexed text, roughly 500 tokens.
+pub const DEFAULT_CHUNK_CHARS: usize = 2000;
+
+/// Default number of characters repeated between neighbouring chunks.
+pub const DEFAULT_CHUNK_OVERLAP: usize = 200;
+
+/// Longest accepted collection name.
+const MAX_NAME_LEN: usize = 64;
+
+/// Errors from reading or updating the vector index.
+#[derive(Error, Debug)]
+pub enum IndexError {
+    #[error(
+        "collection names may only contain letters, digits, '-' or '_' \
+         (at most {MAX_NAME_LEN} characters), got '{0}'"
+    )]
+    InvalidName(String),
+
+    #[error("unknown collection '{0}' — create it with index_documents")]
+    NotFound(String),
+
+    #[error(
+        "collection '{collection}' was indexed with {expected}, not {got} — \
+         delete and re-index it to change models"
+    )]
+    ModelMismatch {
+        collection: String,
+        expected: String,
+        got: String,
+    },
+
+    #[error("collection '{collection}' holds {expected}-dimensional vectors, got {got}")]
+    DimensionMismatch {
+        collection: String,
+        expected: usize,
+        got: usize,
+    },
+
+    #[error("{path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("{path} is not a valid index file: {message}")]
+    Corrupt { path: PathBuf, message: String },
+}
+
+/// An indexed text with its chunks, as stored in a collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    /// Caller-supplied fields returned with every search hit.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub metadata: Map<String, Value>,
+    pub chunks: Vec<Chunk>,
+    /// When the document was (re-)indexed, in seconds since the Unix epoch.
+    pub indexed_at: u64,
+}
+
+impl Document {
+    pub fn new(metadata: Map<String, Value>, chunks: Vec<Chunk>) -> Self {
+        let indexed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self {
+            metadata,
+            chunks,
+            indexed_at,
+        }
+    }
+}
+
+/// A piece of a document and its embedding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub text: String,
+    /// Stored as base64 little-endian `f32`s to keep index files small.
+    #[serde(with = "base64_vector")]
+    pub vector: Vec<f32>,
+}
+
+/// A chunk matching a search, with its cosine similarity to the query.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub document_id: String,
+    /// Position of the chunk within its document, from 0.
+    pub chunk: usize,
+    pub score: f32,
+    pub text: String,
+    pub metadata: Map<String, Value>,
+}
+
+/// Summary of one collection.
+#[derive(Debug, Clone)]
+pub struct CollectionInfo {
+    pub name: String,
+    pub model: String,
+    pub dimensions: usize,
+    pub documents: usize,
+    pub chunks: usize,
+}
+
+/// On-disk form of a collection: every chunk was embedded with `model`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Collection {
+    model: String,
+    dimensions: usize,
+    documents: BTreeMap<String, Document>,
+}
+
+/// Named collections of embedded document chunks, each persisted as one JSON
+/// file in a directory. Collections are cached in memory once loaded, so the
+/// directory must not be shared with another running server.
+#[derive(Debug)]
+pub struct VectorIndex {
+    dir: PathBuf,
+    collections: Mutex<HashMap<String, Collection>>,
+}
+
+impl Default for VectorIndex {
+    fn default() -> Self {
+        Self::new(default_dir())
+    }
+}
+
+impl VectorIndex {
+    /// Keep collections in `dir`, which is created on first write.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            collections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The embedding model a collection was built with.
+    pub async fn model(&self, collection: &str) -> Result<String, IndexError> {
+        let mut cache = self.collections.lock().await;
+        self.loaded(&mut cache, collection)
+            .await?
+            .map(|c| c.model.clone())
+            .ok_or_else(|| IndexError::NotFound(collection.into()))
+    }
+
+    /// Add documents to a collection, creating it if needed. A document whose
+    /// id is already indexed is replaced. Returns the ids that were replaced.
+    pub async fn upsert(
+        &self,
+        collection: &str,
+        model: &str,
+        documents: Vec<(String, Document)>,
+    ) -> Result<Vec<String>, IndexError> {
+        let mut cache = self.collections.lock().await;
+        let existing = self.loaded(&mut cache, collection).await?;
+        let mut updated = match existing {
+            Some(c) if c.model != model => {
+                return Err(IndexError::ModelMismatch {
+                    collection: collection.into(),
+                    expected: c.model.clone(),
+                    got: model.into(),
+                });
+            }
+            Some(c) => Collection {
+                model: c.model.clone(),
+                dimensions: c.dimensions,
+                documents: c.documents.clone(),
+            },
+            None => Collection {
+                model: model.into(),
+                dimensions: documents
+                    .iter()
+                    .flat_map(|(_, d)| &d.chunks)
+                    .map(|c| c.vector.len())
+                    .next()
+                    .unwrap_or(0),
+                documents: BTreeMap::new(),
+            },
+        };
+
+        if let Some(chunk) = documents
+            .iter()
+            .flat_map(|(_, d)| &d.chunks)
+            .find(|c| c.vector.len() != updated.dimensions)
+        {
+            return Err(IndexError::DimensionMismatch {
+                collection: collection.into(),
+                expected: updated.dimensions,
+                got: chunk.vector.len(),
+            });
+        }
+
+        let mut replaced = Vec::new();
+        for (id, document) in documents {
+            if updated.documents.insert(id.clone(), document).is_some() {
+                replaced.push(id);
+            }
+        }
+        self.save(collection, &updated).await?;
+        cache.insert(collection.into(), updated);
+        Ok(replaced)
+    }
+
+    /// The `top_k` chunks most similar to `query`, best first.
+    pub async fn search(
+        &self,
+        collection: &str,
+        query: &[f32],
+        top_k: usize,
+    ) -> Result<Vec<Hit>, IndexError> {
+        let mut cache = self.collections.lock().await;
+        let c = self
+            .loaded(&mut cache, collection)
+            .await?
+            .ok_or_else(|| IndexError::NotFound(collection.into()))?;
+        if query.len() != c.dimensions {
+            return Err(IndexError::DimensionMismatch {
+                collection: collection.into(),
+                expected: c.dimensions,
+                got: query.len(),
+            });
+        }
+
+        let chunks: Vec<(&String, usize, &Document, &Chunk)> = c
+            .documents
+            .iter()
+            .flat_map(|(id, doc)| {
+                doc.chunks
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, chunk)| (id, i, doc, chunk))
+            })
+            .collect();
+        let ranked = vectors::rank(query, chunks.iter().map(|(.., c)| c.vector.as_slice()));
+        Ok(ranked
+            .into_iter()
+            .take(top_k)
+            .map(|(i, score)| {
+                let (id, chunk, doc, c) = chunks[i];
+                Hit {
+                    document_id: id.clone(),
+                    chunk,
+                    score,
+                    text: c.text.clone(),
+                    metadata: doc.metadata.clone(),
+                }
+            })
+            .collect())
+    }
+
+    /// Remove documents from a collection, returning the ids that were found.
+    pub async fn delete_documents(
+        &self,
+        collection: &str,
+        ids: &[String],
+    ) -> Result<Vec<String>, IndexError> {
+        let mut cache = self.collections.lock().await;
+        let c = self
+            .loaded(&mut cache, collection)
+            .await?
+            .ok_or_else(|| IndexError::NotFound(collection.into()))?;
+        let deleted: Vec<String> = ids
+            .iter()
+            .filter(|id| c.documents.contains_key(*id))
+            .cloned()
+            .collect();
+        if deleted.is_empty() {
+            return Ok(deleted);
+        }
+
+        let updated = Collection {
+            model: c.model.clone(),
+            dimensions: c.dimensions,
+            documents: c
+                .documents
+                .iter()
+                .filter(|(id, _)| !deleted.contains(id))
+                .map(|(id, doc)| (id.clone(), doc.clone()))
+                .collect(),
+        };
+        self.save(collection, &updated).await?;
+        cache.insert(collection.into(), updated);
+        Ok(deleted)
+    }
+
+    /// Delete a collection and its file.
+    pub async fn delete_collection(&self, collection: &str) -> Result<(), IndexError> {
+        let mut cache = self.collections.lock().await;
+        check_name(collection)?;
+        cache.remove(collection);
+        let path = self.path(collection);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(IndexError::NotFound(collection.into()))
+            }
+            Err(source) => Err(IndexError::Io { path, source }),
+        }
+    }
+
+    /// All collections in the index directory, by name.
+    pub async fn list(&self) -> Result<Vec<CollectionInfo>, IndexError> {
+        let mut names = Vec::new();
+        match tokio::fs::read_dir(&self.dir).await {
+            Ok(mut entries) => {
+                let io_err = |source| IndexError::Io {
+                    path: self.dir.clone(),
+                    source,
+                };
+                while let Some(entry) = entries.next_entry().await.map_err(io_err)? {
+                    let name = entry.file_name();
+                    if let Some(name) = name.to_str().and_then(|n| n.strip_suffix(".json"))
+                        && check_name(name).is_ok()
+                    {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(source) => {
+                return Err(IndexError::Io {
+                    path: self.dir.clone(),
+                    source,
+                });
+            }
+        }
+        names.sort();
+
+        let mut cache = self.collections.lock().await;
+        let mut infos = Vec::with_capacity(names.len());
+        for name in names {
+            if let Some(c) = self.loaded(&mut cache, &name).await? {
+                infos.push(CollectionInfo {
+                    model: c.model.clone(),
+                    dimensions: c.dimensions,
+                    documents: c.documents.len(),
+                    chunks: c.documents.values().map(|d| d.chunks.len()).sum(),
+                    name,
+                });
+            }
+        }
+        Ok(infos)
+    }
+
+    fn path(&self, collection: &str) -> PathBuf {
+        self.dir.join(format!("{collection}.json"))
+    }
+
+    /// The cached collection, loading it from disk on first use. `None` if
+    /// it does not exist.
+    async fn loaded<'a>(
+        &self,
+        cache: &'a mut HashMap<String, Collection>,
+        collection: &str,
+    ) -> Result<Option<&'a mut Collection>, IndexError> {
+        check_name(collection)?;
+        if !cache.contains_key(collection) {
+            let path = self.path(collection);
+            let bytes = match tokio::fs::read(&path).await {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(source) => return Err(IndexError::Io { path, source }),
+            };
+            let loaded = serde_json::from_slice(&bytes).map_err(|e| IndexError::Corrupt {
+                path,
+                message: e.to_string(),
+            })?;
+            cache.insert(collection.into(), loaded);
+        }
+        Ok(cache.get_mut(collection))
+    }
+
+    /// Write a collection through a temporary file, so a crash never leaves
+    /// a half-written index behind.
+    async fn save(&self, collection: &str, c: &Collection) -> Result<(), IndexError> {
+        let path = self.path(collection);
+        let tmp = self.dir.join(format!(".{collection}.json.tmp"));
+        let io_err = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| IndexError::Io { path, source }
+        };
+        let bytes = serde_json::to_vec(c).expect("collections always serialize");
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(io_err(&self.dir))?;
+        tokio::fs::write(&tmp, bytes).await.map_err(io_err(&tmp))?;
+        tokio::fs::rename(&tmp, &path).await.map_err(io_err(&path))
+    }
+}
+
+/// Where collections are kept unless configured otherwise.
+pub fn default_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| {
+            let home = std::env::var("HOME").unwrap_or_else(|_| "/root".into());
+            PathBuf::from(home).join(".local/share")
+        })
+        .join("mcp-server-grok-chat")
+        .join("index")
+}
+
+fn check_name(name: &str) -> Result<(), IndexError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(IndexError::InvalidName(name.into()))
+    }
+}
+
+/// Split text into chunks of at most `max_chars` characters, breaking at a
+/// paragraph, line, sentence or word boundary in the second half of each
+/// chunk where possible. Consecutive chunks share about `overlap` characters.
+pub fn chunk_text(text: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+    // Byte offset of every character, plus the end of the text.
+    let bounds: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect();
+    chunk_units(text, &bounds, max_chars, overlap)
+}
+
+/// [`chunk_text`] measured in arbitrary units, such as tokens: `bounds`
+/// holds the byte offset where each unit starts, followed by `text.len()`,
+/// and every offset must fall on a character boundary.
+pub fn chunk_units(text: &str, bounds: &[usize], max_units: usize, overlap: usize) -> Vec<String> {
+    let max_units = max_units.max(1);
+    let len = bounds.len() - 1;
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < len {
+        let mut end = len.min(start + max_units);
+        if end < len {
+            let window = &text[bounds[start + max_units / 2]..bounds[end]];
+            let cut = ["\n\n", "\n", ". ", " "]
+                .iter()
+                .find_map(|sep| window.rfind(sep).map(|i| i + sep.len()));
+            if let Some(cut) = cut {
+                // Mid-unit separators cut before the unit that holds them.
+                let byte = bounds[start + max_units / 2] + cut;
+                end = bounds.binary_search(&byte).unwrap_or_else(|i| i - 1);
+            }
+        }
+
+        let chunk = text[bounds[start]..bounds[end]].trim();
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_string());
+        }
+        if end == len {
+            break;
+        }
+
+        // Step back for the overlap, then forward to the next word so the
+        // following chunk does not open mid-word.
+        let mut next = end.saturating_sub(overlap).max(start + 1);
+        if next < end
+            && let Some(space) = text[bounds[next]..bounds[end]].find(char::is_whitespace)
+        {
+            next = bounds
+                .binary_search(&(bounds[next] + space))
+                .map_or(next, |i| i + 1);
+        }
+        start = next;
+    }
+    chunks
+}
+
+mod base64_vector {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(vector: &[f32], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&crate::vectors::to_base64(vector))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<f32>, D::Error> {
+        let encoded = String::deserialize(d)?;
+        crate::vectors::from_base64(&encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(text: &str, vector: Vec<f32>) -> Document {
+        Document::new(
+            Map::new(),
+            vec![Chunk {
+                text: text.into(),
+                vector,
+            }],
+        )
+    }
+
+    #[test]
+    fn chunks_break_at_boundaries_and_overlap() {
+        let text = "First paragraph here.\n\nSecond one is a bit longer. It has two sentences.";
+        let chunks = chunk_text(text, 40, 10);
+        assert_eq!(chunks[0], "First paragraph here.");
+        assert!(chunks.iter().all(|c| c.chars().count() <= 40));
+        assert!(chunks.last().unwrap().ends_with("two sentences."));
+        // Overlapping chunks repeat whole words from the end of the previous one.
+        assert!(chunks.windows(2).skip(1).any(|w| {
+            let first_word = w[1].split_whitespace().next().unwrap();
+            w[0].contains(first_word)
+        }));
+
+        assert_eq!(chunk_text("short", 40, 10), ["short"]);
+        assert!(chunk_text("   ", 40, 10).is_empty());
+        // No boundaries at all: hard cuts, multi-byte safe.
+        let chunks = chunk_text(&"é".repeat(25), 10, 0);
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn chunk_units_counts_units_not_characters() {
+        // Four two-word "tokens"; at most two per chunk, cut at a space.
+        let text = "aa bb cc dd ee ff gg hh";
+        let bounds = [0, 6, 12, 18, text.len()];
+        assert_eq!(
+            chunk_units(text, &bounds, 2, 0),
+            ["aa bb cc dd", "ee ff gg hh"]
+        );
+        // A separator inside a unit cuts before that unit.
+        assert_eq!(
+            chunk_units("ab cd. ef gh", &[0, 3, 10, 12], 2, 0),
+            ["ab", "cd. ef gh"]
+        );
+    }
+
+    #[tokio::test]
+    async fn collections_persist_reindex_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = VectorIndex::new(dir.path().join("index"));
+        index
+            .upsert(
+                "notes",
+                "m",
+                vec![
+                    ("a".into(), doc("apples", vec![1.0, 0.0])),
+                    ("b".into(), doc("bananas", vec![0.0, 1.0])),
+                ],
+            )
+            .await
+            .unwrap();
+
+        // A fresh instance reads the same collection from disk.
+        let index = VectorIndex::new(dir.path().join("index"));
+        let hits = index.search("notes", &[0.9, 0.1], 1).await.unwrap();
+        assert_eq!(hits[0].document_id, "a");
+
+        let replaced = index
+            .upsert(
+                "notes",
+                "m",
+                vec![("a".into(), doc("avocados", vec![0.0, 1.0]))],
+            )
+            .await
+            .unwrap();
+        assert_eq!(replaced, ["a"]);
+        let hits = index.search("notes", &[0.0, 1.0], 5).await.unwrap();
+        let texts: Vec<&str> = hits.iter().map(|h| h.text.as_str()).collect();
+        assert_eq!(texts, ["avocados", "bananas"]);
+        let infos = index.list().await.unwrap();
+        assert_eq!((infos[0].documents, infos[0].chunks), (2, 2));
+
+        assert!(matches!(
+            index.upsert("notes", "other", vec![]).await,
+            Err(IndexError::ModelMismatch { .. })
+        ));
+        assert!(matches!(
+            index.search("notes", &[1.0], 1).await,
+            Err(IndexError::DimensionMismatch { .. })
+        ));
+        assert!(matches!(
+            index.search("../x", &[1.0], 1).await,
+            Err(IndexError::InvalidName(_))
+        ));
+
+        let deleted = index
+            .delete_documents("notes", &["a".into(), "zz".into()])
+            .await
+            .unwrap();
+        assert_eq!(deleted, ["a"]);
+        index.delete_collection("notes").await.unwrap();
+        assert!(index.list().await.unwrap().is_empty());
+        assert!(matches!(
+            index.model("notes").await,
+            Err(IndexError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_delete_leaves_collection_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = VectorIndex::new(dir.path());
+        index
+            .upsert("notes", "m", vec![("a".into(), doc("apples", vec![1.0]))])
+            .await
+            .unwrap();
+
+        // A directory where the temporary file goes makes the save fail.
+        std::fs::create_dir(dir.path().join(".notes.json.tmp")).unwrap();
+        assert!(matches!(
+            index.delete_documents("notes", &["a".into()]).await,
+            Err(IndexError::Io { .. })
+        ));
+        let hits = index.search("notes", &[1.0], 5).await.unwrap();
+        assert_eq!(hits[0].document_id, "a");
+    }
+}
@@ -0,0 +1,238 @@
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a key rejected with HTTP 401 stays out of rotation.
+const REJECTED_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// How the next API key is chosen from a [`KeyPool`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySelection {
+    /// Weighted round-robin across all keys (default).
+    #[default]
+    RoundRobin,
+    /// Prefer keys that were rate limited longest ago (or never), falling
+    /// back to weighted round-robin among equals.
+    LeastRecentlyLimited,
+}
+
+#[derive(Debug)]
+struct PooledKey {
+    secret: String,
+    weight: u32,
+    label: String,
+}
+
+#[derive(Debug, Default, Clone)]
+struct KeyState {
+    /// Smooth weighted round-robin counter.
+    current: i64,
+    last_limited: Option<Instant>,
+    /// Set on HTTP 401; the key is skipped until then.
+    rejected_until: Option<Instant>,
+}
+
+/// A set of xAI API keys with rotation and failover.
+///
+/// `XaiClient` picks a key per attempt; on HTTP 429 the key is marked as
+/// limited, and on HTTP 401 it is taken out of rotation for
+/// [`REJECTED_BACKOFF`]. Either way the request fails over to the next key
+/// not yet tried.
+#[derive(Debug)]
+pub struct KeyPool {
+    keys: Vec<PooledKey>,
+    selection: KeySelection,
+    state: Mutex<Vec<KeyState>>,
+}
+
+impl KeyPool {
+    /// Build a pool from `(key, weight)` pairs. Weights below 1 are treated as 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` is empty.
+    pub fn new(keys: impl IntoIterator<Item = (String, u32)>, selection: KeySelection) -> Self {
+        let keys: Vec<PooledKey> = keys
+            .into_iter()
+            .map(|(secret, weight)| PooledKey {
+                label: redact(&secret),
+                secret,
+                weight: weight.max(1),
+            })
+            .collect();
+        assert!(!keys.is_empty(), "KeyPool requires at least one key");
+
+        Self {
+            state: Mutex::new(vec![KeyState::default(); keys.len()]),
+            keys,
+            selection,
+        }
+    }
+
+    /// A pool with a single key.
+    pub fn single(key: String) -> Self {
+        Self::new([(key, 1)], KeySelection::default())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// The secret for key `index`.
+    pub(crate) fn secret(&self, index: usize) -> &str {
+        &self.keys[index].secret
+    }
+
+    /// A redacted label for key `index`, safe to log.
+    pub(crate) fn label(&self, index: usize) -> &str {
+        &self.keys[index].label
+    }
+
+    /// Choose the next key, skipping those in `tried` unless every key has
+    /// been tried, and those recently rejected unless no other is left.
+    pub(crate) fn pick(&self, tried: &[usize]) -> usize {
+        let mut state = self.lock();
+
+        let mut candidates: Vec<usize> = (0..self.keys.len())
+            .filter(|i| !tried.contains(i))
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..self.keys.len()).collect();
+        }
+        let now = Instant::now();
+        if candidates
+            .iter()
+            .any(|&i| state[i].rejected_until.is_none_or(|t| t <= now))
+        {
+            candidates.retain(|&i| state[i].rejected_until.is_none_or(|t| t <= now));
+        }
+
+        if self.selection == KeySelection::LeastRecentlyLimited {
+            // `None` (never limited) sorts before any instant.
+            let oldest = candidates.iter().map(|&i| state[i].last_limited).min();
+            candidates.retain(|&i| Some(state[i].last_limited) == oldest);
+        }
+
+        // Smooth weighted round-robin (as in nginx): spreads picks evenly
+        // instead of sending runs of requests to the heaviest key.
+        let total: i64 = candidates
+            .iter()
+            .map(|&i| i64::from(self.keys[i].weight))
+            .sum();
+        for &i in &candidates {
+            state[i].current += i64::from(self.keys[i].weight);
+        }
+        let best = candidates
+            .iter()
+            .copied()
+            .max_by_key(|&i| (state[i].current, Reverse(i)))
+            .expect("candidates is never empty");
+        state[best].current -= total;
+        best
+    }
+
+    /// Record that key `index` was rate limited.
+    pub(crate) fn mark_limited(&self, index: usize) {
+        self.lock()[index].last_limited = Some(Instant::now());
+    }
+
+    /// Record that key `index` was rejected, taking it out of rotation for
+    /// [`REJECTED_BACKOFF`].
+    pub(crate) fn mark_rejected(&self, index: usize) {
+        self.lock()[index].rejected_until = Some(Instant::now() + REJECTED_BACKOFF);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<KeyState>> {
+        // The state is only counters and timestamps; a poisoned lock is harmless.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl From<String> for KeyPool {
+    fn from(key: String) -> Self {
+        Self::single(key)
+    }
+}
+
+/// Redact a key down to its last four characters, e.g. `…a1B2`.
+fn redact(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "…".into();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("…{tail}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(weights: &[u32], selection: KeySelection) -> KeyPool {
+        KeyPool::new(
+            weights
+                .iter()
+                .enumerate()
+                .map(|(i, &w)| (format!("xai-key-number-{i}"), w)),
+            selection,
+        )
+    }
+
+    #[test]
+    fn round_robin_respects_weights() {
+        let pool = pool(&[1, 2], KeySelection::RoundRobin);
+        let picks: Vec<usize> = (0..6).map(|_| pool.pick(&[])).collect();
+        assert_eq!(picks.iter().filter(|&&i| i == 0).count(), 2);
+        assert_eq!(picks.iter().filter(|&&i| i == 1).count(), 4);
+        // Smooth WRR interleaves rather than bunching.
+        assert_eq!(picks, [1, 0, 1, 1, 0, 1]);
+    }
+
+    #[test]
+    fn pick_skips_tried_keys_until_exhausted() {
+        let pool = pool(&[1, 1, 1], KeySelection::RoundRobin);
+        let first = pool.pick(&[]);
+        let second = pool.pick(&[first]);
+        assert_ne!(first, second);
+        let third = pool.pick(&[first, second]);
+        assert!(third != first && third != second);
+        // With everything tried, any key may be reused.
+        assert!(pool.pick(&[0, 1, 2]) < 3);
+    }
+
+    #[test]
+    fn least_recently_limited_avoids_limited_keys() {
+        let pool = pool(&[1, 1, 1], KeySelection::LeastRecentlyLimited);
+        pool.mark_limited(0);
+        for _ in 0..4 {
+            assert_ne!(pool.pick(&[]), 0);
+        }
+        pool.mark_limited(1);
+        pool.mark_limited(2);
+        // All limited: key 0 was limited longest ago.
+        assert_eq!(pool.pick(&[]), 0);
+    }
+
+    #[test]
+    fn rejected_keys_leave_rotation() {
+        for selection in [KeySelection::RoundRobin, KeySelection::LeastRecentlyLimited] {
+            let pool = pool(&[1, 1, 1], selection);
+            pool.mark_rejected(0);
+            pool.mark_limited(1);
+            for _ in 0..6 {
+                assert_ne!(pool.pick(&[]), 0);
+            }
+            // A rejected key is still used once nothing else is left.
+            assert_eq!(pool.pick(&[1, 2]), 0);
+        }
+    }
+
+    #[test]
+    fn labels_are_redacted() {
+        let pool = KeyPool::single("xai-supersecretvalue1234".into());
+        assert_eq!(pool.label(0), "…1234");
+        assert_eq!(redact("short"), "…");
+    }
+}
@@ -1,4 +1,13 @@
 pub mod api;
+pub mod cassette;
 pub mod config;
+pub mod files;
+pub mod index;
+pub mod keypool;
 pub mod params;
+pub mod ratelimit;
+pub mod schema;
 pub mod server;
+pub mod session;
+pub mod tokens;
+pub mod vectors;
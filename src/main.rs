@@ -1,15 +1,12 @@
-mod api;
-mod config;
-mod params;
-mod server;
-
 use anyhow::Result;
 use rmcp::{ServiceExt, transport::stdio};
+use std::time::Duration;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
-use api::XaiClient;
-use server::GrokServer;
+use grok_chat::api::XaiClient;
+use grok_chat::config;
+use grok_chat::server::GrokServer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -21,8 +18,20 @@ async fn main() -> Result<()> {
 
     info!("loading config");
     let cfg = config::load()?;
-    let client = XaiClient::new(cfg.api_key);
-    let server = GrokServer::new(client);
+    let mut client = XaiClient::new(cfg.key_pool())
+        .with_http_options(&cfg.http.options()?)?
+        .with_timeout(Duration::from_secs(cfg.timeout_secs))
+        .with_retry_policy((&cfg.retry).into())
+        .with_rate_limiter((&cfg.rate_limit).into());
+    if let Some(cassette) = &cfg.cassette {
+        info!(mode = ?cassette.mode, path = %cassette.path.display(), "using cassette");
+        client = client.with_cassette(cassette.open()?);
+    }
+    let server = GrokServer::new(client)
+        .with_file_access(cfg.files.access()?)
+        .with_context_lengths(cfg.context_lengths.clone())
+        .with_sessions((&cfg.sessions).into())
+        .with_index((&cfg.index).into());
 
     info!("starting MCP server via stdio");
     let service = server.serve(stdio()).await?;
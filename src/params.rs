@@ -38,10 +38,107 @@ impl ImageDetail {
     }
 }
 
+/// Output format for the `generate_image` tool.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageResponseFormat {
+    /// Return hosted image URLs.
+    Url,
+    /// Return the image data inline (default).
+    #[default]
+    B64Json,
+}
+
+impl ImageResponseFormat {
+    /// Return the string representation used by the xAI API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Url => "url",
+            Self::B64Json => "b64_json",
+        }
+    }
+}
+
+/// How the `embedding` tool returns vectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingOutput {
+    /// Dimensions and the first few values of each vector (default).
+    #[default]
+    Preview,
+    /// Full vectors as JSON arrays.
+    Json,
+    /// Full vectors as base64-packed little-endian f32s.
+    Base64,
+    /// A 2-D float32 NumPy file at `output_path`.
+    Npy,
+    /// One JSON object per line at `output_path`.
+    Jsonl,
+}
+
+/// Wire encoding requested from the embeddings endpoint.
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    /// JSON float arrays.
+    Float,
+    /// Base64-packed little-endian f32s, smaller on the wire.
+    Base64,
+}
+
+impl EncodingFormat {
+    /// Return the string representation used by the xAI API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Float => "float",
+            Self::Base64 => "base64",
+        }
+    }
+}
+
+/// Shape of the final summary from the `summarize` tool.
+#[derive(Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryStyle {
+    /// Flowing prose (default).
+    #[default]
+    Paragraph,
+    /// A bulleted list of key points.
+    Bullets,
+    /// A headline sentence followed by a short paragraph.
+    Tldr,
+}
+
+/// Target length of the final summary from the `summarize` tool.
+#[derive(Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryLength {
+    /// About 100 words.
+    Short,
+    /// About 300 words (default).
+    #[default]
+    Medium,
+    /// About 800 words.
+    Long,
+}
+
+impl SummaryLength {
+    /// Ceiling on the tokens generated for the final summary, with headroom
+    /// over the target word count.
+    pub fn max_tokens(&self) -> u32 {
+        match self {
+            Self::Short => 300,
+            Self::Medium => 800,
+            Self::Long => 2000,
+        }
+    }
+}
+
 /// Parameters for the `chat` tool.
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct ChatParams {
-    #[schemars(description = "The user message / prompt to send to Grok")]
+    #[schemars(description = "The user message / prompt to send to Grok. \
+                        May be empty when 'messages' ends with tool results.")]
     pub prompt: String,
 
     #[schemars(description = "Optional system prompt to set context/behaviour")]
@@ -49,7 +146,9 @@ pub struct ChatParams {
 
     #[schemars(
         description = "Full conversation history as JSON array of {role, content} objects. \
-                        When provided, 'prompt' is appended as the final user message."
+                        When provided, 'prompt' is appended as the final user message. \
+                        Assistant messages may carry tool_calls; answer each with a \
+                        {role: \"tool\", tool_call_id, content} message."
     )]
     pub messages: Option<String>,
 
@@ -80,6 +179,46 @@ pub struct ChatParams {
                         \"xhigh\" is multi-agent only and will error on other models."
     )]
     pub reasoning_effort: Option<String>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+
+    #[schemars(
+        description = "Count the prompt's tokens first and fail fast, without calling the model, \
+                        if prompt plus max_tokens would exceed the model's context length"
+    )]
+    pub preflight: Option<bool>,
+
+    #[schemars(
+        description = "Continue from an earlier response (its response_id) so the model keeps \
+                        that conversation's context server-side. Multi-agent models only."
+    )]
+    pub previous_response_id: Option<String>,
+
+    #[schemars(
+        description = "Whether xAI stores the response so it can be continued or retrieved \
+                        later (default: true). Multi-agent models only."
+    )]
+    pub store: Option<bool>,
+
+    #[schemars(
+        description = "Continue a server-side session created with create_session: its history \
+                        is sent before 'prompt', and the prompt and reply are added to it. \
+                        Cannot be combined with 'messages'."
+    )]
+    pub session_id: Option<String>,
+
+    #[schemars(description = "Functions the model may call, as a JSON array of \
+                        {type: \"function\", function: {name, description, parameters}} objects \
+                        (or bare {name, description, parameters}). Calls are returned in \
+                        structuredContent.tool_calls for you to execute.")]
+    pub tools: Option<String>,
+
+    #[schemars(
+        description = "\"auto\" (default), \"none\", \"required\", or the name of the one function \
+                        the model must call"
+    )]
+    pub tool_choice: Option<String>,
 }
 
 /// Parameters for the `chat_with_vision` tool.
@@ -88,10 +227,21 @@ pub struct VisionParams {
     #[schemars(description = "Text prompt describing what to analyse in the image")]
     pub prompt: String,
 
-    #[schemars(description = "URL of the image to analyse (must be http:// or https://)")]
-    pub image_url: String,
+    #[schemars(
+        description = "Image to analyse: an http(s):// URL, a base64 data: URI, or an absolute \
+                        path to a JPEG/PNG file inside the server's allowed directories. \
+                        Optional when 'images' is given."
+    )]
+    pub image_url: Option<String>,
 
-    #[schemars(description = "Image detail level: \"low\" or \"high\" (default: \"high\")")]
+    #[schemars(description = "Further images, sent in order after image_url, \
+                        e.g. before/after screenshots or the pages of a scanned document")]
+    pub images: Option<Vec<VisionImage>>,
+
+    #[schemars(
+        description = "Image detail level: \"low\" or \"high\" (default: \"high\"). \
+                        Applies to every image without its own detail."
+    )]
     pub detail: Option<ImageDetail>,
 
     #[schemars(
@@ -105,6 +255,19 @@ pub struct VisionParams {
 
     #[schemars(description = "Maximum tokens to generate")]
     pub max_tokens: Option<u32>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// One image in a multi-image `chat_with_vision` request.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct VisionImage {
+    #[schemars(description = "http(s):// URL, base64 data: URI, or absolute local file path")]
+    pub url: String,
+
+    #[schemars(description = "Detail level for this image, overriding the request's 'detail'")]
+    pub detail: Option<ImageDetail>,
 }
 
 /// Parameters for the `chat_with_search` tool.
@@ -142,6 +305,21 @@ pub struct SearchParams {
                         \"xhigh\" is multi-agent only and will error on other models."
     )]
     pub reasoning_effort: Option<String>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+
+    #[schemars(
+        description = "Continue from an earlier response (its response_id), e.g. to ask a \
+                        follow-up question about the same search results"
+    )]
+    pub previous_response_id: Option<String>,
+
+    #[schemars(
+        description = "Whether xAI stores the response so it can be continued or retrieved \
+                        later (default: true)"
+    )]
+    pub store: Option<bool>,
 }
 
 /// Parameters for the `embedding` tool.
@@ -152,4 +330,331 @@ pub struct EmbeddingParams {
 
     #[schemars(description = "Embedding model to use (default: grok-2-text-embedding)")]
     pub model: Option<String>,
+
+    #[schemars(
+        description = "\"preview\" (default) shows dimensions and the first values; \"json\" returns \
+                        full vectors in structuredContent; \"base64\" returns them as base64 \
+                        little-endian f32; \"npy\" and \"jsonl\" write them to output_path"
+    )]
+    pub output: Option<EmbeddingOutput>,
+
+    #[schemars(
+        description = "Absolute path of the .npy or .jsonl file to write, inside the server's \
+                        allowed directories. Required for the npy and jsonl outputs."
+    )]
+    pub output_path: Option<String>,
+
+    #[schemars(
+        description = "Wire encoding requested from xAI: \"float\" or \"base64\". \
+                        Does not change the output format."
+    )]
+    pub encoding_format: Option<EncodingFormat>,
+
+    #[schemars(
+        description = "Truncate vectors to this many dimensions, on models that support it"
+    )]
+    pub dimensions: Option<u32>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `similarity` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SimilarityParams {
+    #[schemars(description = "Text to compare the candidates against")]
+    pub query: String,
+
+    #[schemars(description = "Texts to rank by similarity to the query")]
+    pub candidates: Vec<String>,
+
+    #[schemars(description = "Return only the best this many candidates (default: all)")]
+    pub top_k: Option<usize>,
+
+    #[schemars(description = "Embedding model to use (default: grok-2-text-embedding)")]
+    pub model: Option<String>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `generate_image` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ImageGenerationParams {
+    #[schemars(description = "Description of the image to generate")]
+    pub prompt: String,
+
+    #[schemars(description = "Image model to use (default: grok-2-image)")]
+    pub model: Option<String>,
+
+    #[schemars(description = "Number of images to generate, 1-10 (default: 1)")]
+    pub n: Option<u32>,
+
+    #[schemars(
+        description = "\"b64_json\" returns the images inline (default); \"url\" returns hosted image URLs"
+    )]
+    pub response_format: Option<ImageResponseFormat>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `count_tokens` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CountTokensParams {
+    #[schemars(description = "Text to tokenize")]
+    pub text: String,
+
+    #[schemars(description = "Model whose tokenizer to use (default: grok-4.3)")]
+    pub model: Option<String>,
+
+    #[schemars(description = "Also list the individual tokens (default: false)")]
+    pub include_tokens: Option<bool>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `chat_deferred_result` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeferredResultParams {
+    #[schemars(description = "Request id returned by chat_deferred_submit. \
+                        Omit to list the requests this server is still waiting on.")]
+    pub request_id: Option<String>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `get_response` and `delete_response` tools.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ResponseIdParams {
+    #[schemars(description = "The response_id of a stored response")]
+    pub response_id: String,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `create_session` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CreateSessionParams {
+    #[schemars(
+        description = "Id for the new session (letters, digits, '-', '_', '.'). \
+                        Generated when omitted."
+    )]
+    pub session_id: Option<String>,
+
+    #[schemars(description = "System prompt used for every turn of the session")]
+    pub system_prompt: Option<String>,
+}
+
+/// Parameters for the `get_session`, `reset_session` and `delete_session` tools.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SessionIdParams {
+    #[schemars(description = "The session's id")]
+    pub session_id: String,
+}
+
+/// Parameters for the `index_documents` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct IndexDocumentsParams {
+    #[schemars(
+        description = "Collection to add the documents to (letters, digits, '-', '_'). \
+                        Created on first use."
+    )]
+    pub collection: String,
+
+    #[schemars(description = "Documents to chunk, embed and store")]
+    pub documents: Vec<IndexDocument>,
+
+    #[schemars(description = "Embedding model (default: the collection's model, or \
+                        grok-2-text-embedding for a new collection)")]
+    pub model: Option<String>,
+
+    #[schemars(description = "Maximum characters per chunk (default: 2000)")]
+    pub chunk_chars: Option<usize>,
+
+    #[schemars(description = "Characters repeated between neighbouring chunks (default: 200)")]
+    pub chunk_overlap: Option<usize>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// One document in an `index_documents` request: inline text or a local file.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct IndexDocument {
+    #[schemars(
+        description = "Document id, unique within the collection. Indexing an existing id \
+                        replaces it. Defaults to 'path'."
+    )]
+    pub id: Option<String>,
+
+    #[schemars(description = "Text to index. Give either this or 'path'.")]
+    pub text: Option<String>,
+
+    #[schemars(
+        description = "Absolute path of a UTF-8 text file inside the server's allowed \
+                        directories. Give either this or 'text'."
+    )]
+    pub path: Option<String>,
+
+    #[schemars(description = "Fields returned with every search hit from this document")]
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// Parameters for the `search_index` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SearchIndexParams {
+    #[schemars(description = "Collection to search")]
+    pub collection: String,
+
+    #[schemars(description = "Text to find related chunks for")]
+    pub query: String,
+
+    #[schemars(description = "Number of chunks to return (default: 5)")]
+    pub top_k: Option<usize>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `delete_documents` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeleteDocumentsParams {
+    #[schemars(description = "Collection to delete from")]
+    pub collection: String,
+
+    #[schemars(description = "Ids of the documents to remove")]
+    pub document_ids: Vec<String>,
+}
+
+/// Parameters for the `delete_collection` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CollectionParams {
+    #[schemars(description = "The collection's name")]
+    pub collection: String,
+}
+
+/// Parameters for the `chat_with_documents` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ChatWithDocumentsParams {
+    #[schemars(description = "The question to answer from the collection")]
+    pub prompt: String,
+
+    #[schemars(description = "Collection to retrieve excerpts from")]
+    pub collection: String,
+
+    #[schemars(description = "Optional system prompt, sent ahead of the retrieved excerpts")]
+    pub system_prompt: Option<String>,
+
+    #[schemars(description = "Chunks to retrieve before applying the token budget (default: 8)")]
+    pub top_k: Option<usize>,
+
+    #[schemars(
+        description = "Estimated token budget for retrieved excerpts (default: 4000). \
+                        Lower-ranked chunks that do not fit are left out."
+    )]
+    pub context_tokens: Option<u32>,
+
+    #[schemars(
+        description = "Model ID. Defaults to grok-4.3. \
+                        Call the list_models tool for the current set of available models."
+    )]
+    pub model: Option<String>,
+
+    #[schemars(description = "Sampling temperature (0.0 - 2.0)")]
+    pub temperature: Option<f32>,
+
+    #[schemars(description = "Maximum tokens to generate")]
+    pub max_tokens: Option<u32>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Parameters for the `summarize` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SummarizeParams {
+    #[schemars(description = "Text to summarise. Give either this or 'path'.")]
+    pub text: Option<String>,
+
+    #[schemars(
+        description = "Absolute path of a UTF-8 text file inside the server's allowed \
+                        directories. Give either this or 'text'."
+    )]
+    pub path: Option<String>,
+
+    #[schemars(
+        description = "\"paragraph\" (default), \"bullets\" or \"tldr\" (a headline sentence \
+                        and a short paragraph)"
+    )]
+    pub style: Option<SummaryStyle>,
+
+    #[schemars(description = "\"short\", \"medium\" (default) or \"long\"")]
+    pub length: Option<SummaryLength>,
+
+    #[schemars(
+        description = "What the summary should focus on, e.g. \"decisions and open risks\""
+    )]
+    pub focus: Option<String>,
+
+    #[schemars(
+        description = "Most tokens of document text per request (default: 8000). Longer \
+                        documents are summarised in parts of this size."
+    )]
+    pub chunk_tokens: Option<u32>,
+
+    #[schemars(
+        description = "Model ID. Defaults to grok-4.3. \
+                        Call the list_models tool for the current set of available models."
+    )]
+    pub model: Option<String>,
+
+    #[schemars(description = "Sampling temperature (0.0 - 2.0)")]
+    pub temperature: Option<f32>,
+
+    #[schemars(description = "Request timeout in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
+
+    #[schemars(
+        description = "Count each request's tokens first and fail it, without calling the \
+                        model, if it would exceed the model's context length"
+    )]
+    pub preflight: Option<bool>,
+}
+
+/// Parameters for the `chat_batch` tool.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ChatBatchParams {
+    #[schemars(description = "Prompts to send, each as its own single-turn chat completion")]
+    pub prompts: Vec<String>,
+
+    #[schemars(description = "Optional system prompt shared by every prompt")]
+    pub system_prompt: Option<String>,
+
+    #[schemars(
+        description = "Model ID. Defaults to grok-4.3. \
+                        Call the list_models tool for the current set of available models."
+    )]
+    pub model: Option<String>,
+
+    #[schemars(
+        description = "Optional JSON schema string every response must conform to. \
+                        Each item's output is validated separately."
+    )]
+    pub response_schema: Option<String>,
+
+    #[schemars(description = "Sampling temperature (0.0 - 2.0)")]
+    pub temperature: Option<f32>,
+
+    #[schemars(description = "Maximum tokens to generate per prompt")]
+    pub max_tokens: Option<u32>,
+
+    #[schemars(description = "Requests in flight at once (default: 4, at most 16)")]
+    pub concurrency: Option<usize>,
+
+    #[schemars(description = "Timeout per request in seconds, overriding the server default")]
+    pub timeout_secs: Option<u64>,
 }
@@ -0,0 +1,340 @@
+use reqwest::header::HeaderMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::tokens;
+
+const LIMIT_REQUESTS: &str = "x-ratelimit-limit-requests";
+const LIMIT_TOKENS: &str = "x-ratelimit-limit-tokens";
+const REMAINING_REQUESTS: &str = "x-ratelimit-remaining-requests";
+const REMAINING_TOKENS: &str = "x-ratelimit-remaining-tokens";
+
+/// A token bucket holding up to one minute's allowance, refilled continuously.
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn per_minute(limit: u32, now: Instant) -> Self {
+        Self {
+            capacity: f64::from(limit),
+            available: f64::from(limit),
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.available =
+            (self.available + elapsed.as_secs_f64() * self.capacity / 60.0).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Time until `amount` is available. Requests larger than the whole bucket
+    /// are capped at its capacity so they wait for a full bucket rather than forever.
+    fn wait_for(&self, amount: f64) -> Duration {
+        let amount = amount.min(self.capacity);
+        if self.available >= amount {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((amount - self.available) * 60.0 / self.capacity)
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.capacity);
+    }
+
+    /// Never believe we have more allowance left than the server says we do.
+    fn clamp(&mut self, remaining: f64) {
+        self.available = self.available.min(remaining.max(0.0));
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+impl Buckets {
+    fn refill(&mut self, now: Instant) {
+        for bucket in [&mut self.requests, &mut self.tokens].into_iter().flatten() {
+            bucket.refill(now);
+        }
+    }
+
+    fn wait_for(&self, tokens: f64) -> Duration {
+        [
+            self.requests.as_ref().map(|b| b.wait_for(1.0)),
+            self.tokens.as_ref().map(|b| b.wait_for(tokens)),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or(Duration::ZERO)
+    }
+
+    fn take(&mut self, tokens: f64) {
+        if let Some(b) = self.requests.as_mut() {
+            b.take(1.0);
+        }
+        if let Some(b) = self.tokens.as_mut() {
+            b.take(tokens);
+        }
+    }
+}
+
+/// The configured buckets, shared by every key, plus one set per key built
+/// from what xAI reports for it.
+#[derive(Debug, Default)]
+struct Limits {
+    shared: Buckets,
+    keys: Vec<Buckets>,
+}
+
+impl Limits {
+    fn key(&mut self, key: usize) -> &mut Buckets {
+        if self.keys.len() <= key {
+            self.keys.resize_with(key + 1, Buckets::default);
+        }
+        &mut self.keys[key]
+    }
+}
+
+/// Client-side limiter for requests per minute and estimated tokens per minute.
+///
+/// Shared by every request made through one [`XaiClient`](crate::api::XaiClient),
+/// so concurrent tool calls queue locally instead of tripping HTTP 429s. The
+/// configured limits apply across all keys. xAI's `x-ratelimit-*` headers
+/// describe only the key that served the response, so they feed a separate
+/// set of buckets per key (indexed as in the [`KeyPool`](crate::keypool::KeyPool)):
+/// each adopts the reported `x-ratelimit-limit-requests` /
+/// `x-ratelimit-limit-tokens` (or the configured limit when none is reported)
+/// and is lowered to the reported `x-ratelimit-remaining-requests` /
+/// `x-ratelimit-remaining-tokens`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_minute: Option<u32>,
+    tokens_per_minute: Option<u32>,
+    limits: Mutex<Limits>,
+}
+
+impl RateLimiter {
+    /// Create a limiter; `None` leaves that dimension unlimited.
+    pub fn new(requests_per_minute: Option<u32>, tokens_per_minute: Option<u32>) -> Self {
+        let now = Instant::now();
+        Self {
+            requests_per_minute,
+            tokens_per_minute,
+            limits: Mutex::new(Limits {
+                shared: Buckets {
+                    requests: requests_per_minute.map(|l| Bucket::per_minute(l, now)),
+                    tokens: tokens_per_minute.map(|l| Bucket::per_minute(l, now)),
+                },
+                keys: Vec::new(),
+            }),
+        }
+    }
+
+    /// A limiter with no configured limits. It only waits once xAI reports
+    /// its own through [`observe`](Self::observe).
+    pub fn unlimited() -> Self {
+        Self::new(None, None)
+    }
+
+    /// Whether a token-per-minute limit is in force, i.e. whether callers
+    /// need to bother estimating request size.
+    pub fn limits_tokens(&self) -> bool {
+        self.tokens_per_minute.is_some() || self.lock().keys.iter().any(|b| b.tokens.is_some())
+    }
+
+    /// Estimate the token cost of a request from its serialized body length.
+    pub fn estimate_tokens(body_len: usize) -> u32 {
+        tokens::estimate_len(body_len)
+    }
+
+    /// Wait until one request costing `tokens` fits in the shared buckets and
+    /// those of `key`, then deduct it.
+    pub async fn acquire(&self, key: usize, tokens: u32) {
+        loop {
+            let wait = self.try_acquire(key, f64::from(tokens), Instant::now());
+            if wait.is_zero() {
+                return;
+            }
+            tracing::debug!(?wait, "rate limiter: waiting for capacity");
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Deduct the request if it fits now, else return how long to wait.
+    fn try_acquire(&self, key: usize, tokens: f64, now: Instant) -> Duration {
+        let mut limits = self.lock();
+        limits.shared.refill(now);
+        limits.key(key).refill(now);
+
+        let wait = limits
+            .shared
+            .wait_for(tokens)
+            .max(limits.key(key).wait_for(tokens));
+        if wait.is_zero() {
+            limits.shared.take(tokens);
+            limits.key(key).take(tokens);
+        }
+        wait
+    }
+
+    /// Adapt the buckets of `key` to the `x-ratelimit-*` headers on a response
+    /// it served: adopt the reported limit, then clamp to the reported
+    /// remaining allowance.
+    pub fn observe(&self, key: usize, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name)?.to_str().ok().map(str::trim);
+        let remaining = |name: &str| header(name)?.parse::<f64>().ok();
+        let limit = |name: &str| header(name)?.parse::<u32>().ok().filter(|&l| l > 0);
+        let requests = remaining(REMAINING_REQUESTS);
+        let tokens = remaining(REMAINING_TOKENS);
+        let request_limit = limit(LIMIT_REQUESTS);
+        let token_limit = limit(LIMIT_TOKENS);
+        if requests.is_none()
+            && tokens.is_none()
+            && request_limit.is_none()
+            && token_limit.is_none()
+        {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut limits = self.lock();
+        let buckets = limits.key(key);
+        buckets.refill(now);
+        // A bare `remaining` still needs a capacity to refill towards.
+        let request_limit =
+            request_limit.or(self.requests_per_minute.filter(|_| requests.is_some()));
+        let token_limit = token_limit.or(self.tokens_per_minute.filter(|_| tokens.is_some()));
+        adapt(&mut buckets.requests, request_limit, requests, now);
+        adapt(&mut buckets.tokens, token_limit, tokens, now);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Limits> {
+        // The buckets hold no invariants a panic could break; keep going.
+        self.limits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Create a per-key bucket on first sight of a limit, then clamp it to the
+/// reported remaining allowance.
+fn adapt(bucket: &mut Option<Bucket>, limit: Option<u32>, remaining: Option<f64>, now: Instant) {
+    if let (None, Some(l)) = (&bucket, limit) {
+        *bucket = Some(Bucket::per_minute(l, now));
+    }
+    if let (Some(b), Some(r)) = (bucket.as_mut(), remaining) {
+        b.clamp(r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_never_waits() {
+        let limiter = RateLimiter::unlimited();
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert_eq!(limiter.try_acquire(0, 1e9, now), Duration::ZERO);
+        }
+        assert!(!limiter.limits_tokens());
+    }
+
+    #[test]
+    fn requests_bucket_allows_burst_then_waits() {
+        let limiter = RateLimiter::new(Some(60), None);
+        let now = Instant::now();
+        for _ in 0..60 {
+            assert_eq!(limiter.try_acquire(0, 0.0, now), Duration::ZERO);
+        }
+        // One request per second refill rate.
+        assert_eq!(limiter.try_acquire(0, 0.0, now), Duration::from_secs(1));
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.try_acquire(0, 0.0, later), Duration::ZERO);
+    }
+
+    #[test]
+    fn tokens_bucket_waits_for_deficit() {
+        let limiter = RateLimiter::new(None, Some(600));
+        let now = Instant::now();
+        assert!(limiter.limits_tokens());
+        assert_eq!(limiter.try_acquire(0, 500.0, now), Duration::ZERO);
+        // 100 left, need 300: 200 tokens at 10/sec.
+        assert_eq!(limiter.try_acquire(0, 300.0, now), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn oversized_request_waits_for_full_bucket_only() {
+        let limiter = RateLimiter::new(None, Some(600));
+        let now = Instant::now();
+        assert_eq!(limiter.try_acquire(0, 10_000.0, now), Duration::ZERO);
+        assert_eq!(
+            limiter.try_acquire(0, 10_000.0, now),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn observe_clamps_to_server_remaining() {
+        let limiter = RateLimiter::new(Some(600), Some(6000));
+        let mut headers = HeaderMap::new();
+        headers.insert(REMAINING_REQUESTS, "0".parse().unwrap());
+        headers.insert(REMAINING_TOKENS, "garbage".parse().unwrap());
+        limiter.observe(0, &headers);
+
+        let mut limits = limiter.lock();
+        let buckets = limits.key(0);
+        assert!(buckets.requests.as_ref().unwrap().available < 1.0);
+        assert!(buckets.tokens.is_none());
+        assert!(limits.shared.tokens.as_ref().unwrap().available > 5000.0);
+    }
+
+    #[test]
+    fn observe_adopts_server_limits() {
+        let limiter = RateLimiter::new(Some(600), None);
+        let mut headers = HeaderMap::new();
+        headers.insert(LIMIT_REQUESTS, "60".parse().unwrap());
+        headers.insert(LIMIT_TOKENS, "1200".parse().unwrap());
+        headers.insert(REMAINING_TOKENS, "100".parse().unwrap());
+        limiter.observe(0, &headers);
+
+        assert!(limiter.limits_tokens());
+        let mut limits = limiter.lock();
+        // The configured limit still applies across keys.
+        assert_eq!(limits.shared.requests.as_ref().unwrap().capacity, 600.0);
+        let buckets = limits.key(0);
+        assert_eq!(buckets.requests.as_ref().unwrap().capacity, 60.0);
+        let tokens = buckets.tokens.as_ref().unwrap();
+        assert_eq!(tokens.capacity, 1200.0);
+        assert!(tokens.available <= 100.0);
+    }
+
+    #[test]
+    fn observe_only_limits_the_serving_key() {
+        let limiter = RateLimiter::unlimited();
+        let mut headers = HeaderMap::new();
+        headers.insert(LIMIT_REQUESTS, "60".parse().unwrap());
+        headers.insert(REMAINING_REQUESTS, "0".parse().unwrap());
+        let now = Instant::now();
+        limiter.observe(0, &headers);
+
+        assert_eq!(limiter.try_acquire(1, 0.0, now), Duration::ZERO);
+        assert_eq!(limiter.try_acquire(0, 0.0, now), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn estimate_tokens_rounds_up() {
+        assert_eq!(RateLimiter::estimate_tokens(0), 0);
+        assert_eq!(RateLimiter::estimate_tokens(1), 1);
+        assert_eq!(RateLimiter::estimate_tokens(8), 2);
+        assert_eq!(RateLimiter::estimate_tokens(9), 3);
+    }
+}
@@ -0,0 +1,405 @@
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// How deep `$ref`s and nested schemas are followed before giving up, so a
+/// self-referencing schema cannot recurse forever.
+const MAX_DEPTH: usize = 64;
+
+/// One way in which a value fails a schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    /// Location in the value, e.g. `$.items[2].name`.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Check `value` against a JSON Schema, returning every violation found.
+///
+/// Covers the keywords structured output schemas use: `type`, `enum`,
+/// `const`, `properties`, `required`, `additionalProperties`, `items`,
+/// `anyOf` / `oneOf` / `allOf`, length, size and numeric bounds, and local
+/// `$ref`s into `$defs` or `definitions`. Other keywords (e.g. `pattern`,
+/// `format`) are not checked.
+pub fn validate(schema: &Value, value: &Value) -> Vec<SchemaError> {
+    let mut validator = Validator {
+        root: schema,
+        errors: Vec::new(),
+    };
+    validator.check(schema, value, "$".into(), 0);
+    validator.errors
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    errors: Vec<SchemaError>,
+}
+
+impl<'a> Validator<'a> {
+    fn fail(&mut self, path: &str, message: impl Into<String>) {
+        self.errors.push(SchemaError {
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+
+    fn check(&mut self, schema: &'a Value, value: &Value, path: String, depth: usize) {
+        if depth > MAX_DEPTH {
+            return self.fail(&path, "schema nests too deeply");
+        }
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return self.fail(&path, "no value is allowed here"),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                Some(target) => self.check(target, value, path.clone(), depth + 1),
+                None => self.fail(&path, format!("cannot resolve $ref \"{reference}\"")),
+            }
+        }
+
+        if let Some(expected) = schema.get("type")
+            && !type_matches(expected, value)
+        {
+            return self.fail(
+                &path,
+                format!("expected {}, got {}", type_list(expected), type_name(value)),
+            );
+        }
+        if let Some(options) = schema.get("enum").and_then(Value::as_array)
+            && !options.contains(value)
+        {
+            self.fail(
+                &path,
+                format!("must be one of {}", Value::from(options.clone())),
+            );
+        }
+        if let Some(expected) = schema.get("const")
+            && expected != value
+        {
+            self.fail(&path, format!("must be {expected}"));
+        }
+
+        self.check_combinators(schema, value, &path, depth);
+        match value {
+            Value::Object(object) => self.check_object(schema, object, &path, depth),
+            Value::Array(items) => self.check_array(schema, items, &path, depth),
+            Value::String(text) => self.check_string(schema, text, &path),
+            Value::Number(_) => self.check_number(schema, value, &path),
+            _ => {}
+        }
+    }
+
+    fn check_combinators(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        value: &Value,
+        path: &str,
+        depth: usize,
+    ) {
+        if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+            for sub in all {
+                self.check(sub, value, path.into(), depth + 1);
+            }
+        }
+        let root = self.root;
+        let passing = |subs: &'a [Value]| {
+            subs.iter()
+                .filter(|sub| {
+                    let mut inner = Validator {
+                        root,
+                        errors: Vec::new(),
+                    };
+                    inner.check(sub, value, path.into(), depth + 1);
+                    inner.errors.is_empty()
+                })
+                .count()
+        };
+        if let Some(any) = schema.get("anyOf").and_then(Value::as_array)
+            && passing(any) == 0
+        {
+            self.fail(path, "does not match any of the anyOf schemas");
+        }
+        if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+            let matched = passing(one);
+            if matched != 1 {
+                self.fail(
+                    path,
+                    format!("must match exactly one oneOf schema, matched {matched}"),
+                );
+            }
+        }
+    }
+
+    fn check_object(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        object: &Map<String, Value>,
+        path: &str,
+        depth: usize,
+    ) {
+        for name in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(name) {
+                self.fail(path, format!("missing required property \"{name}\""));
+            }
+        }
+
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+        for (name, item) in object {
+            let item_path = format!("{path}.{name}");
+            match (properties.and_then(|p| p.get(name)), additional) {
+                (Some(sub), _) | (None, Some(sub @ Value::Object(_))) => {
+                    self.check(sub, item, item_path, depth + 1)
+                }
+                (None, Some(Value::Bool(false))) => {
+                    self.fail(path, format!("unexpected property \"{name}\""))
+                }
+                (None, _) => {}
+            }
+        }
+
+        let count = object.len() as u64;
+        if let Some(min) = schema.get("minProperties").and_then(Value::as_u64)
+            && count < min
+        {
+            self.fail(path, format!("must have at least {min} properties"));
+        }
+        if let Some(max) = schema.get("maxProperties").and_then(Value::as_u64)
+            && count > max
+        {
+            self.fail(path, format!("must have at most {max} properties"));
+        }
+    }
+
+    fn check_array(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        items: &[Value],
+        path: &str,
+        depth: usize,
+    ) {
+        if let Some(sub) = schema.get("items") {
+            for (i, item) in items.iter().enumerate() {
+                self.check(sub, item, format!("{path}[{i}]"), depth + 1);
+            }
+        }
+
+        let count = items.len() as u64;
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+            && count < min
+        {
+            self.fail(path, format!("must have at least {min} items"));
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+            && count > max
+        {
+            self.fail(path, format!("must have at most {max} items"));
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true))
+            && items
+                .iter()
+                .enumerate()
+                .any(|(i, item)| items[..i].contains(item))
+        {
+            self.fail(path, "items must be unique");
+        }
+    }
+
+    fn check_string(&mut self, schema: &Map<String, Value>, text: &str, path: &str) {
+        let len = text.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64)
+            && len < min
+        {
+            self.fail(path, format!("must be at least {min} characters"));
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64)
+            && len > max
+        {
+            self.fail(path, format!("must be at most {max} characters"));
+        }
+    }
+
+    fn check_number(&mut self, schema: &Map<String, Value>, value: &Value, path: &str) {
+        let Some(n) = value.as_f64() else {
+            return;
+        };
+        let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+        if let Some(min) = bound("minimum")
+            && n < min
+        {
+            self.fail(path, format!("must be >= {min}"));
+        }
+        if let Some(max) = bound("maximum")
+            && n > max
+        {
+            self.fail(path, format!("must be <= {max}"));
+        }
+        if let Some(min) = bound("exclusiveMinimum")
+            && n <= min
+        {
+            self.fail(path, format!("must be > {min}"));
+        }
+        if let Some(max) = bound("exclusiveMaximum")
+            && n >= max
+        {
+            self.fail(path, format!("must be < {max}"));
+        }
+    }
+
+    /// Resolve a local reference such as `#/$defs/item`.
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer)
+    }
+}
+
+fn type_matches(expected: &Value, value: &Value) -> bool {
+    match expected {
+        Value::String(name) => is_type(name, value),
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| is_type(name, value)),
+        _ => true,
+    }
+}
+
+fn is_type(name: &str, value: &Value) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        _ => true,
+    }
+}
+
+fn type_list(expected: &Value) -> String {
+    match expected {
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(" or "),
+        other => other.as_str().unwrap_or("?").into(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn errors(schema: Value, value: Value) -> Vec<String> {
+        validate(&schema, &value)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn accepts_conforming_output() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "tags": {"type": "array", "items": {"type": "string"}},
+                "score": {"type": "integer", "minimum": 0, "maximum": 10}
+            },
+            "required": ["name", "score"],
+            "additionalProperties": false
+        });
+        let value = json!({"name": "x", "tags": ["a"], "score": 7});
+        assert!(errors(schema, value).is_empty());
+    }
+
+    #[test]
+    fn reports_each_violation_with_its_path() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "items": {"type": "array", "items": {"$ref": "#/$defs/item"}},
+                "status": {"enum": ["ok", "failed"]}
+            },
+            "required": ["items", "status"],
+            "additionalProperties": false,
+            "$defs": {
+                "item": {
+                    "type": "object",
+                    "properties": {"qty": {"type": "integer", "minimum": 1}},
+                    "required": ["qty"]
+                }
+            }
+        });
+        let value = json!({"items": [{"qty": 2}, {"qty": 0}, {}], "extra": true});
+        assert_eq!(
+            errors(schema, value),
+            [
+                "$: missing required property \"status\"",
+                "$: unexpected property \"extra\"",
+                "$.items[1].qty: must be >= 1",
+                "$.items[2]: missing required property \"qty\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_types_and_combinators() {
+        assert_eq!(
+            errors(json!({"type": "integer"}), json!(1.5)),
+            ["$: expected integer, got number"]
+        );
+        assert!(errors(json!({"type": ["string", "null"]}), json!(null)).is_empty());
+        assert!(
+            errors(
+                json!({"anyOf": [{"type": "string"}, {"type": "number"}]}),
+                json!(true)
+            )[0]
+            .contains("anyOf")
+        );
+        assert!(
+            errors(
+                json!({"oneOf": [{"type": "number"}, {"type": "integer"}]}),
+                json!(3)
+            )[0]
+            .contains("matched 2")
+        );
+    }
+
+    #[test]
+    fn recursive_refs_terminate() {
+        let schema = json!({"$ref": "#"});
+        assert_eq!(errors(schema, json!(1)), ["$: schema nests too deeply"]);
+    }
+}
@@ -1,33 +1,218 @@
+use futures::StreamExt;
 use moka::future::Cache;
 use reqwest::Method;
 use rmcp::{
-    ErrorData as McpError, ServerHandler, handler::server::tool::ToolRouter,
-    handler::server::wrapper::Parameters, model::*, tool, tool_handler, tool_router,
+    ErrorData as McpError, Peer, RoleServer, ServerHandler, handler::server::tool::ToolRouter,
+    handler::server::wrapper::Parameters, model::*, service::RequestContext, tool, tool_handler,
+    tool_router,
 };
 use serde_json::Value;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tracing::debug;
 
 use crate::api::{
-    ChatMessage, ChatRequest, ChatResponse, EmbeddingRequest, EmbeddingResponse, ModelsResponse,
-    ReasoningConfig, ResponsesMessage, ResponsesRequest, ResponsesResponse, XaiClient,
+    ApiError, ApiErrorKind, ChatCompletionChunk, ChatMessage, ChatRequest, ChatResponse,
+    ChatStreamAccumulator, ContentPart, EmbeddingRequest, EmbeddingResponse, EmbeddingUsage,
+    ImageGenerationRequest, ImageGenerationResponse, MessageContent, ModelsResponse,
+    ReasoningConfig, RequestOptions, ResponseDeleted, ResponsesMessage, ResponsesRequest,
+    ResponsesResponse, ResponsesStreamAccumulator, ResponsesStreamEvent, TokenizeRequest,
+    TokenizeResponse, ToolChoice, ToolDefinition, Usage, XaiClient,
 };
-use crate::params::{ChatParams, EmbeddingParams, SearchParams, SearchType, VisionParams};
+use crate::files::{self, FileAccess};
+use crate::index::{self, Chunk, Document, Hit, IndexError, VectorIndex};
+use crate::params::{
+    ChatBatchParams, ChatParams, ChatWithDocumentsParams, CollectionParams, CountTokensParams,
+    CreateSessionParams, DeferredResultParams, DeleteDocumentsParams, EmbeddingOutput,
+    EmbeddingParams, ImageDetail, ImageGenerationParams, IndexDocumentsParams, ResponseIdParams,
+    SearchIndexParams, SearchParams, SearchType, SessionIdParams, SimilarityParams,
+    SummarizeParams, SummaryLength, SummaryStyle, VisionImage, VisionParams,
+};
+use crate::schema;
+use crate::session::{Session, SessionError, SessionStore};
+use crate::tokens;
+use crate::vectors;
 
 const DEFAULT_MODEL: &str = "grok-4.3";
 const DEFAULT_EMBEDDING_MODEL: &str = "grok-2-text-embedding";
+const DEFAULT_IMAGE_MODEL: &str = "grok-2-image";
+
+/// Upper bound on `n` accepted by the image generation endpoint.
+const MAX_IMAGES: u32 = 10;
+
+/// Characters of each candidate shown in the `similarity` text output.
+const SNIPPET_CHARS: usize = 80;
+
+/// Texts embedded per `/embeddings` call.
+const EMBED_BATCH_SIZE: usize = 64;
+
+/// Chunks returned by `search_index` unless `top_k` is given.
+const DEFAULT_TOP_K: usize = 5;
+
+/// Chunks `chat_with_documents` retrieves unless `top_k` is given.
+const DEFAULT_DOCUMENT_CHUNKS: usize = 8;
+
+/// Estimated tokens of retrieved excerpts `chat_with_documents` sends by default.
+const DEFAULT_CONTEXT_TOKENS: u32 = 4000;
+
+/// Estimated tokens of text per `summarize` request unless `chunk_tokens` is given.
+const DEFAULT_SUMMARY_CHUNK_TOKENS: u32 = 8000;
+
+/// Smallest accepted `chunk_tokens`, leaving room for meaningful parts.
+const MIN_SUMMARY_CHUNK_TOKENS: u32 = 200;
+
+/// Bytes of text sent per `/tokenize-text` call when splitting a document
+/// for `summarize`, keeping each request and its per-token response bounded.
+const TOKENIZE_WINDOW_BYTES: usize = 256 * 1024;
+
+/// Parts of a document `summarize` sends to xAI at once.
+const SUMMARY_CONCURRENCY: usize = 4;
+
+/// Most prompts accepted by one `chat_batch` call.
+const MAX_BATCH_PROMPTS: usize = 500;
+
+/// `chat_batch` requests in flight at once unless `concurrency` is given.
+const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+/// Upper bound on `chat_batch`'s `concurrency`.
+const MAX_BATCH_CONCURRENCY: usize = 16;
+
+/// Instructions ahead of the excerpts in `chat_with_documents`.
+const DOCUMENTS_PROMPT: &str = "Answer using the numbered excerpts below. Cite the excerpts \
+    supporting each statement inline as [n], e.g. [1] or [2][3]. If the excerpts do not \
+    contain the answer, say so instead of guessing.";
+
+/// Context windows of known models, matched by the longest ID prefix.
+/// `context_lengths` in config.toml takes precedence.
+const CONTEXT_LENGTHS: &[(&str, u32)] = &[
+    ("grok-4-fast", 2_000_000),
+    ("grok-4-1-fast", 2_000_000),
+    ("grok-4", 256_000),
+    ("grok-code-fast", 256_000),
+    ("grok-3", 131_072),
+];
+
+/// Tokens added per message by the chat template (role markers, separators).
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
 
 /// Valid roles for chat messages.
 const VALID_ROLES: &[&str] = &["system", "user", "assistant", "tool"];
 
+/// Minimum interval between progress notifications while streaming.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long xAI keeps a deferred completion's result; older ids are forgotten.
+const DEFERRED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A deferred chat completion submitted through this server and not yet collected.
+#[derive(Debug, Clone)]
+struct PendingDeferred {
+    model: String,
+    submitted: Instant,
+    /// The `response_schema` to validate the answer against, if one was given.
+    schema: Option<Value>,
+}
+
+/// Settings and progress shared by every request of one `summarize` call.
+struct SummaryJob<'a> {
+    model: &'a str,
+    style: SummaryStyle,
+    length: SummaryLength,
+    focus: Option<&'a str>,
+    temperature: Option<f32>,
+    /// Most estimated tokens of partial summaries merged in one request.
+    budget: u32,
+    /// Whether to run the token preflight before every request.
+    preflight: bool,
+    opts: &'a RequestOptions,
+    progress: Option<ProgressReporter>,
+    /// Requests finished, and expected in all; more merge rounds raise the total.
+    done: AtomicUsize,
+    total: AtomicUsize,
+}
+
+/// The outcome of a `summarize` job.
+struct Summary {
+    text: String,
+    usage: Usage,
+    /// Parts, numbered from 1, whose request failed, with the error of each.
+    failed_parts: Vec<(usize, Value)>,
+}
+
 /// The MCP server wrapping the xAI Grok API.
 #[derive(Clone)]
 pub struct GrokServer {
     client: std::sync::Arc<XaiClient>,
+    files: std::sync::Arc<FileAccess>,
+    context_lengths: std::sync::Arc<HashMap<String, u32>>,
     models_cache: Cache<(), String>,
+    deferred: std::sync::Arc<std::sync::Mutex<HashMap<String, PendingDeferred>>>,
+    sessions: std::sync::Arc<SessionStore>,
+    index: std::sync::Arc<VectorIndex>,
     tool_router: ToolRouter<Self>,
 }
 
+/// Forwards streamed text to the MCP client as `notifications/progress`.
+///
+/// Only created when the client supplied a progress token; deltas are batched
+/// so a fast stream doesn't flood the transport.
+struct ProgressReporter {
+    peer: Peer<RoleServer>,
+    token: ProgressToken,
+    received: usize,
+    pending: String,
+    last_flush: Instant,
+}
+
+impl ProgressReporter {
+    fn from_context(ctx: &RequestContext<RoleServer>) -> Option<Self> {
+        Some(Self {
+            peer: ctx.peer.clone(),
+            token: ctx.meta.get_progress_token()?,
+            received: 0,
+            pending: String::new(),
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Queue a text delta, sending it if the batching interval has elapsed.
+    async fn push(&mut self, delta: &str) {
+        if delta.is_empty() {
+            return;
+        }
+        self.received += delta.chars().count();
+        self.pending.push_str(delta);
+        if self.last_flush.elapsed() >= PROGRESS_INTERVAL {
+            self.flush().await;
+        }
+    }
+
+    /// Report that `done` of `total` requests of a multi-request tool have
+    /// finished. Not to be mixed with [`push`](Self::push) on one token.
+    async fn step(&self, done: usize, total: usize, message: String) {
+        let param = ProgressNotificationParam::new(self.token.clone(), done as f64)
+            .with_total(total as f64)
+            .with_message(message);
+        if let Err(e) = self.peer.notify_progress(param).await {
+            debug!(error = %e, "failed to send progress notification");
+        }
+    }
+
+    /// Send any queued text. `progress` is the number of characters received so far.
+    async fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let param = ProgressNotificationParam::new(self.token.clone(), self.received as f64)
+            .with_message(std::mem::take(&mut self.pending));
+        if let Err(e) = self.peer.notify_progress(param).await {
+            debug!(error = %e, "failed to send progress notification");
+        }
+        self.last_flush = Instant::now();
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Shared helpers — keep tool methods DRY
 // ---------------------------------------------------------------------------
@@ -46,6 +231,120 @@ impl GrokServer {
         Ok(())
     }
 
+    /// Turn an API failure into a tool error with an actionable message and a
+    /// machine-readable `error` object in `structuredContent`.
+    fn api_error_result(err: &ApiError) -> CallToolResult {
+        let kind = err.kind();
+        let hint = match kind {
+            ApiErrorKind::RateLimited => "xAI rate limit reached. Wait before retrying.",
+            ApiErrorKind::Unauthorized => {
+                "The xAI API key was rejected. Check api_key in the server's config.toml."
+            }
+            ApiErrorKind::ModelNotFound => {
+                "The model is unknown or unavailable. Call list_models for valid model IDs."
+            }
+            ApiErrorKind::ContextLengthExceeded => {
+                "The request exceeds the model's context window. \
+                 Shorten the prompt or history, or lower max_tokens."
+            }
+            ApiErrorKind::InvalidRequest => "xAI rejected the request parameters.",
+            ApiErrorKind::Upstream => "xAI failed to process the request. Retry later.",
+            ApiErrorKind::Network => "Could not reach the xAI API. Check network connectivity.",
+            ApiErrorKind::Timeout => {
+                "The request timed out. Retry with a larger timeout_secs or a smaller request."
+            }
+            ApiErrorKind::Cancelled => "The request was cancelled by the client.",
+        };
+
+        let mut error = serde_json::json!({
+            "code": kind.code(),
+            "retryable": kind.is_retryable(),
+            "message": err.to_string(),
+        });
+        if let ApiError::Api {
+            status,
+            detail,
+            retry_after,
+            ..
+        } = err
+        {
+            error["status"] = status.as_u16().into();
+            error["message"] = detail.message.clone().into();
+            if let Some(code) = &detail.code {
+                error["upstream_code"] = code.clone().into();
+            }
+            if let Some(error_type) = &detail.error_type {
+                error["type"] = error_type.clone().into();
+            }
+            if let Some(param) = &detail.param {
+                error["param"] = param.clone().into();
+            }
+            if let Some(after) = retry_after {
+                error["retry_after_secs"] = after.as_secs_f64().into();
+            }
+        }
+
+        let mut result = CallToolResult::error(vec![Content::text(format!(
+            "[{}] {hint}\n{err}",
+            kind.code()
+        ))]);
+        result.structured_content = Some(serde_json::json!({ "error": error }));
+        result
+    }
+
+    /// A tool error with the same `[code] message` text and structured
+    /// `error` object as [`api_error_result`](Self::api_error_result), for
+    /// failures detected before calling xAI.
+    fn tool_error(kind: ApiErrorKind, message: &str) -> CallToolResult {
+        Self::coded_error(kind.code(), kind.is_retryable(), message)
+    }
+
+    /// A `[code] message` tool error for failures that are not API errors.
+    fn coded_error(code: &str, retryable: bool, message: &str) -> CallToolResult {
+        let mut result = CallToolResult::error(vec![Content::text(format!("[{code}] {message}"))]);
+        result.structured_content = Some(serde_json::json!({
+            "error": {
+                "code": code,
+                "retryable": retryable,
+                "message": message,
+            }
+        }));
+        result
+    }
+
+    /// Build per-call request options: the MCP request's cancellation token
+    /// plus an optional timeout override.
+    fn request_options(
+        ctx: &RequestContext<RoleServer>,
+        timeout_secs: Option<u64>,
+    ) -> Result<RequestOptions, McpError> {
+        if timeout_secs == Some(0) {
+            return Err(McpError::invalid_params(
+                "timeout_secs must be greater than 0",
+                None,
+            ));
+        }
+        Ok(RequestOptions {
+            timeout: timeout_secs.map(Duration::from_secs),
+            cancel: Some(ctx.ct.clone()),
+        })
+    }
+
+    /// Reject ids that could escape their place in a request path.
+    fn validate_id(field: &str, id: &str) -> Result<(), McpError> {
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(McpError::invalid_params(
+                format!("{field} must be a non-empty id of letters, digits, '-', '_' or '.'"),
+                None,
+            ));
+        }
+        Ok(())
+    }
+
     /// Build the messages vec from optional system prompt, optional history, and current prompt.
     fn build_messages(
         system_prompt: Option<&str>,
@@ -61,7 +360,7 @@ impl GrokServer {
         if let Some(json) = history_json {
             let parsed: Vec<ChatMessage> =
                 serde_json::from_str(json).map_err(|e| format!("Invalid messages JSON: {e}"))?;
-            for msg in &parsed {
+            for (i, msg) in parsed.iter().enumerate() {
                 if !VALID_ROLES.contains(&msg.role.as_str()) {
                     return Err(format!(
                         "Invalid role '{}' in messages — must be one of: {}",
@@ -69,14 +368,95 @@ impl GrokServer {
                         VALID_ROLES.join(", ")
                     ));
                 }
+                msg.validate()
+                    .map_err(|e| format!("Invalid message at index {i}: {e}"))?;
             }
             messages.extend(parsed);
         }
 
-        messages.push(ChatMessage::user(prompt));
+        // Sending tool results back needs no new user turn.
+        let answers_tools = messages.last().is_some_and(|m| m.role == "tool");
+        if !(prompt.is_empty() && answers_tools) {
+            messages.push(ChatMessage::user(prompt));
+        }
         Ok(messages)
     }
 
+    /// Parse `tools` JSON: an array of `{type: "function", function: {...}}`
+    /// definitions, or of bare `{name, description, parameters}` functions.
+    fn parse_tools(json: &str) -> Result<Vec<ToolDefinition>, String> {
+        let items: Vec<Value> =
+            serde_json::from_str(json).map_err(|e| format!("Invalid tools JSON: {e}"))?;
+        if items.is_empty() {
+            return Err("tools must contain at least one function".into());
+        }
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let parsed = if item.get("type").is_some() {
+                    serde_json::from_value::<ToolDefinition>(item)
+                } else {
+                    serde_json::from_value(item).map(|function| ToolDefinition {
+                        kind: "function".into(),
+                        function,
+                    })
+                };
+                match parsed {
+                    Ok(tool) if tool.kind == "function" => Ok(tool),
+                    Ok(tool) => Err(format!("tools[{i}]: unsupported type \"{}\"", tool.kind)),
+                    Err(e) => Err(format!("tools[{i}]: {e}")),
+                }
+            })
+            .collect()
+    }
+
+    /// Parse `tool_choice`: `none`, `auto`, `required`, a function name, or
+    /// the JSON object form.
+    fn parse_tool_choice(choice: &str) -> Result<ToolChoice, String> {
+        let choice = choice.trim();
+        match choice {
+            "none" => Ok(ToolChoice::None),
+            "auto" => Ok(ToolChoice::Auto),
+            "required" => Ok(ToolChoice::Required),
+            json if json.starts_with('{') => {
+                serde_json::from_str(json).map_err(|e| format!("Invalid tool_choice: {e}"))
+            }
+            "" => Err("tool_choice must not be empty".into()),
+            name => Ok(ToolChoice::Function { name: name.into() }),
+        }
+    }
+
+    /// The result of a chat completion. Tool calls also go into
+    /// `structuredContent`, with their arguments parsed where they are valid
+    /// JSON, so the calling agent can execute them.
+    fn chat_result(resp: &ChatResponse) -> CallToolResult {
+        let mut result = CallToolResult::success(vec![Content::text(resp.to_string())]);
+        let Some(choice) = resp.choices.first() else {
+            return result;
+        };
+        if let Some(calls) = &choice.message.tool_calls {
+            let calls: Vec<Value> = calls
+                .iter()
+                .map(|call| {
+                    let arguments = serde_json::from_str(&call.function.arguments)
+                        .unwrap_or_else(|_| Value::String(call.function.arguments.clone()));
+                    serde_json::json!({
+                        "id": call.id,
+                        "name": call.function.name,
+                        "arguments": arguments,
+                    })
+                })
+                .collect();
+            result.structured_content = Some(serde_json::json!({
+                "content": choice.message.content,
+                "tool_calls": calls,
+                "finish_reason": choice.finish_reason,
+            }));
+        }
+        result
+    }
+
     /// Build a ChatRequest with shared optional fields applied.
     fn build_chat_request(
         model: Option<&str>,
@@ -84,7 +464,7 @@ impl GrokServer {
         temperature: Option<f32>,
         max_tokens: Option<u32>,
         response_schema: Option<&str>,
-        tools: Option<Vec<Value>>,
+        tools: Option<Vec<ToolDefinition>>,
     ) -> Result<ChatRequest, String> {
         let mut req = ChatRequest::new(model.unwrap_or(DEFAULT_MODEL), messages);
         req.temperature = temperature;
@@ -94,6 +474,9 @@ impl GrokServer {
         if let Some(schema_str) = response_schema {
             let schema: Value = serde_json::from_str(schema_str)
                 .map_err(|e| format!("Invalid response_schema JSON: {e}"))?;
+            if !schema.is_object() {
+                return Err("response_schema must be a JSON object".into());
+            }
             req.response_format = Some(serde_json::json!({
                 "type": "json_schema",
                 "json_schema": {
@@ -108,17 +491,183 @@ impl GrokServer {
     }
 
     /// Send a chat request and return the formatted result.
-    async fn do_chat(&self, req: &ChatRequest) -> Result<CallToolResult, McpError> {
-        match self
-            .client
-            .request::<_, ChatResponse>(Method::POST, "/chat/completions", Some(req))
+    /// Streams with progress notifications when a reporter is supplied.
+    /// With a session, the final user message and the reply are appended to
+    /// it once the reply has passed any `response_schema` check.
+    async fn do_chat(
+        &self,
+        req: ChatRequest,
+        opts: &RequestOptions,
+        progress: Option<ProgressReporter>,
+        session: Option<&str>,
+    ) -> Result<CallToolResult, McpError> {
+        let schema = Self::response_schema(&req);
+        let prompt = session.and(req.messages.last().cloned());
+        let resp = match self.chat_response(req, opts, progress).await {
+            Ok(resp) => resp,
+            Err(rejected) => return Ok(rejected),
+        };
+        let result = Self::completion_result(&resp, schema.as_ref());
+        if result.is_error != Some(true)
+            && let (Some(id), Some(prompt), Some(choice)) = (session, prompt, resp.choices.first())
+        {
+            self.record_turn(id, prompt, ChatMessage::from(&choice.message));
+        }
+        Ok(result)
+    }
+
+    /// Send a chat request like [`do_chat`](Self::do_chat), but return the
+    /// response itself for tools that post-process the reply. `Err` is the
+    /// tool error to send instead.
+    async fn chat_response(
+        &self,
+        req: ChatRequest,
+        opts: &RequestOptions,
+        progress: Option<ProgressReporter>,
+    ) -> Result<ChatResponse, CallToolResult> {
+        self.send_chat(req, opts, progress)
             .await
+            .map_err(|e| Self::api_error_result(&e))
+    }
+
+    /// The JSON schema a request asked the model to follow, if any.
+    fn response_schema(req: &ChatRequest) -> Option<Value> {
+        req.response_format
+            .as_ref()
+            .and_then(|format| format.pointer("/json_schema/schema"))
+            .cloned()
+    }
+
+    /// The result of a finished completion, validated against `schema` when
+    /// one was requested.
+    fn completion_result(resp: &ChatResponse, schema: Option<&Value>) -> CallToolResult {
+        match schema {
+            Some(schema) => Self::structured_result(resp, schema),
+            None => Self::chat_result(resp),
+        }
+    }
+
+    /// Send a chat request, streaming it with progress notifications when a
+    /// reporter is supplied.
+    async fn send_chat(
+        &self,
+        mut req: ChatRequest,
+        opts: &RequestOptions,
+        progress: Option<ProgressReporter>,
+    ) -> Result<ChatResponse, ApiError> {
+        match progress {
+            Some(progress) => {
+                req.stream = Some(true);
+                self.stream_chat(&req, opts, progress).await
+            }
+            None => {
+                self.client
+                    .request_with(Method::POST, "/chat/completions", Some(&req), opts)
+                    .await
+            }
+        }
+    }
+
+    /// The result of a completion requested with `response_schema`: the
+    /// model's JSON, checked locally against the schema, as
+    /// `structuredContent`.
+    fn structured_result(resp: &ChatResponse, schema: &Value) -> CallToolResult {
+        let Some(choice) = resp.choices.first() else {
+            return Self::chat_result(resp);
+        };
+        if choice.message.tool_calls.is_some() {
+            return Self::chat_result(resp);
+        }
+
+        let output = choice.message.content.as_deref().unwrap_or_default();
+        let value: Value = match serde_json::from_str(output) {
+            Ok(value) => value,
+            Err(e) => {
+                let cut_off = if choice.finish_reason.as_deref() == Some("length") {
+                    " (it was cut off at max_tokens)"
+                } else {
+                    ""
+                };
+                return Self::invalid_output(
+                    &format!("The model's output is not valid JSON{cut_off}: {e}"),
+                    &[],
+                    output,
+                );
+            }
+        };
+        let errors = schema::validate(schema, &value);
+        if !errors.is_empty() {
+            return Self::invalid_output(
+                "The model's output does not match response_schema.",
+                &errors,
+                output,
+            );
+        }
+
+        let mut result = CallToolResult::success(vec![Content::text(output)]);
+        // structuredContent must be an object.
+        result.structured_content = Some(match value {
+            Value::Object(_) => value,
+            other => serde_json::json!({ "value": other }),
+        });
+        result
+    }
+
+    /// An `invalid_output` tool error listing the schema violations, with the
+    /// raw model output attached. Resending the same request is not expected
+    /// to fix it, so it is not marked retryable.
+    fn invalid_output(
+        message: &str,
+        errors: &[schema::SchemaError],
+        output: &str,
+    ) -> CallToolResult {
+        let details: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        let text = if details.is_empty() {
+            message.to_string()
+        } else {
+            format!("{message}\n- {}", details.join("\n- "))
+        };
+        let mut result = Self::coded_error("invalid_output", false, &text);
+        result
+            .content
+            .push(Content::text(format!("Model output:\n{output}")));
+        if let Some(error) = result
+            .structured_content
+            .as_mut()
+            .and_then(|c| c.get_mut("error"))
         {
-            Ok(resp) => Ok(CallToolResult::success(vec![Content::text(
-                resp.to_string(),
-            )])),
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(e.to_string())])),
+            error["violations"] = details.into();
+            error["output"] = output.into();
+        }
+        result
+    }
+
+    /// Append a completed exchange to a session. The session may have been
+    /// deleted while the request was in flight, in which case it is dropped.
+    fn record_turn(&self, session: &str, prompt: ChatMessage, reply: ChatMessage) {
+        if let Err(e) = self.sessions.append(session, [prompt, reply]) {
+            debug!(error = %e, "not recording turn");
+        }
+    }
+
+    /// Stream a chat completion, forwarding content deltas as progress.
+    async fn stream_chat(
+        &self,
+        req: &ChatRequest,
+        opts: &RequestOptions,
+        mut progress: ProgressReporter,
+    ) -> Result<ChatResponse, ApiError> {
+        let mut stream = self
+            .client
+            .request_stream_with(Method::POST, "/chat/completions", Some(req), opts)
+            .await?;
+        let mut acc = ChatStreamAccumulator::default();
+        while let Some(chunk) = stream.next_json::<ChatCompletionChunk>().await {
+            let text = acc.push(chunk?);
+            progress.push(&text).await;
         }
+        progress.flush().await;
+        Ok(acc.finish())
     }
 
     /// Check if a model slug is the multi-agent variant (requires Responses API).
@@ -151,204 +700,2345 @@ impl GrokServer {
     }
 
     /// Send a request via the Responses API and return the formatted result.
-    async fn do_responses(&self, req: &ResponsesRequest) -> Result<CallToolResult, McpError> {
-        match self
+    /// Streams with progress notifications when a reporter is supplied.
+    async fn do_responses(
+        &self,
+        mut req: ResponsesRequest,
+        opts: &RequestOptions,
+        progress: Option<ProgressReporter>,
+        session: Option<&str>,
+    ) -> Result<CallToolResult, McpError> {
+        let prompt = session.and(req.input.last().map(|m| ChatMessage::user(&m.content)));
+        let result = match progress {
+            Some(progress) => {
+                req.stream = Some(true);
+                self.stream_responses(&req, opts, progress).await
+            }
+            None => {
+                self.client
+                    .request_with::<_, ResponsesResponse>(
+                        Method::POST,
+                        "/responses",
+                        Some(&req),
+                        opts,
+                    )
+                    .await
+            }
+        };
+
+        match result {
+            Ok(resp) => {
+                if let (Some(id), Some(prompt)) = (session, prompt) {
+                    self.record_turn(id, prompt, ChatMessage::assistant(&resp.output_text()));
+                }
+                Ok(Self::responses_result(&resp))
+            }
+            Err(e) => Ok(Self::api_error_result(&e)),
+        }
+    }
+
+    /// Format a Responses API result, with its `response_id` in
+    /// `structuredContent` for follow-up calls.
+    fn responses_result(resp: &ResponsesResponse) -> CallToolResult {
+        let mut result = CallToolResult::success(vec![Content::text(resp.to_string())]);
+        if let Some(id) = &resp.id {
+            result.structured_content = Some(serde_json::json!({ "response_id": id }));
+        }
+        result
+    }
+
+    /// Stream a Responses API call, forwarding output text deltas as progress.
+    async fn stream_responses(
+        &self,
+        req: &ResponsesRequest,
+        opts: &RequestOptions,
+        mut progress: ProgressReporter,
+    ) -> Result<ResponsesResponse, ApiError> {
+        let mut stream = self
             .client
-            .request::<_, ResponsesResponse>(Method::POST, "/responses", Some(req))
-            .await
-        {
-            Ok(resp) => Ok(CallToolResult::success(vec![Content::text(
-                resp.to_string(),
-            )])),
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(e.to_string())])),
+            .request_stream_with(Method::POST, "/responses", Some(req), opts)
+            .await?;
+        let mut acc = ResponsesStreamAccumulator::default();
+        while let Some(event) = stream.next_json::<ResponsesStreamEvent>().await {
+            let text = acc.push(event?);
+            progress.push(&text).await;
         }
+        progress.flush().await;
+        Ok(acc.finish())
     }
 
-    /// Build search tool definitions for the xAI agent tools API.
-    fn search_tools(search_type: SearchType) -> Vec<Value> {
-        let mut tools = Vec::new();
-        if matches!(search_type, SearchType::Web | SearchType::Both) {
-            tools.push(serde_json::json!({ "type": "web_search" }));
+    /// Build the user message for `chat_with_vision`: the prompt, then each
+    /// image in order (`image_url` first) with its own or the default detail.
+    async fn vision_message(
+        files: &FileAccess,
+        prompt: &str,
+        image_url: Option<String>,
+        images: Vec<VisionImage>,
+        default_detail: ImageDetail,
+    ) -> Result<ChatMessage, String> {
+        let first = image_url.map(|url| ("image_url".to_string(), url, None));
+        let rest = images
+            .into_iter()
+            .enumerate()
+            .map(|(i, img)| (format!("images[{i}]"), img.url, img.detail));
+        let sources: Vec<_> = first.into_iter().chain(rest).collect();
+        if sources.is_empty() {
+            return Err("provide image_url or at least one entry in images".into());
         }
-        if matches!(search_type, SearchType::X | SearchType::Both) {
-            tools.push(serde_json::json!({ "type": "x_search" }));
+
+        let mut parts = vec![ContentPart::text(prompt)];
+        for (field, source, detail) in sources {
+            let url = files
+                .image_url(&source)
+                .await
+                .map_err(|e| format!("{field}: {e}"))?;
+            let detail = detail.unwrap_or_else(|| default_detail.clone());
+            parts.push(ContentPart::image_url(&url, Some(detail.as_str())));
         }
-        tools
+        Ok(ChatMessage::user_with_parts(parts))
     }
-}
 
-// ---------------------------------------------------------------------------
-// Tool definitions
-// ---------------------------------------------------------------------------
+    /// Turn generated images into MCP content: inline `image` blocks for
+    /// base64 data, text for URLs, each followed by its revised prompt.
+    fn image_result(resp: ImageGenerationResponse) -> CallToolResult {
+        let mut content = Vec::new();
+        for (i, image) in resp.data.into_iter().enumerate() {
+            let n = i + 1;
+            if let Some(data) = image.b64_json {
+                let mime = Self::sniff_image_mime(&data);
+                content.push(Content::image(data, mime));
+            } else if let Some(url) = image.url {
+                content.push(Content::text(format!("Image {n}: {url}")));
+            }
+            if let Some(revised) = image.revised_prompt {
+                content.push(Content::text(format!("Revised prompt {n}: {revised}")));
+            }
+        }
+        if content.is_empty() {
+            return CallToolResult::error(vec![Content::text("xAI returned no images")]);
+        }
+        CallToolResult::success(content)
+    }
 
-#[tool_router]
-impl GrokServer {
-    pub fn new(client: XaiClient) -> Self {
-        let models_cache = Cache::builder()
-            .max_capacity(1)
-            .time_to_live(Duration::from_secs(300))
-            .build();
+    /// Guess an image MIME type from the leading bytes of base64 data.
+    /// xAI currently returns JPEG, which is also the fallback.
+    fn sniff_image_mime(b64: &str) -> &'static str {
+        use base64::Engine;
 
-        Self {
-            client: std::sync::Arc::new(client),
-            models_cache,
-            tool_router: Self::tool_router(),
+        let prefix = &b64.as_bytes()[..b64.len().min(16)];
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(prefix)
+            .unwrap_or_default();
+        files::sniff_image_mime(&bytes).unwrap_or("image/jpeg")
+    }
+
+    /// The context window of `model`: configured lengths first, then the
+    /// built-in table by longest matching prefix.
+    fn context_length(&self, model: &str) -> Option<u32> {
+        if let Some(&len) = self.context_lengths.get(model) {
+            return Some(len);
         }
+        CONTEXT_LENGTHS
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|&(_, len)| len)
     }
 
-    #[tool(
-        description = "Send a chat completion request to Grok. Supports multi-turn conversations, \
-                           structured output via JSON schema, model selection, and multi-agent \
-                           research (when using a multi-agent model)."
-    )]
-    async fn chat(
-        &self,
-        Parameters(p): Parameters<ChatParams>,
-    ) -> Result<CallToolResult, McpError> {
-        debug!(model = ?p.model, "chat tool called");
-        Self::validate_temperature(p.temperature)?;
-        Self::validate_reasoning_effort(p.reasoning_effort.as_deref())?;
+    /// Flatten messages into one text for tokenization. Image parts are not counted.
+    fn messages_text(messages: &[ChatMessage]) -> String {
+        messages
+            .iter()
+            .map(|m| {
+                let content = m.content.as_ref().map(MessageContent::text);
+                format!("{}: {}", m.role, content.unwrap_or_default())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 
-        let model = p.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    /// Count the tokens in `messages` and fail fast if they, plus
+    /// `max_tokens`, cannot fit in the model's context window.
+    /// Returns the tool result to send instead of calling the model, if any.
+    async fn preflight(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        max_tokens: Option<u32>,
+        opts: &RequestOptions,
+    ) -> Result<Option<CallToolResult>, McpError> {
+        let Some(context) = self.context_length(model) else {
+            return Err(McpError::invalid_params(
+                format!(
+                    "preflight: unknown context length for model '{model}' — \
+                     add it to context_lengths in config.toml"
+                ),
+                None,
+            ));
+        };
 
-        // Multi-agent models require the Responses API, not Chat Completions.
-        if Self::is_multi_agent_model(model) {
-            let mut input = Vec::new();
-            if let Some(sys) = &p.system_prompt {
-                input.push(ResponsesMessage::system(sys));
-            }
-            input.push(ResponsesMessage::user(&p.prompt));
+        let req = TokenizeRequest {
+            text: Self::messages_text(messages),
+            model: model.into(),
+        };
+        let tokens = match self
+            .client
+            .request_with::<_, TokenizeResponse>(Method::POST, "/tokenize-text", Some(&req), opts)
+            .await
+        {
+            Ok(resp) => resp.count() + MESSAGE_OVERHEAD_TOKENS * messages.len(),
+            Err(e) => return Ok(Some(Self::api_error_result(&e))),
+        };
 
-            let req = ResponsesRequest {
-                model: model.into(),
-                input,
-                temperature: p.temperature,
-                max_output_tokens: p.max_tokens,
-                tools: None,
-                reasoning: Self::build_reasoning(p.reasoning_effort.as_deref()),
-            };
+        let needed = tokens as u64 + u64::from(max_tokens.unwrap_or(0));
+        debug!(tokens, needed, context, "chat preflight");
+        if needed <= u64::from(context) {
+            return Ok(None);
+        }
+        let reserve = max_tokens
+            .map(|m| format!(" plus max_tokens {m}"))
+            .unwrap_or_default();
+        Ok(Some(Self::tool_error(
+            ApiErrorKind::ContextLengthExceeded,
+            &format!(
+                "The prompt is about {tokens} tokens{reserve}, which exceeds the \
+                 {context}-token context window of {model}. \
+                 Shorten the prompt or history, or lower max_tokens."
+            ),
+        )))
+    }
 
-            return self.do_responses(&req).await;
+    /// Build the Chat Completions request for `chat`-style parameters, with
+    /// the session's system prompt and history if given, running the token
+    /// preflight when asked. `Err` is the tool result to send instead of
+    /// calling the model.
+    async fn prepare_chat(
+        &self,
+        p: &ChatParams,
+        model: &str,
+        opts: &RequestOptions,
+        session: Option<&Session>,
+    ) -> Result<Result<ChatRequest, CallToolResult>, McpError> {
+        if p.previous_response_id.is_some() || p.store.is_some() {
+            return Err(McpError::invalid_params(
+                "previous_response_id and store need the Responses API — \
+                 use a multi-agent model or chat_with_search",
+                None,
+            ));
         }
 
-        let messages =
-            Self::build_messages(p.system_prompt.as_deref(), p.messages.as_deref(), &p.prompt)
-                .map_err(|e| McpError::invalid_params(e, None))?;
+        let system_prompt = p
+            .system_prompt
+            .as_deref()
+            .or(session.and_then(|s| s.system_prompt.as_deref()));
+        let mut messages = Self::build_messages(system_prompt, p.messages.as_deref(), &p.prompt)
+            .map_err(|e| McpError::invalid_params(e, None))?;
+        if let Some(session) = session {
+            let at = messages.len() - 1;
+            messages.splice(at..at, session.messages.iter().cloned());
+        }
 
-        let req = Self::build_chat_request(
-            Some(model),
-            messages,
-            p.temperature,
-            p.max_tokens,
-            p.response_schema.as_deref(),
-            None,
-        )
-        .map_err(|e| McpError::invalid_params(e, None))?;
+        if p.preflight.unwrap_or(false)
+            && let Some(rejected) = self.preflight(model, &messages, p.max_tokens, opts).await?
+        {
+            return Ok(Err(rejected));
+        }
 
-        self.do_chat(&req).await
+        let tools = p.tools.as_deref().map(Self::parse_tools).transpose();
+        let mut req = tools
+            .and_then(|tools| {
+                Self::build_chat_request(
+                    Some(model),
+                    messages,
+                    p.temperature,
+                    p.max_tokens,
+                    p.response_schema.as_deref(),
+                    tools,
+                )
+            })
+            .map_err(|e| McpError::invalid_params(e, None))?;
+        req.tool_choice = p
+            .tool_choice
+            .as_deref()
+            .map(Self::parse_tool_choice)
+            .transpose()
+            .map_err(|e| McpError::invalid_params(e, None))?;
+        Ok(Ok(req))
     }
 
-    #[tool(description = "Analyse an image with Grok's vision capabilities. \
-                           Provide an image URL and a text prompt.")]
-    async fn chat_with_vision(
+    /// Look up the session named by `session_id`, which excludes `messages`.
+    fn chat_session(
         &self,
-        Parameters(p): Parameters<VisionParams>,
-    ) -> Result<CallToolResult, McpError> {
-        debug!(model = ?p.model, "chat_with_vision tool called");
-        if !p.image_url.starts_with("http://") && !p.image_url.starts_with("https://") {
+        session_id: Option<&str>,
+        messages: Option<&str>,
+    ) -> Result<Option<Session>, McpError> {
+        let Some(id) = session_id else {
+            return Ok(None);
+        };
+        if messages.is_some() {
             return Err(McpError::invalid_params(
-                "image_url must start with http:// or https://",
+                "pass either session_id or messages, not both",
                 None,
             ));
         }
-        Self::validate_temperature(p.temperature)?;
+        self.sessions.get(id).map(Some).map_err(Self::session_error)
+    }
 
-        let detail = p.detail.unwrap_or_default();
-        let messages = vec![ChatMessage::user_with_image(
-            &p.prompt,
-            &p.image_url,
-            detail.as_str(),
-        )];
+    /// Function calling needs Chat Completions and a caller-held history.
+    fn check_tool_params(p: &ChatParams, model: &str) -> Result<(), McpError> {
+        let problem = if p.tool_choice.is_some() && p.tools.is_none() {
+            "tool_choice requires tools"
+        } else if p.tools.is_none() {
+            return Ok(());
+        } else if Self::is_multi_agent_model(model) {
+            "tools are not supported on multi-agent models"
+        } else if p.session_id.is_some() {
+            "tools cannot be combined with session_id — pass the history in messages \
+             so tool results can be sent back"
+        } else {
+            return Ok(());
+        };
+        Err(McpError::invalid_params(problem, None))
+    }
 
-        let req = Self::build_chat_request(
-            p.model.as_deref(),
-            messages,
-            p.temperature,
-            p.max_tokens,
-            None,
-            None,
-        )
-        .map_err(|e| McpError::invalid_params(e, None))?;
+    fn session_error(err: SessionError) -> McpError {
+        McpError::invalid_params(err.to_string(), None)
+    }
+
+    fn index_error(err: IndexError) -> McpError {
+        match err {
+            IndexError::Io { .. } | IndexError::Corrupt { .. } => {
+                McpError::internal_error(err.to_string(), None)
+            }
+            _ => McpError::invalid_params(err.to_string(), None),
+        }
+    }
+
+    /// Instructions for the shape, length and focus of the final summary.
+    fn summary_guidance(job: &SummaryJob<'_>) -> String {
+        let style = match job.style {
+            SummaryStyle::Paragraph => "Write it as prose paragraphs.",
+            SummaryStyle::Bullets => "Write it as a bulleted list of the key points.",
+            SummaryStyle::Tldr => "Start with a one-sentence headline, then one short paragraph.",
+        };
+        let length = match job.length {
+            SummaryLength::Short => "Aim for about 100 words.",
+            SummaryLength::Medium => "Aim for about 300 words.",
+            SummaryLength::Long => "Aim for about 800 words.",
+        };
+        let mut guidance = format!("{style} {length}");
+        if let Some(focus) = job.focus {
+            guidance.push_str(&format!(" Focus on: {focus}"));
+        }
+        guidance
+    }
+
+    /// Split `text` into parts of at most `chunk_tokens` tokens, counted by
+    /// the model's tokenizer one [`TOKENIZE_WINDOW_BYTES`] window at a time.
+    /// If the tokens cannot be mapped back onto the text, parts are sized by
+    /// the document's average characters per token.
+    async fn summary_chunks(
+        &self,
+        model: &str,
+        text: &str,
+        chunk_tokens: u32,
+        opts: &RequestOptions,
+    ) -> Result<Vec<String>, CallToolResult> {
+        let mut bounds = Some(Vec::new());
+        let mut count = 0;
+        let mut offset = 0;
+        for window in Self::tokenize_windows(text, TOKENIZE_WINDOW_BYTES) {
+            let req = TokenizeRequest {
+                text: window.into(),
+                model: model.into(),
+            };
+            let resp: TokenizeResponse = self
+                .client
+                .request_with(Method::POST, "/tokenize-text", Some(&req), opts)
+                .await
+                .map_err(|e| Self::api_error_result(&e))?;
+            count += resp.count();
+            // Each window's bounds end with its length, which is where the
+            // next window's first token starts.
+            bounds = bounds.zip(resp.token_bounds(window)).map(|(mut all, own)| {
+                all.extend(own[..own.len() - 1].iter().map(|b| offset + b));
+                all
+            });
+            offset += window.len();
+        }
+
+        let chunk_tokens = chunk_tokens as usize;
+        Ok(match bounds {
+            Some(mut bounds) => {
+                bounds.push(text.len());
+                index::chunk_units(text, &bounds, chunk_tokens, 0)
+            }
+            None => {
+                let chars = text.chars().count();
+                let max_chars = chars * chunk_tokens / count.max(1);
+                index::chunk_text(text, max_chars, 0)
+            }
+        })
+    }
+
+    /// Split `text` into consecutive slices of at most `max_bytes`, each
+    /// ending just after whitespace in its second half where possible, so
+    /// the pieces can be tokenized separately.
+    fn tokenize_windows(text: &str, max_bytes: usize) -> Vec<&str> {
+        let mut windows = Vec::new();
+        let mut rest = text;
+        while rest.len() > max_bytes {
+            let mut end = max_bytes;
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            if let Some((i, c)) = rest[..end]
+                .char_indices()
+                .rev()
+                .take_while(|&(i, _)| i >= end / 2)
+                .find(|(_, c)| c.is_whitespace())
+            {
+                end = i + c.len_utf8();
+            }
+            let (window, tail) = rest.split_at(end);
+            windows.push(window);
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            windows.push(rest);
+        }
+        windows
+    }
+
+    /// Number partial summaries so the merge keeps them in document order.
+    fn join_parts(parts: &[String]) -> String {
+        parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| format!("Part {}:\n{part}", i + 1))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Split partial summaries into consecutive groups whose combined
+    /// estimated size stays within `budget` tokens where possible. Every
+    /// group holds at least two parts, so each merge round shrinks the list.
+    fn group_parts(parts: Vec<String>, budget: u32) -> Vec<Vec<String>> {
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        let mut current: Vec<String> = Vec::new();
+        let mut size = 0;
+        for part in parts {
+            let cost = tokens::estimate(&part);
+            if current.len() >= 2 && size + cost > budget {
+                groups.push(std::mem::take(&mut current));
+                size = 0;
+            }
+            size += cost;
+            current.push(part);
+        }
+        match groups.last_mut() {
+            Some(last) if current.len() == 1 => last.append(&mut current),
+            _ if !current.is_empty() => groups.push(current),
+            _ => {}
+        }
+        groups
+    }
+
+    /// One summarisation request: `instructions` as the system prompt and
+    /// `text` as the user turn, sent like any `chat` call, with the job's
+    /// preflight if asked. Reports a progress step when it finishes.
+    async fn summary_request(
+        &self,
+        job: &SummaryJob<'_>,
+        instructions: &str,
+        text: &str,
+        step: String,
+        max_tokens: Option<u32>,
+    ) -> Result<(String, Usage), CallToolResult> {
+        let messages = vec![ChatMessage::system(instructions), ChatMessage::user(text)];
+        if job.preflight
+            && let Some(rejected) = self
+                .preflight(job.model, &messages, max_tokens, job.opts)
+                .await
+                .map_err(|e| Self::tool_error(ApiErrorKind::InvalidRequest, &e.message))?
+        {
+            return Err(rejected);
+        }
+        let mut req = ChatRequest::new(job.model, messages);
+        req.temperature = job.temperature;
+        req.max_tokens = max_tokens;
+        let resp = self.chat_response(req, job.opts, None).await?;
+
+        let done = job.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(progress) = &job.progress {
+            progress
+                .step(done, job.total.load(Ordering::Relaxed), step)
+                .await;
+        }
+        let summary = resp
+            .choices
+            .first()
+            .and_then(|c| c.message.content.clone())
+            .unwrap_or_default();
+        Ok((summary, resp.usage.unwrap_or_default()))
+    }
+
+    /// Run `pending` at most `limit` at a time, returning their outputs in
+    /// order. It takes a `Vec` because a lazily mapped iterator trips the
+    /// Send check on tool futures.
+    async fn run_buffered<F: Future>(pending: Vec<F>, limit: usize) -> Vec<F::Output> {
+        futures::stream::iter(pending)
+            .buffered(limit)
+            .collect()
+            .await
+    }
+
+    /// Run summarisation requests a few at a time, keeping their order and
+    /// adding the token usage of those that succeed to `usage`.
+    async fn summary_requests(
+        &self,
+        job: &SummaryJob<'_>,
+        requests: Vec<(String, String, String)>,
+        usage: &mut Usage,
+    ) -> Vec<Result<String, CallToolResult>> {
+        let pending = requests
+            .iter()
+            .map(|(instructions, text, step)| {
+                self.summary_request(job, instructions, text, step.clone(), None)
+            })
+            .collect();
+        Self::run_buffered(pending, SUMMARY_CONCURRENCY)
+            .await
+            .into_iter()
+            .map(|result| {
+                result.map(|(summary, u)| {
+                    usage.add(&u);
+                    summary
+                })
+            })
+            .collect()
+    }
+
+    /// Summarise each chunk concurrently, merge the partial summaries in
+    /// rounds until they fit in one request, then write the final summary.
+    ///
+    /// A part whose request fails is left out and reported in
+    /// [`Summary::failed_parts`]; the job only fails if every part does, or
+    /// if a merge or the final request fails.
+    async fn map_reduce(
+        &self,
+        job: &SummaryJob<'_>,
+        chunks: &[String],
+    ) -> Result<Summary, CallToolResult> {
+        let guidance = Self::summary_guidance(job);
+        let max_tokens = Some(job.length.max_tokens());
+        let mut usage = Usage::default();
+        let n = chunks.len();
+        if n == 1 {
+            job.total.store(1, Ordering::Relaxed);
+            let (text, usage) = self
+                .summary_request(
+                    job,
+                    &format!("Summarise the following document. {guidance}"),
+                    &chunks[0],
+                    "Summarised the document".into(),
+                    max_tokens,
+                )
+                .await?;
+            return Ok(Summary {
+                text,
+                usage,
+                failed_parts: Vec::new(),
+            });
+        }
+
+        job.total.store(n + 1, Ordering::Relaxed);
+        let focus = job
+            .focus
+            .map(|f| format!(" Pay particular attention to: {f}"))
+            .unwrap_or_default();
+        let requests = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                (
+                    format!(
+                        "You are summarising part {} of {n} of a longer document. Summarise \
+                         this part faithfully and concisely, keeping key facts, names, numbers \
+                         and conclusions.{focus}",
+                        i + 1
+                    ),
+                    chunk.clone(),
+                    format!("Summarised part {} of {n}", i + 1),
+                )
+            })
+            .collect();
+
+        let mut parts = Vec::with_capacity(n);
+        let mut failed_parts = Vec::new();
+        let mut first_failure = None;
+        for (i, result) in self
+            .summary_requests(job, requests, &mut usage)
+            .await
+            .into_iter()
+            .enumerate()
+        {
+            match result {
+                Ok(part) => parts.push(part),
+                Err(rejected) => {
+                    let error = rejected
+                        .structured_content
+                        .as_ref()
+                        .and_then(|c| c.get("error"))
+                        .cloned()
+                        .unwrap_or_default();
+                    failed_parts.push((i + 1, error));
+                    first_failure.get_or_insert(rejected);
+                }
+            }
+        }
+        if parts.is_empty() {
+            return Err(first_failure.expect("every part failed"));
+        }
+
+        while parts.len() > 1 && tokens::estimate(&Self::join_parts(&parts)) > job.budget {
+            let groups = Self::group_parts(parts, job.budget);
+            job.total.fetch_add(groups.len(), Ordering::Relaxed);
+            let requests = groups
+                .iter()
+                .map(|group| {
+                    (
+                        format!(
+                            "The following are summaries of consecutive parts of one document, \
+                             in order. Merge them into one concise summary, keeping key facts, \
+                             names, numbers and conclusions.{focus}"
+                        ),
+                        Self::join_parts(group),
+                        format!("Merged {} partial summaries", group.len()),
+                    )
+                })
+                .collect();
+            parts = self
+                .summary_requests(job, requests, &mut usage)
+                .await
+                .into_iter()
+                .collect::<Result<_, _>>()?;
+        }
+
+        let (text, u) = self
+            .summary_request(
+                job,
+                &format!(
+                    "The following are summaries of consecutive parts of one document, in \
+                     order. Combine them into a single summary of the whole document. {guidance}"
+                ),
+                &Self::join_parts(&parts),
+                "Wrote the final summary".into(),
+                max_tokens,
+            )
+            .await?;
+        usage.add(&u);
+        Ok(Summary {
+            text,
+            usage,
+            failed_parts,
+        })
+    }
+
+    /// One `chat_batch` item: the reply (or, with a schema, the validated
+    /// JSON) on success, otherwise the same `error` object a tool error has.
+    fn batch_item(
+        index: usize,
+        result: Result<ChatResponse, ApiError>,
+        schema: Option<&Value>,
+    ) -> (Value, Option<Usage>) {
+        let failed = |result: CallToolResult| {
+            let error = result
+                .structured_content
+                .and_then(|mut c| c.get_mut("error").map(Value::take))
+                .unwrap_or_default();
+            serde_json::json!({ "index": index, "ok": false, "error": error })
+        };
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => return (failed(Self::api_error_result(&e)), None),
+        };
+        let finish_reason = resp.choices.first().and_then(|c| c.finish_reason.clone());
+        let item = match schema {
+            Some(schema) => {
+                let result = Self::structured_result(&resp, schema);
+                if result.is_error == Some(true) {
+                    failed(result)
+                } else {
+                    serde_json::json!({
+                        "index": index,
+                        "ok": true,
+                        "output": result.structured_content,
+                        "finish_reason": finish_reason,
+                    })
+                }
+            }
+            None => serde_json::json!({
+                "index": index,
+                "ok": true,
+                "content": resp.choices.first().and_then(|c| c.message.content.clone()),
+                "finish_reason": finish_reason,
+            }),
+        };
+        (item, resp.usage)
+    }
+
+    /// All `chat_batch` items in prompt order, one paragraph each, with the
+    /// success count and summed usage.
+    fn batch_result(items: Vec<Value>, usage: Usage) -> CallToolResult {
+        let succeeded = items.iter().filter(|item| item["ok"] == true).count();
+        let failed = items.len() - succeeded;
+        let mut text: Vec<String> = items
+            .iter()
+            .map(|item| {
+                let index = &item["index"];
+                if item["ok"] == true {
+                    match &item["output"] {
+                        Value::Null => {
+                            format!("[{index}] {}", item["content"].as_str().unwrap_or(""))
+                        }
+                        output => format!("[{index}] {output}"),
+                    }
+                } else {
+                    let error = &item["error"];
+                    format!(
+                        "[{index}] error [{}] {}",
+                        error["code"].as_str().unwrap_or("error"),
+                        error["message"].as_str().unwrap_or("")
+                    )
+                }
+            })
+            .collect();
+        text.push(format!(
+            "[{succeeded} succeeded, {failed} failed, tokens: {} prompt + {} completion = {} total]",
+            usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
+        ));
+
+        let mut result = CallToolResult::success(vec![Content::text(text.join("\n\n"))]);
+        result.structured_content = Some(serde_json::json!({
+            "results": items,
+            "succeeded": succeeded,
+            "failed": failed,
+            "usage": {
+                "prompt_tokens": usage.prompt_tokens,
+                "completion_tokens": usage.completion_tokens,
+                "total_tokens": usage.total_tokens,
+            },
+        }));
+        result
+    }
+
+    /// Keep the best hits whose estimated size fits in `budget` tokens, in
+    /// rank order. A chunk too large for what is left is skipped, so a
+    /// smaller, lower-ranked one may still fit.
+    fn select_context(hits: Vec<Hit>, budget: u32) -> Vec<Hit> {
+        let mut remaining = budget;
+        hits.into_iter()
+            .filter(|hit| {
+                let cost = tokens::estimate(&hit.text) + MESSAGE_OVERHEAD_TOKENS as u32;
+                let fits = cost <= remaining;
+                if fits {
+                    remaining -= cost;
+                }
+                fits
+            })
+            .collect()
+    }
+
+    /// The system prompt for `chat_with_documents`: the caller's own, then
+    /// the citation instructions and the excerpts numbered from 1.
+    fn documents_system_prompt(system_prompt: Option<&str>, sources: &[Hit]) -> String {
+        let mut prompt = String::new();
+        if let Some(sys) = system_prompt {
+            prompt.push_str(sys);
+            prompt.push_str("\n\n");
+        }
+        prompt.push_str(DOCUMENTS_PROMPT);
+        for (i, hit) in sources.iter().enumerate() {
+            prompt.push_str(&format!(
+                "\n\n[{}] {} #{}\n{}",
+                i + 1,
+                hit.document_id,
+                hit.chunk,
+                hit.text
+            ));
+        }
+        prompt
+    }
+
+    /// Add the excerpts sent to the model to a `chat_with_documents` answer,
+    /// marking those the model's reply cites.
+    fn documents_result(resp: &ChatResponse, sources: &[Hit]) -> CallToolResult {
+        let mut result = Self::chat_result(resp);
+        let answer = resp
+            .choices
+            .first()
+            .and_then(|c| c.message.content.clone())
+            .unwrap_or_default();
+
+        let mut listing = String::from("Sources:");
+        let mut entries = Vec::with_capacity(sources.len());
+        for (i, hit) in sources.iter().enumerate() {
+            let label = format!("[{}]", i + 1);
+            let cited = answer.contains(&label);
+            listing.push_str(&format!(
+                "\n{label} {} #{} (score {:.4}){}",
+                hit.document_id,
+                hit.chunk,
+                hit.score,
+                if cited { "" } else { " — not cited" }
+            ));
+            entries.push(serde_json::json!({
+                "ref": i + 1,
+                "document_id": hit.document_id,
+                "chunk": hit.chunk,
+                "score": hit.score,
+                "metadata": hit.metadata,
+                "cited": cited,
+            }));
+        }
+        result.content.push(Content::text(listing));
+        result.structured_content = Some(serde_json::json!({
+            "answer": answer,
+            "sources": entries,
+        }));
+        result
+    }
+
+    /// Search hits as numbered passages, best first, with the full chunk text.
+    fn hits_result(collection: &str, hits: &[Hit]) -> CallToolResult {
+        let text = if hits.is_empty() {
+            format!("No chunks in '{collection}' to match.")
+        } else {
+            hits.iter()
+                .enumerate()
+                .map(|(i, h)| {
+                    format!(
+                        "{}. {} #{} (score {:.4})\n{}",
+                        i + 1,
+                        h.document_id,
+                        h.chunk,
+                        h.score,
+                        h.text
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        };
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = Some(serde_json::json!({
+            "collection": collection,
+            "results": hits
+                .iter()
+                .map(|h| serde_json::json!({
+                    "document_id": h.document_id,
+                    "chunk": h.chunk,
+                    "score": h.score,
+                    "text": h.text,
+                    "metadata": h.metadata,
+                }))
+                .collect::<Vec<_>>(),
+        }));
+        result
+    }
+
+    fn lock_deferred(&self) -> std::sync::MutexGuard<'_, HashMap<String, PendingDeferred>> {
+        // Entries are only inserted or removed whole; a poisoned lock is harmless.
+        self.deferred.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Remember a submitted deferred completion until it is collected or expires.
+    fn track_deferred(&self, request_id: String, model: String, schema: Option<Value>) {
+        let mut pending = self.lock_deferred();
+        pending.retain(|_, p| p.submitted.elapsed() < DEFERRED_TTL);
+        pending.insert(
+            request_id,
+            PendingDeferred {
+                model,
+                submitted: Instant::now(),
+                schema,
+            },
+        );
+    }
+
+    /// Deferred completions still awaiting collection, oldest first.
+    fn pending_deferred(&self) -> Vec<(String, PendingDeferred)> {
+        let mut pending = self.lock_deferred();
+        pending.retain(|_, p| p.submitted.elapsed() < DEFERRED_TTL);
+        let mut list: Vec<_> = pending
+            .iter()
+            .map(|(id, p)| (id.clone(), p.clone()))
+            .collect();
+        list.sort_by_key(|(_, p)| p.submitted);
+        list
+    }
+
+    /// Embed `texts` in `/embeddings` calls of up to [`EMBED_BATCH_SIZE`]
+    /// inputs, returning the vectors in input order with usage summed. API
+    /// failures and incomplete responses become tool errors.
+    async fn embed_texts(
+        &self,
+        model: &str,
+        texts: Vec<String>,
+        opts: &RequestOptions,
+    ) -> Result<EmbeddingResponse, CallToolResult> {
+        let mut merged = EmbeddingResponse {
+            data: Vec::with_capacity(texts.len()),
+            usage: None,
+        };
+        for batch in texts.chunks(EMBED_BATCH_SIZE) {
+            let count = batch.len();
+            let req = EmbeddingRequest::new(model, Value::from(batch.to_vec()));
+            let mut resp: EmbeddingResponse = self
+                .client
+                .request_with(Method::POST, "/embeddings", Some(&req), opts)
+                .await
+                .map_err(|e| Self::api_error_result(&e))?;
+            resp.data.sort_by_key(|d| d.index);
+            if resp.data.len() != count || resp.data.iter().enumerate().any(|(i, d)| d.index != i) {
+                return Err(Self::tool_error(
+                    ApiErrorKind::Upstream,
+                    &format!(
+                        "xAI returned {} embeddings for {count} inputs",
+                        resp.data.len()
+                    ),
+                ));
+            }
+
+            let offset = merged.data.len();
+            merged.data.extend(resp.data.into_iter().map(|mut d| {
+                d.index += offset;
+                d
+            }));
+            if let Some(usage) = resp.usage {
+                let total = merged.usage.get_or_insert(EmbeddingUsage {
+                    prompt_tokens: 0,
+                    total_tokens: 0,
+                });
+                total.prompt_tokens += usage.prompt_tokens;
+                total.total_tokens += usage.total_tokens;
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Rank `candidates` by the cosine similarity of their embeddings to the
+    /// query's, which is the first entry of `resp`.
+    fn similarity_result(
+        model: &str,
+        candidates: &[String],
+        resp: &EmbeddingResponse,
+        top_k: Option<usize>,
+    ) -> CallToolResult {
+        let (query, rest) = resp
+            .data
+            .split_first()
+            .expect("embed_texts returns one vector per input");
+        let mut ranked = vectors::rank(
+            &query.embedding,
+            rest.iter().map(|d| d.embedding.as_slice()),
+        );
+        ranked.truncate(top_k.unwrap_or(usize::MAX));
+
+        let mut text = String::new();
+        for (rank, &(i, score)) in ranked.iter().enumerate() {
+            let candidate = &candidates[i];
+            let snippet: String = candidate.chars().take(SNIPPET_CHARS).collect();
+            let ellipsis = if snippet.len() < candidate.len() {
+                "…"
+            } else {
+                ""
+            };
+            text.push_str(&format!(
+                "{}. {score:.4}  [{i}] {snippet}{ellipsis}\n",
+                rank + 1
+            ));
+        }
+        if let Some(usage) = &resp.usage {
+            text.push_str(&format!("[tokens: {} total]", usage.total_tokens));
+        }
+
+        let results: Vec<Value> = ranked
+            .iter()
+            .map(|&(i, score)| {
+                serde_json::json!({ "index": i, "score": score, "text": candidates[i] })
+            })
+            .collect();
+        let mut result = CallToolResult::success(vec![Content::text(text.trim_end())]);
+        result.structured_content = Some(serde_json::json!({
+            "model": model,
+            "results": results,
+        }));
+        result
+    }
+
+    /// Embeddings in the preview, JSON or base64 output formats. The full
+    /// formats carry the vectors in `structuredContent`, mirrored as JSON text.
+    fn embedding_result(
+        model: &str,
+        resp: EmbeddingResponse,
+        output: EmbeddingOutput,
+    ) -> CallToolResult {
+        let usage = resp
+            .usage
+            .as_ref()
+            .map(|u| serde_json::json!({ "prompt_tokens": u.prompt_tokens, "total_tokens": u.total_tokens }));
+        let embeddings: Vec<Value> = match output {
+            EmbeddingOutput::Json => resp
+                .data
+                .iter()
+                .map(|d| serde_json::json!({ "index": d.index, "embedding": d.embedding }))
+                .collect(),
+            EmbeddingOutput::Base64 => resp
+                .data
+                .iter()
+                .map(|d| {
+                    serde_json::json!({
+                        "index": d.index,
+                        "dimensions": d.embedding.len(),
+                        "base64": vectors::to_base64(&d.embedding),
+                    })
+                })
+                .collect(),
+            _ => return CallToolResult::success(vec![Content::text(resp.to_string())]),
+        };
+
+        let structured = serde_json::json!({
+            "model": model,
+            "embeddings": embeddings,
+            "usage": usage,
+        });
+        let mut result = CallToolResult::success(vec![Content::text(structured.to_string())]);
+        result.structured_content = Some(structured);
+        result
+    }
+
+    /// Save embeddings as a `.npy` matrix or JSONL lines in an allowed directory.
+    /// JSONL lines include the input text when the input was a list of strings.
+    async fn write_embeddings(
+        &self,
+        req: &EmbeddingRequest,
+        mut resp: EmbeddingResponse,
+        output: EmbeddingOutput,
+        path: &str,
+    ) -> Result<CallToolResult, McpError> {
+        resp.data.sort_by_key(|d| d.index);
+        let count = resp.data.len();
+        let dimensions = resp.data.first().map_or(0, |d| d.embedding.len());
+
+        let bytes = if output == EmbeddingOutput::Npy {
+            let rows: Vec<Vec<f32>> = resp.data.into_iter().map(|d| d.embedding).collect();
+            match vectors::to_npy(&rows) {
+                Ok(bytes) => bytes,
+                Err(e) => return Ok(Self::tool_error(ApiErrorKind::Upstream, &e)),
+            }
+        } else {
+            let texts = match &req.input {
+                Value::String(text) => vec![Some(text.as_str())],
+                Value::Array(items) => items.iter().map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            let mut lines = String::new();
+            for d in &resp.data {
+                let mut line = serde_json::json!({ "index": d.index, "embedding": d.embedding });
+                if let Some(text) = texts.get(d.index).copied().flatten() {
+                    line["text"] = text.into();
+                }
+                lines.push_str(&line.to_string());
+                lines.push('\n');
+            }
+            lines.into_bytes()
+        };
+
+        let written = self
+            .files
+            .write(path, &bytes)
+            .await
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+        let mut result = CallToolResult::success(vec![Content::text(format!(
+            "Wrote {count} embeddings ({dimensions} dimensions) to {}",
+            written.display()
+        ))]);
+        result.structured_content = Some(serde_json::json!({
+            "path": written,
+            "count": count,
+            "dimensions": dimensions,
+        }));
+        Ok(result)
+    }
+
+    /// Build search tool definitions for the xAI agent tools API.
+    fn search_tools(search_type: SearchType) -> Vec<Value> {
+        let mut tools = Vec::new();
+        if matches!(search_type, SearchType::Web | SearchType::Both) {
+            tools.push(serde_json::json!({ "type": "web_search" }));
+        }
+        if matches!(search_type, SearchType::X | SearchType::Both) {
+            tools.push(serde_json::json!({ "type": "x_search" }));
+        }
+        tools
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tool definitions
+// ---------------------------------------------------------------------------
+
+#[tool_router]
+impl GrokServer {
+    pub fn new(client: XaiClient) -> Self {
+        let models_cache = Cache::builder()
+            .max_capacity(1)
+            .time_to_live(Duration::from_secs(300))
+            .build();
+
+        Self {
+            client: std::sync::Arc::new(client),
+            files: std::sync::Arc::new(FileAccess::disabled()),
+            context_lengths: Default::default(),
+            models_cache,
+            deferred: Default::default(),
+            sessions: Default::default(),
+            index: Default::default(),
+            tool_router: Self::tool_router(),
+        }
+    }
+
+    /// Allow tools to read local files inside the given directories.
+    pub fn with_file_access(mut self, files: FileAccess) -> Self {
+        self.files = std::sync::Arc::new(files);
+        self
+    }
+
+    /// Override or extend the built-in model context lengths used by `chat`'s preflight.
+    pub fn with_context_lengths(mut self, lengths: HashMap<String, u32>) -> Self {
+        self.context_lengths = std::sync::Arc::new(lengths);
+        self
+    }
+
+    /// Use `sessions` (and its history limits) for `chat`'s `session_id`.
+    pub fn with_sessions(mut self, sessions: SessionStore) -> Self {
+        self.sessions = std::sync::Arc::new(sessions);
+        self
+    }
+
+    /// Keep `index_documents` collections in the given index.
+    pub fn with_index(mut self, index: VectorIndex) -> Self {
+        self.index = std::sync::Arc::new(index);
+        self
+    }
+
+    #[tool(
+        description = "Send a chat completion request to Grok. Supports multi-turn conversations, \
+                           structured output via JSON schema, model selection, and multi-agent \
+                           research (when using a multi-agent model)."
+    )]
+    async fn chat(
+        &self,
+        Parameters(p): Parameters<ChatParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, "chat tool called");
+        Self::validate_temperature(p.temperature)?;
+        Self::validate_reasoning_effort(p.reasoning_effort.as_deref())?;
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+
+        let model = p.model.as_deref().unwrap_or(DEFAULT_MODEL);
+        let session = self.chat_session(p.session_id.as_deref(), p.messages.as_deref())?;
+        Self::check_tool_params(&p, model)?;
+
+        // Multi-agent models require the Responses API, not Chat Completions.
+        if Self::is_multi_agent_model(model) {
+            let mut input = Vec::new();
+            let session_prompt = session.as_ref().and_then(|s| s.system_prompt.as_ref());
+            if let Some(sys) = p.system_prompt.as_ref().or(session_prompt) {
+                input.push(ResponsesMessage::system(sys));
+            }
+            // The Responses input is text-only; tool-call turns carry none.
+            for msg in session.iter().flat_map(|s| &s.messages) {
+                let text = msg.content.as_ref().map(MessageContent::text);
+                if let Some(text) = text.filter(|t| !t.is_empty()) {
+                    input.push(ResponsesMessage {
+                        role: msg.role.clone(),
+                        content: text,
+                    });
+                }
+            }
+            input.push(ResponsesMessage::user(&p.prompt));
+            if let Some(id) = &p.previous_response_id {
+                Self::validate_id("previous_response_id", id)?;
+            }
+
+            let mut req = ResponsesRequest::new(model, input);
+            req.temperature = p.temperature;
+            req.max_output_tokens = p.max_tokens;
+            req.reasoning = Self::build_reasoning(p.reasoning_effort.as_deref());
+            req.previous_response_id = p.previous_response_id;
+            req.store = p.store;
+
+            return self
+                .do_responses(
+                    req,
+                    &opts,
+                    ProgressReporter::from_context(&ctx),
+                    p.session_id.as_deref(),
+                )
+                .await;
+        }
+
+        let req = match self
+            .prepare_chat(&p, model, &opts, session.as_ref())
+            .await?
+        {
+            Ok(req) => req,
+            Err(rejected) => return Ok(rejected),
+        };
+
+        self.do_chat(
+            req,
+            &opts,
+            ProgressReporter::from_context(&ctx),
+            p.session_id.as_deref(),
+        )
+        .await
+    }
+
+    #[tool(
+        description = "Queue a chat completion to run in the background and return a request id \
+                           at once. Takes the same parameters as chat; collect the answer later \
+                           with chat_deferred_result. Useful for long reasoning jobs."
+    )]
+    async fn chat_deferred_submit(
+        &self,
+        Parameters(p): Parameters<ChatParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, "chat_deferred_submit tool called");
+        Self::validate_temperature(p.temperature)?;
+        Self::validate_reasoning_effort(p.reasoning_effort.as_deref())?;
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+
+        let model = p.model.as_deref().unwrap_or(DEFAULT_MODEL);
+        if Self::is_multi_agent_model(model) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "'{model}' uses the Responses API, which does not support deferred \
+                     completions — call chat instead"
+                ),
+                None,
+            ));
+        }
+        if p.session_id.is_some() {
+            return Err(McpError::invalid_params(
+                "session_id is not supported for deferred completions",
+                None,
+            ));
+        }
+        Self::check_tool_params(&p, model)?;
+
+        let req = match self.prepare_chat(&p, model, &opts, None).await? {
+            Ok(req) => req,
+            Err(rejected) => return Ok(rejected),
+        };
+
+        let schema = Self::response_schema(&req);
+        match self.client.submit_deferred(req, &opts).await {
+            Ok(request_id) => {
+                self.track_deferred(request_id.clone(), model.into(), schema);
+                let mut result = CallToolResult::success(vec![Content::text(format!(
+                    "Submitted. Request id: {request_id}\n\
+                     Call chat_deferred_result with this id to collect the answer \
+                     (available for 24 hours)."
+                ))]);
+                result.structured_content = Some(serde_json::json!({
+                    "request_id": request_id,
+                    "model": model,
+                }));
+                Ok(result)
+            }
+            Err(e) => Ok(Self::api_error_result(&e)),
+        }
+    }
+
+    #[tool(
+        description = "Collect the result of a chat_deferred_submit request. Reports that the \
+                           request is still running if it has not finished. Without a request_id, \
+                           lists the requests not yet collected."
+    )]
+    async fn chat_deferred_result(
+        &self,
+        Parameters(p): Parameters<DeferredResultParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(request_id = ?p.request_id, "chat_deferred_result tool called");
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+
+        let Some(request_id) = p.request_id else {
+            let pending = self.pending_deferred();
+            let text = if pending.is_empty() {
+                "No deferred requests pending.".to_string()
+            } else {
+                pending
+                    .iter()
+                    .map(|(id, p)| {
+                        format!(
+                            "- {id} ({}, submitted {}s ago)",
+                            p.model,
+                            p.submitted.elapsed().as_secs()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            let mut result = CallToolResult::success(vec![Content::text(text)]);
+            result.structured_content = Some(serde_json::json!({
+                "pending": pending
+                    .iter()
+                    .map(|(id, p)| serde_json::json!({
+                        "request_id": id,
+                        "model": p.model,
+                        "age_secs": p.submitted.elapsed().as_secs(),
+                    }))
+                    .collect::<Vec<_>>(),
+            }));
+            return Ok(result);
+        };
+
+        Self::validate_id("request_id", &request_id)?;
+        match self.client.deferred_result(&request_id, &opts).await {
+            Ok(Some(resp)) => {
+                let schema = self
+                    .lock_deferred()
+                    .remove(&request_id)
+                    .and_then(|p| p.schema);
+                Ok(Self::completion_result(&resp, schema.as_ref()))
+            }
+            Ok(None) => {
+                let age = self
+                    .lock_deferred()
+                    .get(&request_id)
+                    .map(|p| format!(" (submitted {}s ago)", p.submitted.elapsed().as_secs()))
+                    .unwrap_or_default();
+                let mut result = CallToolResult::success(vec![Content::text(format!(
+                    "Request {request_id} is still running{age}. Try again later."
+                ))]);
+                result.structured_content = Some(serde_json::json!({
+                    "status": "pending",
+                    "request_id": request_id,
+                }));
+                Ok(result)
+            }
+            Err(e) => Ok(Self::api_error_result(&e)),
+        }
+    }
+
+    #[tool(
+        description = "Start a server-side conversation session. Pass its session_id to chat \
+                           to continue the conversation without re-sending the history."
+    )]
+    async fn create_session(
+        &self,
+        Parameters(p): Parameters<CreateSessionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(session_id = ?p.session_id, "create_session tool called");
+        let session = self
+            .sessions
+            .create(p.session_id, p.system_prompt)
+            .map_err(Self::session_error)?;
+        let mut result = CallToolResult::success(vec![Content::text(format!(
+            "Created session {}.",
+            session.id
+        ))]);
+        result.structured_content = Some(serde_json::json!({ "session_id": session.id }));
+        Ok(result)
+    }
+
+    #[tool(description = "List the server-side conversation sessions, most recently used first.")]
+    async fn list_sessions(&self) -> Result<CallToolResult, McpError> {
+        let sessions = self.sessions.list();
+        let text = if sessions.is_empty() {
+            "No sessions.".to_string()
+        } else {
+            sessions
+                .iter()
+                .map(|s| {
+                    format!(
+                        "- {}: {} messages, idle {}s",
+                        s.id,
+                        s.messages.len(),
+                        s.last_used.elapsed().as_secs()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = Some(serde_json::json!({
+            "sessions": sessions
+                .iter()
+                .map(|s| serde_json::json!({
+                    "session_id": s.id,
+                    "messages": s.messages.len(),
+                    "idle_secs": s.last_used.elapsed().as_secs(),
+                }))
+                .collect::<Vec<_>>(),
+        }));
+        Ok(result)
+    }
+
+    #[tool(description = "Show a session's system prompt and message history.")]
+    async fn get_session(
+        &self,
+        Parameters(p): Parameters<SessionIdParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let session = self
+            .sessions
+            .get(&p.session_id)
+            .map_err(Self::session_error)?;
+
+        let mut text = format!(
+            "Session {}: {} messages",
+            session.id,
+            session.messages.len()
+        );
+        if session.trimmed > 0 {
+            text.push_str(&format!(" ({} older messages trimmed)", session.trimmed));
+        }
+        if let Some(sys) = &session.system_prompt {
+            text.push_str(&format!("\nSystem prompt: {sys}"));
+        }
+        if !session.messages.is_empty() {
+            text.push('\n');
+            text.push_str(&Self::messages_text(&session.messages));
+        }
+
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = Some(serde_json::json!({
+            "session_id": session.id,
+            "system_prompt": session.system_prompt,
+            "messages": session.messages,
+            "trimmed": session.trimmed,
+        }));
+        Ok(result)
+    }
+
+    #[tool(description = "Clear a session's history, keeping its id and system prompt.")]
+    async fn reset_session(
+        &self,
+        Parameters(p): Parameters<SessionIdParams>,
+    ) -> Result<CallToolResult, McpError> {
+        self.sessions
+            .reset(&p.session_id)
+            .map_err(Self::session_error)?;
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Reset session {}.",
+            p.session_id
+        ))]))
+    }
+
+    #[tool(description = "Delete a session and its history.")]
+    async fn delete_session(
+        &self,
+        Parameters(p): Parameters<SessionIdParams>,
+    ) -> Result<CallToolResult, McpError> {
+        self.sessions
+            .delete(&p.session_id)
+            .map_err(Self::session_error)?;
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Deleted session {}.",
+            p.session_id
+        ))]))
+    }
+
+    #[tool(description = "Analyse an image with Grok's vision capabilities. \
+                           Provide an image (URL, data: URI or local file path) and a text prompt.")]
+    async fn chat_with_vision(
+        &self,
+        Parameters(p): Parameters<VisionParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, "chat_with_vision tool called");
+        Self::validate_temperature(p.temperature)?;
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+        let message = Self::vision_message(
+            &self.files,
+            &p.prompt,
+            p.image_url,
+            p.images.unwrap_or_default(),
+            p.detail.unwrap_or_default(),
+        )
+        .await
+        .map_err(|e| McpError::invalid_params(e, None))?;
+        let messages = vec![message];
+
+        let req = Self::build_chat_request(
+            p.model.as_deref(),
+            messages,
+            p.temperature,
+            p.max_tokens,
+            None,
+            None,
+        )
+        .map_err(|e| McpError::invalid_params(e, None))?;
+
+        self.do_chat(req, &opts, ProgressReporter::from_context(&ctx), None)
+            .await
+    }
+
+    #[tool(
+        description = "Chat with Grok using live web search and/or X (Twitter) search. \
+                           The model will automatically search the internet to ground its response."
+    )]
+    async fn chat_with_search(
+        &self,
+        Parameters(p): Parameters<SearchParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, search_type = ?p.search_type, "chat_with_search tool called");
+        Self::validate_temperature(p.temperature)?;
+        Self::validate_reasoning_effort(p.reasoning_effort.as_deref())?;
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+
+        let search_type = p.search_type.unwrap_or_default();
+
+        let mut input = Vec::new();
+        if let Some(sys) = &p.system_prompt {
+            input.push(ResponsesMessage::system(sys));
+        }
+        input.push(ResponsesMessage::user(&p.prompt));
+
+        if let Some(id) = &p.previous_response_id {
+            Self::validate_id("previous_response_id", id)?;
+        }
+
+        let mut req = ResponsesRequest::new(p.model.as_deref().unwrap_or(DEFAULT_MODEL), input);
+        req.temperature = p.temperature;
+        req.max_output_tokens = p.max_tokens;
+        req.tools = Some(Self::search_tools(search_type));
+        req.reasoning = Self::build_reasoning(p.reasoning_effort.as_deref());
+        req.previous_response_id = p.previous_response_id;
+        req.store = p.store;
+
+        self.do_responses(req, &opts, ProgressReporter::from_context(&ctx), None)
+            .await
+    }
+
+    #[tool(
+        description = "Generate text embeddings using Grok's embedding model. Returns a preview by \
+                       default; full vectors as JSON or base64, or written to a .npy/.jsonl file."
+    )]
+    async fn embedding(
+        &self,
+        Parameters(p): Parameters<EmbeddingParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, "embedding tool called");
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+        let input: Value = serde_json::from_str(&p.input).map_err(|e| {
+            McpError::invalid_params(
+                format!("Invalid input JSON (must be a quoted string or array of strings): {e}"),
+                None,
+            )
+        })?;
+
+        if p.dimensions == Some(0) {
+            return Err(McpError::invalid_params(
+                "dimensions must be at least 1",
+                None,
+            ));
+        }
+
+        let output = p.output.unwrap_or_default();
+        let output_path = match (output, p.output_path) {
+            (EmbeddingOutput::Npy | EmbeddingOutput::Jsonl, None) => {
+                return Err(McpError::invalid_params(
+                    "output_path is required for the npy and jsonl outputs",
+                    None,
+                ));
+            }
+            (EmbeddingOutput::Npy | EmbeddingOutput::Jsonl, Some(path)) => {
+                // Fail before spending tokens on vectors that cannot be saved.
+                self.files
+                    .resolve_output(&path)
+                    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+                Some(path)
+            }
+            (_, Some(_)) => {
+                return Err(McpError::invalid_params(
+                    "output_path only applies to the npy and jsonl outputs",
+                    None,
+                ));
+            }
+            (_, None) => None,
+        };
+
+        let mut req =
+            EmbeddingRequest::new(p.model.as_deref().unwrap_or(DEFAULT_EMBEDDING_MODEL), input);
+        req.encoding_format = p.encoding_format.map(|f| f.as_str().into());
+        req.dimensions = p.dimensions;
+
+        let resp = match self
+            .client
+            .request_with::<_, EmbeddingResponse>(Method::POST, "/embeddings", Some(&req), &opts)
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => return Ok(Self::api_error_result(&e)),
+        };
+
+        match output_path {
+            Some(path) => self.write_embeddings(&req, resp, output, &path).await,
+            None => Ok(Self::embedding_result(&req.model, resp, output)),
+        }
+    }
+
+    #[tool(
+        description = "Rank candidate texts by semantic similarity to a query, using the cosine \
+                       similarity of Grok embeddings. Returns the candidates best first with scores."
+    )]
+    async fn similarity(
+        &self,
+        Parameters(p): Parameters<SimilarityParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, candidates = p.candidates.len(), "similarity tool called");
+        if p.query.trim().is_empty() {
+            return Err(McpError::invalid_params("query must not be empty", None));
+        }
+        if p.candidates.is_empty() {
+            return Err(McpError::invalid_params(
+                "candidates must not be empty",
+                None,
+            ));
+        }
+        if p.top_k == Some(0) {
+            return Err(McpError::invalid_params("top_k must be at least 1", None));
+        }
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+        let model = p.model.as_deref().unwrap_or(DEFAULT_EMBEDDING_MODEL);
+
+        let texts = std::iter::once(&p.query)
+            .chain(&p.candidates)
+            .cloned()
+            .collect();
+        match self.embed_texts(model, texts, &opts).await {
+            Ok(resp) => Ok(Self::similarity_result(
+                model,
+                &p.candidates,
+                &resp,
+                p.top_k,
+            )),
+            Err(result) => Ok(result),
+        }
+    }
+
+    #[tool(
+        description = "Chunk, embed and store documents (inline text or local files) in a named \
+                       local collection for search_index. Re-indexing a document id replaces it."
+    )]
+    async fn index_documents(
+        &self,
+        Parameters(p): Parameters<IndexDocumentsParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(collection = %p.collection, documents = p.documents.len(), "index_documents tool called");
+        if p.documents.is_empty() {
+            return Err(McpError::invalid_params(
+                "documents must not be empty",
+                None,
+            ));
+        }
+        let chunk_chars = p.chunk_chars.unwrap_or(index::DEFAULT_CHUNK_CHARS);
+        let chunk_overlap = p
+            .chunk_overlap
+            .unwrap_or(index::DEFAULT_CHUNK_OVERLAP.min(chunk_chars / 2));
+        if chunk_chars == 0 || chunk_overlap >= chunk_chars {
+            return Err(McpError::invalid_params(
+                "chunk_chars must be greater than 0 and larger than chunk_overlap",
+                None,
+            ));
+        }
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+
+        let model = match self.index.model(&p.collection).await {
+            Ok(existing) => match p.model {
+                Some(model) if model != existing => {
+                    return Err(Self::index_error(IndexError::ModelMismatch {
+                        collection: p.collection,
+                        expected: existing,
+                        got: model,
+                    }));
+                }
+                _ => existing,
+            },
+            Err(IndexError::NotFound(_)) => {
+                p.model.unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.into())
+            }
+            Err(e) => return Err(Self::index_error(e)),
+        };
+
+        // Read and chunk everything before spending tokens on embeddings.
+        let mut pending: Vec<(String, serde_json::Map<String, Value>, Vec<String>)> = Vec::new();
+        for doc in p.documents {
+            let mut metadata = doc.metadata.unwrap_or_default();
+            let (id, text) = match (doc.text, doc.path) {
+                (Some(text), None) => {
+                    let id = doc.id.ok_or_else(|| {
+                        McpError::invalid_params("documents with inline text need an id", None)
+                    })?;
+                    (id, text)
+                }
+                (None, Some(path)) => {
+                    let text = self
+                        .files
+                        .read_text(&path)
+                        .await
+                        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+                    metadata
+                        .entry("path")
+                        .or_insert_with(|| path.clone().into());
+                    (doc.id.unwrap_or(path), text)
+                }
+                _ => {
+                    return Err(McpError::invalid_params(
+                        "each document needs exactly one of 'text' or 'path'",
+                        None,
+                    ));
+                }
+            };
+            if id.is_empty() || pending.iter().any(|(other, ..)| *other == id) {
+                return Err(McpError::invalid_params(
+                    format!("document ids must be non-empty and unique, got '{id}'"),
+                    None,
+                ));
+            }
+            let chunks = index::chunk_text(&text, chunk_chars, chunk_overlap);
+            if chunks.is_empty() {
+                return Err(McpError::invalid_params(
+                    format!("document '{id}' has no text to index"),
+                    None,
+                ));
+            }
+            pending.push((id, metadata, chunks));
+        }
 
-        self.do_chat(&req).await
+        let texts: Vec<String> = pending.iter().flat_map(|(.., c)| c.clone()).collect();
+        let resp = match self.embed_texts(&model, texts, &opts).await {
+            Ok(resp) => resp,
+            Err(result) => return Ok(result),
+        };
+        let tokens = resp.usage.map_or(0, |u| u.total_tokens);
+        let chunk_count = resp.data.len();
+
+        let mut vectors = resp.data.into_iter().map(|d| d.embedding);
+        let summary: Vec<Value> = pending
+            .iter()
+            .map(|(id, _, chunks)| serde_json::json!({ "id": id, "chunks": chunks.len() }))
+            .collect();
+        let documents = pending
+            .into_iter()
+            .map(|(id, metadata, chunks)| {
+                let chunks = chunks
+                    .into_iter()
+                    .zip(vectors.by_ref())
+                    .map(|(text, vector)| Chunk { text, vector })
+                    .collect();
+                (id, Document::new(metadata, chunks))
+            })
+            .collect();
+        let replaced = self
+            .index
+            .upsert(&p.collection, &model, documents)
+            .await
+            .map_err(Self::index_error)?;
+
+        let mut text = format!(
+            "Indexed {} documents ({} chunks) into '{}' with {model}.",
+            summary.len(),
+            chunk_count,
+            p.collection
+        );
+        if !replaced.is_empty() {
+            text.push_str(&format!(" Replaced: {}.", replaced.join(", ")));
+        }
+        text.push_str(&format!("\n[tokens: {tokens} total]"));
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = Some(serde_json::json!({
+            "collection": p.collection,
+            "model": model,
+            "documents": summary,
+            "replaced": replaced,
+            "total_tokens": tokens,
+        }));
+        Ok(result)
+    }
+
+    #[tool(
+        description = "Find the chunks of a local collection most similar to a query, \
+                       best first, with scores and document metadata."
+    )]
+    async fn search_index(
+        &self,
+        Parameters(p): Parameters<SearchIndexParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(collection = %p.collection, "search_index tool called");
+        if p.query.trim().is_empty() {
+            return Err(McpError::invalid_params("query must not be empty", None));
+        }
+        let top_k = p.top_k.unwrap_or(DEFAULT_TOP_K);
+        if top_k == 0 {
+            return Err(McpError::invalid_params("top_k must be at least 1", None));
+        }
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+        let model = self
+            .index
+            .model(&p.collection)
+            .await
+            .map_err(Self::index_error)?;
+
+        let resp = match self.embed_texts(&model, vec![p.query], &opts).await {
+            Ok(resp) => resp,
+            Err(result) => return Ok(result),
+        };
+        let hits = self
+            .index
+            .search(&p.collection, &resp.data[0].embedding, top_k)
+            .await
+            .map_err(Self::index_error)?;
+        Ok(Self::hits_result(&p.collection, &hits))
+    }
+
+    #[tool(
+        description = "Answer a question from a local collection: retrieves the most relevant \
+                       chunks within a token budget, asks Grok to answer citing them as [n], \
+                       and lists the chunks used."
+    )]
+    async fn chat_with_documents(
+        &self,
+        Parameters(p): Parameters<ChatWithDocumentsParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, collection = %p.collection, "chat_with_documents tool called");
+        Self::validate_temperature(p.temperature)?;
+        if p.prompt.trim().is_empty() {
+            return Err(McpError::invalid_params("prompt must not be empty", None));
+        }
+        let top_k = p.top_k.unwrap_or(DEFAULT_DOCUMENT_CHUNKS);
+        if top_k == 0 {
+            return Err(McpError::invalid_params("top_k must be at least 1", None));
+        }
+        let budget = p.context_tokens.unwrap_or(DEFAULT_CONTEXT_TOKENS);
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+        let embedding_model = self
+            .index
+            .model(&p.collection)
+            .await
+            .map_err(Self::index_error)?;
+
+        let resp = match self
+            .embed_texts(&embedding_model, vec![p.prompt.clone()], &opts)
+            .await
+        {
+            Ok(resp) => resp,
+            Err(result) => return Ok(result),
+        };
+        let hits = self
+            .index
+            .search(&p.collection, &resp.data[0].embedding, top_k)
+            .await
+            .map_err(Self::index_error)?;
+        if hits.is_empty() {
+            return Err(McpError::invalid_params(
+                format!("collection '{}' has no documents", p.collection),
+                None,
+            ));
+        }
+        let sources = Self::select_context(hits, budget);
+        if sources.is_empty() {
+            return Err(McpError::invalid_params(
+                format!("context_tokens {budget} is too small for any retrieved chunk"),
+                None,
+            ));
+        }
+
+        let system_prompt = Self::documents_system_prompt(p.system_prompt.as_deref(), &sources);
+        let messages = Self::build_messages(Some(&system_prompt), None, &p.prompt)
+            .map_err(|e| McpError::invalid_params(e, None))?;
+        let req = Self::build_chat_request(
+            p.model.as_deref(),
+            messages,
+            p.temperature,
+            p.max_tokens,
+            None,
+            None,
+        )
+        .map_err(|e| McpError::invalid_params(e, None))?;
+
+        Ok(
+            match self
+                .chat_response(req, &opts, ProgressReporter::from_context(&ctx))
+                .await
+            {
+                Ok(resp) => Self::documents_result(&resp, &sources),
+                Err(rejected) => rejected,
+            },
+        )
+    }
+
+    #[tool(description = "List the local vector index collections with their sizes and models.")]
+    async fn list_collections(&self) -> Result<CallToolResult, McpError> {
+        let collections = self.index.list().await.map_err(Self::index_error)?;
+        let text = if collections.is_empty() {
+            format!("No collections in {}.", self.index.dir().display())
+        } else {
+            collections
+                .iter()
+                .map(|c| {
+                    format!(
+                        "- {}: {} documents, {} chunks ({}, {} dimensions)",
+                        c.name, c.documents, c.chunks, c.model, c.dimensions
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = Some(serde_json::json!({
+            "collections": collections
+                .iter()
+                .map(|c| serde_json::json!({
+                    "name": c.name,
+                    "model": c.model,
+                    "dimensions": c.dimensions,
+                    "documents": c.documents,
+                    "chunks": c.chunks,
+                }))
+                .collect::<Vec<_>>(),
+        }));
+        Ok(result)
+    }
+
+    #[tool(description = "Remove documents from a local collection by id.")]
+    async fn delete_documents(
+        &self,
+        Parameters(p): Parameters<DeleteDocumentsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let deleted = self
+            .index
+            .delete_documents(&p.collection, &p.document_ids)
+            .await
+            .map_err(Self::index_error)?;
+        let missing: Vec<&String> = p
+            .document_ids
+            .iter()
+            .filter(|id| !deleted.contains(id))
+            .collect();
+        let mut text = format!(
+            "Deleted {} documents from '{}'.",
+            deleted.len(),
+            p.collection
+        );
+        if !missing.is_empty() {
+            let missing: Vec<&str> = missing.iter().map(|id| id.as_str()).collect();
+            text.push_str(&format!(" Not found: {}.", missing.join(", ")));
+        }
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = Some(serde_json::json!({
+            "deleted": deleted,
+            "not_found": missing,
+        }));
+        Ok(result)
+    }
+
+    #[tool(description = "Delete a local collection and all of its documents.")]
+    async fn delete_collection(
+        &self,
+        Parameters(p): Parameters<CollectionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        self.index
+            .delete_collection(&p.collection)
+            .await
+            .map_err(Self::index_error)?;
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Deleted collection '{}'.",
+            p.collection
+        ))]))
+    }
+
+    #[tool(
+        description = "Run many single-turn prompts through Grok concurrently, sharing a system \
+                       prompt, model and optional JSON schema. Returns each item's reply or \
+                       error, in order, with the total token usage."
+    )]
+    async fn chat_batch(
+        &self,
+        Parameters(p): Parameters<ChatBatchParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, prompts = p.prompts.len(), "chat_batch tool called");
+        Self::validate_temperature(p.temperature)?;
+        if !(1..=MAX_BATCH_PROMPTS).contains(&p.prompts.len()) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "prompts must hold between 1 and {MAX_BATCH_PROMPTS} prompts, got {}",
+                    p.prompts.len()
+                ),
+                None,
+            ));
+        }
+        let concurrency = p.concurrency.unwrap_or(DEFAULT_BATCH_CONCURRENCY);
+        if !(1..=MAX_BATCH_CONCURRENCY).contains(&concurrency) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "concurrency must be between 1 and {MAX_BATCH_CONCURRENCY}, got {concurrency}"
+                ),
+                None,
+            ));
+        }
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+
+        // Every prompt shares one response_format, so the schema is parsed once.
+        let template = Self::build_chat_request(
+            p.model.as_deref(),
+            Vec::new(),
+            p.temperature,
+            p.max_tokens,
+            p.response_schema.as_deref(),
+            None,
+        )
+        .map_err(|e| McpError::invalid_params(e, None))?;
+        let schema = Self::response_schema(&template);
+        let requests = p
+            .prompts
+            .iter()
+            .map(|prompt| {
+                let messages = Self::build_messages(p.system_prompt.as_deref(), None, prompt)?;
+                let mut req = Self::build_chat_request(
+                    p.model.as_deref(),
+                    messages,
+                    p.temperature,
+                    p.max_tokens,
+                    None,
+                    None,
+                )?;
+                req.response_format = template.response_format.clone();
+                Ok(req)
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|e| McpError::invalid_params(e, None))?;
+
+        let progress = ProgressReporter::from_context(&ctx);
+        let total = requests.len();
+        let done = AtomicUsize::new(0);
+        let pending = requests
+            .into_iter()
+            .enumerate()
+            .map(|(index, req)| {
+                let (opts, progress, done, schema) = (&opts, &progress, &done, &schema);
+                async move {
+                    let result = self.send_chat(req, opts, None).await;
+                    let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    if let Some(progress) = progress {
+                        let status = if result.is_ok() { "done" } else { "failed" };
+                        progress
+                            .step(done, total, format!("Prompt {index} {status}"))
+                            .await;
+                    }
+                    Self::batch_item(index, result, schema.as_ref())
+                }
+            })
+            .collect();
+        let outcomes = Self::run_buffered(pending, concurrency).await;
+
+        let mut usage = Usage::default();
+        let items = outcomes
+            .into_iter()
+            .map(|(item, u)| {
+                if let Some(u) = u {
+                    usage.add(&u);
+                }
+                item
+            })
+            .collect();
+        Ok(Self::batch_result(items, usage))
+    }
+
+    #[tool(
+        description = "Summarise a long text or local file of any length: it is split into \
+                       parts that fit the model, summarised concurrently, and the partial \
+                       summaries are merged into one. Reports progress per part."
+    )]
+    async fn summarize(
+        &self,
+        Parameters(p): Parameters<SummarizeParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, path = ?p.path, "summarize tool called");
+        Self::validate_temperature(p.temperature)?;
+        let chunk_tokens = p.chunk_tokens.unwrap_or(DEFAULT_SUMMARY_CHUNK_TOKENS);
+        if chunk_tokens < MIN_SUMMARY_CHUNK_TOKENS {
+            return Err(McpError::invalid_params(
+                format!("chunk_tokens must be at least {MIN_SUMMARY_CHUNK_TOKENS}"),
+                None,
+            ));
+        }
+        let text = match (p.text, &p.path) {
+            (Some(text), None) => text,
+            (None, Some(path)) => self
+                .files
+                .read_text(path)
+                .await
+                .map_err(|e| McpError::invalid_params(e.to_string(), None))?,
+            _ => {
+                return Err(McpError::invalid_params(
+                    "give exactly one of 'text' or 'path'",
+                    None,
+                ));
+            }
+        };
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+        let model = p.model.as_deref().unwrap_or(DEFAULT_MODEL);
+        let preflight = p.preflight.unwrap_or(false);
+        if preflight && self.context_length(model).is_none() {
+            return Err(McpError::invalid_params(
+                format!(
+                    "preflight: unknown context length for model '{model}' — \
+                     add it to context_lengths in config.toml"
+                ),
+                None,
+            ));
+        }
+        if text.trim().is_empty() {
+            return Err(McpError::invalid_params(
+                "there is no text to summarise",
+                None,
+            ));
+        }
+
+        let chunks = match self.summary_chunks(model, &text, chunk_tokens, &opts).await {
+            Ok(chunks) => chunks,
+            Err(rejected) => return Ok(rejected),
+        };
+        let job = SummaryJob {
+            model,
+            style: p.style.unwrap_or_default(),
+            length: p.length.unwrap_or_default(),
+            focus: p.focus.as_deref(),
+            temperature: p.temperature,
+            budget: chunk_tokens,
+            preflight,
+            opts: &opts,
+            progress: ProgressReporter::from_context(&ctx),
+            done: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+        };
+
+        let summary = match self.map_reduce(&job, &chunks).await {
+            Ok(summary) => summary,
+            Err(rejected) => return Ok(rejected),
+        };
+        let Summary {
+            text: summary,
+            usage,
+            failed_parts,
+        } = summary;
+        let requests = job.done.load(Ordering::Relaxed);
+        let mut text = summary.clone();
+        if !failed_parts.is_empty() {
+            let numbers: Vec<String> = failed_parts.iter().map(|(i, _)| i.to_string()).collect();
+            text.push_str(&format!(
+                "\n[parts {} of {} failed and are missing from this summary]",
+                numbers.join(", "),
+                chunks.len()
+            ));
+        }
+        text.push_str(&format!(
+            "\n[{} parts, {requests} requests, tokens: {} prompt + {} completion = {} total]",
+            chunks.len(),
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            usage.total_tokens
+        ));
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = Some(serde_json::json!({
+            "summary": summary,
+            "parts": chunks.len(),
+            "requests": requests,
+            "failed_parts": failed_parts
+                .iter()
+                .map(|(part, error)| serde_json::json!({ "part": part, "error": error }))
+                .collect::<Vec<_>>(),
+            "usage": {
+                "prompt_tokens": usage.prompt_tokens,
+                "completion_tokens": usage.completion_tokens,
+                "total_tokens": usage.total_tokens,
+            },
+        }));
+        Ok(result)
     }
 
     #[tool(
-        description = "Chat with Grok using live web search and/or X (Twitter) search. \
-                           The model will automatically search the internet to ground its response."
+        description = "Generate images from a text prompt with Grok's image model. \
+                           Returns the images (or their URLs) and the revised prompt the model used."
     )]
-    async fn chat_with_search(
+    async fn generate_image(
         &self,
-        Parameters(p): Parameters<SearchParams>,
+        Parameters(p): Parameters<ImageGenerationParams>,
+        ctx: RequestContext<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
-        debug!(model = ?p.model, search_type = ?p.search_type, "chat_with_search tool called");
-        Self::validate_temperature(p.temperature)?;
-        Self::validate_reasoning_effort(p.reasoning_effort.as_deref())?;
+        debug!(model = ?p.model, n = ?p.n, "generate_image tool called");
+        if p.prompt.trim().is_empty() {
+            return Err(McpError::invalid_params("prompt must not be empty", None));
+        }
+        if let Some(n) = p.n
+            && !(1..=MAX_IMAGES).contains(&n)
+        {
+            return Err(McpError::invalid_params(
+                format!("n must be between 1 and {MAX_IMAGES}, got {n}"),
+                None,
+            ));
+        }
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
 
-        let search_type = p.search_type.unwrap_or_default();
+        let req = ImageGenerationRequest {
+            model: p.model.unwrap_or_else(|| DEFAULT_IMAGE_MODEL.into()),
+            prompt: p.prompt,
+            n: p.n,
+            response_format: Some(p.response_format.unwrap_or_default().as_str().into()),
+        };
 
-        let mut input = Vec::new();
-        if let Some(sys) = &p.system_prompt {
-            input.push(ResponsesMessage::system(sys));
+        match self
+            .client
+            .request_with::<_, ImageGenerationResponse>(
+                Method::POST,
+                "/images/generations",
+                Some(&req),
+                &opts,
+            )
+            .await
+        {
+            Ok(resp) => Ok(Self::image_result(resp)),
+            Err(e) => Ok(Self::api_error_result(&e)),
         }
-        input.push(ResponsesMessage::user(&p.prompt));
-
-        let tools = Self::search_tools(search_type);
+    }
 
-        let req = ResponsesRequest {
+    #[tool(
+        description = "Count the tokens in a text with a Grok model's tokenizer, \
+                           e.g. to check it fits in the context window before sending it."
+    )]
+    async fn count_tokens(
+        &self,
+        Parameters(p): Parameters<CountTokensParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(model = ?p.model, "count_tokens tool called");
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+        let req = TokenizeRequest {
+            text: p.text,
             model: p.model.unwrap_or_else(|| DEFAULT_MODEL.into()),
-            input,
-            temperature: p.temperature,
-            max_output_tokens: p.max_tokens,
-            tools: Some(tools),
-            reasoning: Self::build_reasoning(p.reasoning_effort.as_deref()),
         };
 
-        self.do_responses(&req).await
+        match self
+            .client
+            .request_with::<_, TokenizeResponse>(Method::POST, "/tokenize-text", Some(&req), &opts)
+            .await
+        {
+            Ok(resp) => {
+                let text = if p.include_tokens.unwrap_or(false) {
+                    format!("{resp:#}")
+                } else {
+                    resp.to_string()
+                };
+                let mut result = CallToolResult::success(vec![Content::text(text)]);
+                result.structured_content = Some(serde_json::json!({
+                    "model": req.model,
+                    "tokens": resp.count(),
+                }));
+                Ok(result)
+            }
+            Err(e) => Ok(Self::api_error_result(&e)),
+        }
     }
 
-    #[tool(description = "Generate text embeddings using Grok's embedding model.")]
-    async fn embedding(
+    #[tool(
+        description = "Fetch a stored Responses API result (from chat with a multi-agent model \
+                           or chat_with_search) by its response_id."
+    )]
+    async fn get_response(
         &self,
-        Parameters(p): Parameters<EmbeddingParams>,
+        Parameters(p): Parameters<ResponseIdParams>,
+        ctx: RequestContext<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
-        debug!(model = ?p.model, "embedding tool called");
-        let input: Value = serde_json::from_str(&p.input).map_err(|e| {
-            McpError::invalid_params(
-                format!("Invalid input JSON (must be a quoted string or array of strings): {e}"),
+        debug!(response_id = %p.response_id, "get_response tool called");
+        Self::validate_id("response_id", &p.response_id)?;
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
+
+        match self
+            .client
+            .request_with::<(), ResponsesResponse>(
+                Method::GET,
+                &format!("/responses/{}", p.response_id),
                 None,
+                &opts,
             )
-        })?;
+            .await
+        {
+            Ok(resp) => Ok(Self::responses_result(&resp)),
+            Err(e) => Ok(Self::api_error_result(&e)),
+        }
+    }
 
-        let req = EmbeddingRequest {
-            model: p.model.unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.into()),
-            input,
-        };
+    #[tool(
+        description = "Delete a stored Responses API result by its response_id. \
+                           It can no longer be continued with previous_response_id."
+    )]
+    async fn delete_response(
+        &self,
+        Parameters(p): Parameters<ResponseIdParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        debug!(response_id = %p.response_id, "delete_response tool called");
+        Self::validate_id("response_id", &p.response_id)?;
+        let opts = Self::request_options(&ctx, p.timeout_secs)?;
 
         match self
             .client
-            .request::<_, EmbeddingResponse>(Method::POST, "/embeddings", Some(&req))
+            .request_with::<(), ResponseDeleted>(
+                Method::DELETE,
+                &format!("/responses/{}", p.response_id),
+                None,
+                &opts,
+            )
             .await
         {
-            Ok(resp) => Ok(CallToolResult::success(vec![Content::text(
-                resp.to_string(),
-            )])),
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(e.to_string())])),
+            Ok(resp) => {
+                let text = if resp.deleted {
+                    format!("Deleted response {}.", resp.id)
+                } else {
+                    format!("Response {} was not deleted.", resp.id)
+                };
+                let mut result = CallToolResult::success(vec![Content::text(text)]);
+                result.structured_content = Some(serde_json::json!({
+                    "response_id": resp.id,
+                    "deleted": resp.deleted,
+                }));
+                Ok(result)
+            }
+            Err(e) => Ok(Self::api_error_result(&e)),
         }
     }
 
     #[tool(description = "List all available Grok models and their IDs.")]
-    async fn list_models(&self) -> Result<CallToolResult, McpError> {
+    async fn list_models(
+        &self,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
         // Check cache first
         if let Some(cached) = self.models_cache.get(&()).await {
             debug!("list_models: returning cached result");
@@ -358,7 +3048,12 @@ impl GrokServer {
         debug!("list_models: fetching from API");
         match self
             .client
-            .request::<(), ModelsResponse>(Method::GET, "/models", None)
+            .request_with::<(), ModelsResponse>(
+                Method::GET,
+                "/models",
+                None,
+                &Self::request_options(&ctx, None)?,
+            )
             .await
         {
             Ok(resp) => {
@@ -374,7 +3069,7 @@ impl GrokServer {
                 self.models_cache.insert((), result.clone()).await;
                 Ok(CallToolResult::success(vec![Content::text(result)]))
             }
-            Err(e) => Ok(CallToolResult::error(vec![Content::text(e.to_string())])),
+            Err(e) => Ok(Self::api_error_result(&e)),
         }
     }
 }
@@ -389,8 +3084,12 @@ impl ServerHandler for GrokServer {
         ServerInfo::new(ServerCapabilities::builder().enable_tools().build())
             .with_server_info(Implementation::new("grok-chat", env!("CARGO_PKG_VERSION")))
             .with_instructions(
-                "xAI Grok MCP server. Tools: chat, chat_with_vision, chat_with_search, \
-                 embedding, list_models.",
+                "xAI Grok MCP server. Tools: chat, chat_batch, chat_deferred_submit, \
+                 chat_deferred_result, create_session, list_sessions, get_session, \
+                 reset_session, delete_session, chat_with_vision, chat_with_search, \
+                 get_response, delete_response, embedding, similarity, index_documents, \
+                 search_index, chat_with_documents, list_collections, delete_documents, \
+                 delete_collection, summarize, generate_image, count_tokens, list_models.",
             )
     }
 }
@@ -473,6 +3172,165 @@ mod tests {
         assert!(result.unwrap_err().contains("Invalid role 'hacker'"));
     }
 
+    #[test]
+    fn build_messages_accepts_multimodal_history() {
+        let history = r#"[{"role": "user", "content": [
+            {"type": "text", "text": "look"},
+            {"type": "image_url", "image_url": {"url": "https://x.test/a.png", "detail": "high"}}
+        ]}, {"role": "assistant", "content": "a cat"}]"#;
+        let msgs = GrokServer::build_messages(None, Some(history), "what colour?").unwrap();
+        assert_eq!(msgs.len(), 3);
+        assert!(msgs[0].content.as_ref().unwrap().has_images());
+    }
+
+    #[test]
+    fn build_messages_rejects_malformed_message() {
+        let history = r#"[{"role": "user", "content": "hi"}, {"role": "tool", "content": "42"}]"#;
+        let err = GrokServer::build_messages(None, Some(history), "hello").unwrap_err();
+        assert!(err.contains("index 1"), "{err}");
+        assert!(err.contains("tool_call_id"), "{err}");
+
+        let history = r#"[{"role": "user", "content": [{"type": "audio"}]}]"#;
+        let err = GrokServer::build_messages(None, Some(history), "hello").unwrap_err();
+        assert!(err.contains("Invalid messages JSON"), "{err}");
+    }
+
+    #[test]
+    fn build_messages_tool_results_need_no_prompt() {
+        let history = r#"[
+            {"role": "user", "content": "Weather in Paris?"},
+            {"role": "assistant", "tool_calls": [{"id": "call_1", "type": "function",
+                "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}}]},
+            {"role": "tool", "tool_call_id": "call_1", "content": "18C, sunny"}
+        ]"#;
+        let msgs = GrokServer::build_messages(None, Some(history), "").unwrap();
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[2].tool_call_id.as_deref(), Some("call_1"));
+
+        let msgs = GrokServer::build_messages(None, Some(history), "Thanks!").unwrap();
+        assert_eq!(msgs.len(), 4);
+    }
+
+    // -- structured output ----------------------------------------------------
+
+    fn completion(content: &str, finish_reason: &str) -> ChatResponse {
+        serde_json::from_value(serde_json::json!({
+            "choices": [{
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": finish_reason
+            }],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 5, "total_tokens": 10}
+        }))
+        .unwrap()
+    }
+
+    fn city_schema() -> Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {"city": {"type": "string"}, "population": {"type": "integer"}},
+            "required": ["city", "population"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn structured_result_returns_validated_json() {
+        let output = r#"{"city": "Paris", "population": 2102650}"#;
+        let result = GrokServer::structured_result(&completion(output, "stop"), &city_schema());
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(result.content[0].as_text().unwrap().text, output);
+        assert_eq!(result.structured_content.unwrap()["city"], "Paris");
+    }
+
+    #[test]
+    fn structured_result_reports_schema_violations() {
+        let output = r#"{"city": "Paris", "population": "2.1M"}"#;
+        let result = GrokServer::structured_result(&completion(output, "stop"), &city_schema());
+        assert_eq!(result.is_error, Some(true));
+        let error = &result.structured_content.unwrap()["error"];
+        assert_eq!(error["code"], "invalid_output");
+        assert_eq!(error["retryable"], false);
+        assert_eq!(
+            error["violations"][0],
+            "$.population: expected integer, got string"
+        );
+        assert_eq!(error["output"], output);
+
+        let result = GrokServer::structured_result(
+            &completion(r#"{"city": "Par"#, "length"),
+            &city_schema(),
+        );
+        let text = result.content[0].as_text().unwrap().text.clone();
+        assert!(text.contains("not valid JSON (it was cut off"), "{text}");
+    }
+
+    // -- function calling -----------------------------------------------------
+
+    #[test]
+    fn parse_tools_accepts_wrapped_and_bare_functions() {
+        let tools = GrokServer::parse_tools(
+            r#"[
+                {"type": "function", "function": {"name": "weather",
+                    "parameters": {"type": "object"}}},
+                {"name": "time", "description": "Current time"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(
+            tools[1],
+            ToolDefinition::function("time", Some("Current time"), None)
+        );
+
+        for (bad, expected) in [
+            ("[]", "at least one"),
+            (r#"[{"description": "no name"}]"#, "tools[0]"),
+            (
+                r#"[{"type": "web_search", "function": {"name": "x"}}]"#,
+                "unsupported type",
+            ),
+            ("nope", "Invalid tools JSON"),
+        ] {
+            let err = GrokServer::parse_tools(bad).unwrap_err();
+            assert!(err.contains(expected), "{bad}: {err}");
+        }
+    }
+
+    #[test]
+    fn parse_tool_choice_modes_names_and_json() {
+        assert_eq!(
+            GrokServer::parse_tool_choice("required"),
+            Ok(ToolChoice::Required)
+        );
+        assert_eq!(
+            GrokServer::parse_tool_choice("weather"),
+            Ok(ToolChoice::Function {
+                name: "weather".into()
+            })
+        );
+        assert_eq!(
+            GrokServer::parse_tool_choice(r#"{"type": "function", "function": {"name": "w"}}"#),
+            Ok(ToolChoice::Function { name: "w".into() })
+        );
+        assert!(GrokServer::parse_tool_choice(" ").is_err());
+    }
+
+    #[test]
+    fn chat_result_exposes_tool_calls() {
+        let resp: ChatResponse = serde_json::from_str(
+            r#"{"choices": [{"message": {"role": "assistant", "content": null,
+                "tool_calls": [{"id": "call_1", "type": "function",
+                    "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}}]},
+                "finish_reason": "tool_calls"}]}"#,
+        )
+        .unwrap();
+        let result = GrokServer::chat_result(&resp);
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["finish_reason"], "tool_calls");
+        assert_eq!(structured["tool_calls"][0]["id"], "call_1");
+        assert_eq!(structured["tool_calls"][0]["arguments"]["city"], "Paris");
+    }
+
     // -- build_chat_request ---------------------------------------------------
 
     #[test]
@@ -500,6 +3358,734 @@ mod tests {
         assert!(result.is_err());
     }
 
+    // -- api_error_result -----------------------------------------------------
+
+    #[test]
+    fn api_error_result_carries_code_and_detail() {
+        let err = ApiError::from_response(
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+            r#"{"error": {"message": "slow down", "type": "rate_limit_error"}}"#,
+            Some(Duration::from_secs(7)),
+        );
+        let result = GrokServer::api_error_result(&err);
+        assert_eq!(result.is_error, Some(true));
+
+        let error = &result.structured_content.unwrap()["error"];
+        assert_eq!(error["code"], "rate_limited");
+        assert_eq!(error["status"], 429);
+        assert_eq!(error["message"], "slow down");
+        assert_eq!(error["type"], "rate_limit_error");
+        assert_eq!(error["retryable"], true);
+        assert_eq!(error["retry_after_secs"], 7.0);
+
+        let text = result.content[0].as_text().unwrap().text.clone();
+        assert!(text.starts_with("[rate_limited]"), "{text}");
+    }
+
+    #[test]
+    fn api_error_result_model_not_found_hint() {
+        let err = ApiError::from_response(
+            reqwest::StatusCode::NOT_FOUND,
+            r#"{"code": "Some requested entity was not found", "error": "The model grok-9 does not exist"}"#,
+            None,
+        );
+        let result = GrokServer::api_error_result(&err);
+        let error = &result.structured_content.unwrap()["error"];
+        assert_eq!(error["code"], "model_not_found");
+        assert_eq!(error["retryable"], false);
+        let text = result.content[0].as_text().unwrap().text.clone();
+        assert!(text.contains("list_models"), "{text}");
+    }
+
+    // -- vision_message -------------------------------------------------------
+
+    #[tokio::test]
+    async fn vision_message_orders_images_with_detail() {
+        let images = vec![
+            VisionImage {
+                url: "https://x.test/after.png".into(),
+                detail: Some(ImageDetail::Low),
+            },
+            VisionImage {
+                url: "https://x.test/diff.png".into(),
+                detail: None,
+            },
+        ];
+        let msg = GrokServer::vision_message(
+            &FileAccess::disabled(),
+            "compare",
+            Some("https://x.test/before.png".into()),
+            images,
+            ImageDetail::Auto,
+        )
+        .await
+        .unwrap();
+
+        let json = serde_json::to_value(&msg).unwrap();
+        let parts = json["content"].as_array().unwrap();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0]["text"], "compare");
+        let urls: Vec<_> = parts[1..]
+            .iter()
+            .map(|p| {
+                (
+                    p["image_url"]["url"].as_str().unwrap(),
+                    p["image_url"]["detail"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                ("https://x.test/before.png", "auto"),
+                ("https://x.test/after.png", "low"),
+                ("https://x.test/diff.png", "auto"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn vision_message_reports_bad_image_by_field() {
+        let files = FileAccess::disabled();
+        let err = GrokServer::vision_message(&files, "p", None, vec![], ImageDetail::High)
+            .await
+            .unwrap_err();
+        assert!(err.contains("provide image_url"), "{err}");
+
+        let images = vec![
+            VisionImage {
+                url: "https://x.test/ok.png".into(),
+                detail: None,
+            },
+            VisionImage {
+                url: "/tmp/not-allowed.png".into(),
+                detail: None,
+            },
+        ];
+        let err = GrokServer::vision_message(&files, "p", None, images, ImageDetail::High)
+            .await
+            .unwrap_err();
+        assert!(err.starts_with("images[1]:"), "{err}");
+    }
+
+    // -- preflight ------------------------------------------------------------
+
+    #[test]
+    fn context_length_prefers_config_then_longest_prefix() {
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), "http://x".into()))
+            .with_context_lengths(HashMap::from([("grok-4.3".to_string(), 1_000)]));
+        assert_eq!(server.context_length("grok-4.3"), Some(1_000));
+        assert_eq!(
+            server.context_length("grok-4-fast-reasoning"),
+            Some(2_000_000)
+        );
+        assert_eq!(server.context_length("grok-4-0709"), Some(256_000));
+        assert_eq!(server.context_length("mystery-model"), None);
+    }
+
+    #[test]
+    fn messages_text_flattens_roles_and_text_parts() {
+        let msgs = vec![
+            ChatMessage::system("be brief"),
+            ChatMessage::user_with_image("what is this?", "https://x.test/a.png", "low"),
+        ];
+        assert_eq!(
+            GrokServer::messages_text(&msgs),
+            "system: be brief\nuser: what is this?"
+        );
+    }
+
+    #[test]
+    fn tool_error_matches_api_error_shape() {
+        let result = GrokServer::tool_error(ApiErrorKind::ContextLengthExceeded, "too long");
+        assert_eq!(result.is_error, Some(true));
+        let text = result.content[0].as_text().unwrap().text.clone();
+        assert_eq!(text, "[context_length_exceeded] too long");
+        let error = &result.structured_content.unwrap()["error"];
+        assert_eq!(error["code"], "context_length_exceeded");
+        assert_eq!(error["retryable"], false);
+    }
+
+    // -- image_result ---------------------------------------------------------
+
+    #[test]
+    fn image_result_inlines_base64_and_lists_urls() {
+        let resp: ImageGenerationResponse = serde_json::from_value(serde_json::json!({
+            "data": [
+                {"b64_json": "iVBORw0KGgoAAAANSUhEUg==", "revised_prompt": "a red fox, studio light"},
+                {"url": "https://imgen.x.ai/fox.jpg"}
+            ]
+        }))
+        .unwrap();
+        let result = GrokServer::image_result(resp);
+
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(result.content.len(), 3);
+        let image = result.content[0].as_image().unwrap();
+        assert_eq!(image.mime_type, "image/png");
+        assert_eq!(
+            result.content[1].as_text().unwrap().text,
+            "Revised prompt 1: a red fox, studio light"
+        );
+        assert_eq!(
+            result.content[2].as_text().unwrap().text,
+            "Image 2: https://imgen.x.ai/fox.jpg"
+        );
+    }
+
+    #[test]
+    fn image_result_without_images_is_error() {
+        let resp = ImageGenerationResponse { data: vec![] };
+        assert_eq!(GrokServer::image_result(resp).is_error, Some(true));
+    }
+
+    #[test]
+    fn sniff_image_mime_defaults_to_jpeg() {
+        assert_eq!(
+            GrokServer::sniff_image_mime("/9j/4AAQSkZJRg=="),
+            "image/jpeg"
+        );
+        assert_eq!(
+            GrokServer::sniff_image_mime("R0lGODlhAQABAAAAACw="),
+            "image/gif"
+        );
+        assert_eq!(GrokServer::sniff_image_mime("not base64!"), "image/jpeg");
+    }
+
+    // -- validate_id ----------------------------------------------------------
+
+    #[test]
+    fn validate_id_rejects_path_characters() {
+        assert!(GrokServer::validate_id("response_id", "resp_01J.abc-9").is_ok());
+        for bad in ["", "../models", "a/b", "id?x=1", "id with space"] {
+            assert!(
+                GrokServer::validate_id("response_id", bad).is_err(),
+                "{bad}"
+            );
+        }
+    }
+
+    // -- sessions -------------------------------------------------------------
+
+    #[tokio::test]
+    async fn prepare_chat_inserts_session_history_before_prompt() {
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), "http://x".into()));
+        let id = server
+            .sessions
+            .create(None, Some("Be terse.".into()))
+            .unwrap()
+            .id;
+        server
+            .sessions
+            .append(
+                &id,
+                [ChatMessage::user("Hi"), ChatMessage::assistant("Hello")],
+            )
+            .unwrap();
+
+        let p: ChatParams = serde_json::from_value(serde_json::json!({
+            "prompt": "And again?",
+            "session_id": id,
+        }))
+        .unwrap();
+        let session = server
+            .chat_session(p.session_id.as_deref(), p.messages.as_deref())
+            .unwrap();
+        let req = server
+            .prepare_chat(
+                &p,
+                DEFAULT_MODEL,
+                &RequestOptions::default(),
+                session.as_ref(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let turns: Vec<_> = req
+            .messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_ref().unwrap().text()))
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                ("system", "Be terse.".to_string()),
+                ("user", "Hi".to_string()),
+                ("assistant", "Hello".to_string()),
+                ("user", "And again?".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn chat_session_rejects_messages_and_unknown_ids() {
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), "http://x".into()));
+        assert!(server.chat_session(None, Some("[]")).unwrap().is_none());
+        assert!(server.chat_session(Some("nope"), None).is_err());
+        let id = server.sessions.create(None, None).unwrap().id;
+        assert!(server.chat_session(Some(&id), Some("[]")).is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_structured_reply_is_not_recorded() {
+        let mut xai = mockito::Server::new_async().await;
+        let mock = xai
+            .mock("POST", "/chat/completions")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"choices": [{"message": {"role": "assistant", "content": "{\"city\": 7}"},
+                    "finish_reason": "stop"}]}"#,
+            )
+            .create_async()
+            .await;
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), xai.url()));
+        let id = server.sessions.create(None, None).unwrap().id;
+
+        let schema = city_schema().to_string();
+        let req = GrokServer::build_chat_request(
+            None,
+            vec![ChatMessage::user("Where?")],
+            None,
+            None,
+            Some(&schema),
+            None,
+        )
+        .unwrap();
+        let result = server
+            .do_chat(req, &RequestOptions::default(), None, Some(&id))
+            .await
+            .unwrap();
+
+        assert_eq!(result.is_error, Some(true));
+        assert!(server.sessions.get(&id).unwrap().messages.is_empty());
+        mock.assert_async().await;
+    }
+
+    // -- deferred tracking ----------------------------------------------------
+
+    #[test]
+    fn pending_deferred_lists_oldest_first_and_drops_expired() {
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), "http://x".into()));
+        server.track_deferred("new".into(), "grok-4.3".into(), None);
+        {
+            let mut pending = server.lock_deferred();
+            let now = Instant::now();
+            for (id, age) in [("old", 60), ("expired", DEFERRED_TTL.as_secs() + 1)] {
+                pending.insert(
+                    id.into(),
+                    PendingDeferred {
+                        model: "grok-4.3".into(),
+                        submitted: now - Duration::from_secs(age),
+                        schema: None,
+                    },
+                );
+            }
+        }
+
+        let ids: Vec<_> = server
+            .pending_deferred()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, ["old", "new"]);
+        assert!(!server.lock_deferred().contains_key("expired"));
+    }
+
+    // -- embedding output ----------------------------------------------------
+
+    fn embeddings() -> EmbeddingResponse {
+        serde_json::from_value(serde_json::json!({
+            "data": [
+                {"index": 1, "embedding": [3.0, 4.0]},
+                {"index": 0, "embedding": [1.0, 2.0]}
+            ],
+            "usage": {"prompt_tokens": 4, "total_tokens": 4}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn embedding_result_full_formats_are_structured() {
+        let json = GrokServer::embedding_result("m", embeddings(), EmbeddingOutput::Json);
+        let structured = json.structured_content.unwrap();
+        assert_eq!(
+            structured["embeddings"][1]["embedding"],
+            serde_json::json!([1.0, 2.0])
+        );
+        assert_eq!(structured["usage"]["total_tokens"], 4);
+
+        let packed = GrokServer::embedding_result("m", embeddings(), EmbeddingOutput::Base64);
+        let entry = &packed.structured_content.unwrap()["embeddings"][0];
+        assert_eq!(entry["dimensions"], 2);
+        let decoded = vectors::from_base64(entry["base64"].as_str().unwrap()).unwrap();
+        assert_eq!(decoded, [3.0, 4.0]);
+
+        let preview = GrokServer::embedding_result("m", embeddings(), EmbeddingOutput::Preview);
+        assert!(preview.structured_content.is_none());
+    }
+
+    #[tokio::test]
+    async fn embed_texts_batches_large_inputs() {
+        let body = |count: usize| {
+            let data: Vec<Value> = (0..count)
+                .map(|i| serde_json::json!({"index": i, "embedding": [i as f32]}))
+                .collect();
+            serde_json::json!({
+                "data": data,
+                "usage": {"prompt_tokens": count, "total_tokens": count}
+            })
+            .to_string()
+        };
+        let mut xai = mockito::Server::new_async().await;
+        let first = xai
+            .mock("POST", "/embeddings")
+            .match_body(mockito::Matcher::Regex(r#"\["t0","#.into()))
+            .with_header("content-type", "application/json")
+            .with_body(body(EMBED_BATCH_SIZE))
+            .create_async()
+            .await;
+        let second = xai
+            .mock("POST", "/embeddings")
+            .match_body(mockito::Matcher::Regex(format!(
+                r#"\["t{EMBED_BATCH_SIZE}","#
+            )))
+            .with_header("content-type", "application/json")
+            .with_body(body(6))
+            .create_async()
+            .await;
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), xai.url()));
+
+        let texts = (0..EMBED_BATCH_SIZE + 6).map(|i| format!("t{i}")).collect();
+        let Ok(resp) = server
+            .embed_texts("m", texts, &RequestOptions::default())
+            .await
+        else {
+            panic!("embedding should succeed");
+        };
+
+        assert_eq!(resp.data.len(), EMBED_BATCH_SIZE + 6);
+        assert!(resp.data.iter().enumerate().all(|(i, d)| d.index == i));
+        assert_eq!(resp.data[EMBED_BATCH_SIZE].embedding, [0.0]);
+        assert_eq!(resp.usage.unwrap().total_tokens, 70);
+        first.assert_async().await;
+        second.assert_async().await;
+    }
+
+    #[test]
+    fn similarity_result_ranks_best_first() {
+        let resp: EmbeddingResponse = serde_json::from_value(serde_json::json!({
+            "data": [
+                {"index": 0, "embedding": [1.0, 0.0]},
+                {"index": 1, "embedding": [0.0, 1.0]},
+                {"index": 2, "embedding": [0.9, 0.1]},
+                {"index": 3, "embedding": [0.6, 0.4]}
+            ]
+        }))
+        .unwrap();
+        let candidates = ["far".to_string(), "near".into(), "middle".into()];
+
+        let result = GrokServer::similarity_result("m", &candidates, &resp, Some(2));
+        let structured = result.structured_content.unwrap();
+        let texts: Vec<&str> = structured["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(texts, ["near", "middle"]);
+        assert_eq!(structured["results"][0]["index"], 1);
+        let text = result.content[0].as_text().unwrap().text.clone();
+        assert!(text.starts_with("1. 0.99"), "{text}");
+    }
+
+    #[test]
+    fn hits_result_numbers_passages_with_metadata() {
+        let mut metadata = serde_json::Map::new();
+        metadata.insert("path".into(), "/docs/a.md".into());
+        let hits = [Hit {
+            document_id: "a".into(),
+            chunk: 2,
+            score: 0.5,
+            text: "Chunk text.".into(),
+            metadata,
+        }];
+        let result = GrokServer::hits_result("notes", &hits);
+        let text = result.content[0].as_text().unwrap().text.clone();
+        assert_eq!(text, "1. a #2 (score 0.5000)\nChunk text.");
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["results"][0]["metadata"]["path"], "/docs/a.md");
+
+        let empty = GrokServer::hits_result("notes", &[]);
+        assert!(
+            empty.content[0]
+                .as_text()
+                .unwrap()
+                .text
+                .contains("No chunks")
+        );
+    }
+
+    // -- chat_batch -----------------------------------------------------------
+
+    #[test]
+    fn batch_items_keep_order_and_report_errors() {
+        let schema = city_schema();
+        let ok = GrokServer::batch_item(
+            0,
+            Ok(completion(
+                r#"{"city": "Paris", "population": 2102650}"#,
+                "stop",
+            )),
+            Some(&schema),
+        );
+        let invalid = GrokServer::batch_item(
+            1,
+            Ok(completion(r#"{"city": "Paris"}"#, "stop")),
+            Some(&schema),
+        );
+        let limited = GrokServer::batch_item(
+            2,
+            Err(ApiError::from_response(
+                reqwest::StatusCode::TOO_MANY_REQUESTS,
+                r#"{"error": {"message": "slow down"}}"#,
+                None,
+            )),
+            None,
+        );
+        assert_eq!(ok.0["output"]["city"], "Paris");
+        assert_eq!(invalid.0["error"]["code"], "invalid_output");
+        assert_eq!(limited.0["error"]["code"], "rate_limited");
+        assert!(limited.1.is_none());
+
+        let usage = Usage {
+            prompt_tokens: 3,
+            completion_tokens: 2,
+            total_tokens: 5,
+        };
+        let result = GrokServer::batch_result(vec![ok.0, invalid.0, limited.0], usage);
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["succeeded"], 1);
+        assert_eq!(structured["failed"], 2);
+        let text = result.content[0].as_text().unwrap().text.clone();
+        assert!(text.starts_with("[0] {"), "{text}");
+        assert!(text.contains("\n\n[2] error [rate_limited] "), "{text}");
+        assert!(
+            text.ends_with("[1 succeeded, 2 failed, tokens: 3 prompt + 2 completion = 5 total]")
+        );
+    }
+
+    // -- summarize ------------------------------------------------------------
+
+    #[test]
+    fn tokenize_windows_cover_text_and_break_at_whitespace() {
+        let text = "alpha beta gamma delta épsilon";
+        let windows = GrokServer::tokenize_windows(text, 12);
+        assert_eq!(windows.concat(), text);
+        assert_eq!(windows[0], "alpha beta ");
+        assert!(windows.iter().all(|w| w.len() <= 12));
+        // No whitespace in the second half: cut at the last char boundary.
+        assert_eq!(GrokServer::tokenize_windows("ééé", 5), ["éé", "é"]);
+        assert!(GrokServer::tokenize_windows("", 5).is_empty());
+    }
+
+    #[test]
+    fn group_parts_merges_at_least_two_per_group() {
+        let parts = |n: usize| (0..n).map(|i| format!("part {i}")).collect::<Vec<_>>();
+        let sizes = |groups: Vec<Vec<String>>| groups.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes(GrokServer::group_parts(parts(5), 4)), [2, 3]);
+        assert_eq!(sizes(GrokServer::group_parts(parts(4), 100)), [4]);
+        assert_eq!(sizes(GrokServer::group_parts(parts(2), 1)), [2]);
+    }
+
+    #[tokio::test]
+    async fn map_reduce_merges_parts_until_they_fit() {
+        let mut xai = mockito::Server::new_async().await;
+        let mock = xai
+            .mock("POST", "/chat/completions")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"choices": [{"message": {"role": "assistant", "content": "partial summary"},
+                    "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12}}"#,
+            )
+            .expect(5)
+            .create_async()
+            .await;
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), xai.url()));
+        let opts = RequestOptions::default();
+        let job = SummaryJob {
+            model: "grok-4.3",
+            style: SummaryStyle::Bullets,
+            length: SummaryLength::Short,
+            focus: None,
+            temperature: None,
+            budget: 10,
+            preflight: false,
+            opts: &opts,
+            progress: None,
+            done: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+        };
+
+        // Three parts, one merge round (their summaries exceed the budget),
+        // then the final summary.
+        let chunks = ["one".to_string(), "two".into(), "three".into()];
+        let Ok(summary) = server.map_reduce(&job, &chunks).await else {
+            panic!("summary should succeed");
+        };
+        assert_eq!(summary.text, "partial summary");
+        assert_eq!(summary.usage.total_tokens, 60);
+        assert!(summary.failed_parts.is_empty());
+        assert_eq!(job.done.load(Ordering::Relaxed), 5);
+        assert_eq!(job.total.load(Ordering::Relaxed), 5);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn map_reduce_reports_failed_parts_and_keeps_the_rest() {
+        let mut xai = mockito::Server::new_async().await;
+        let failing = xai
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::Regex("part 2 of 3".into()))
+            .with_status(400)
+            .with_body(r#"{"error": {"message": "bad part"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let ok = xai
+            .mock("POST", "/chat/completions")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"choices": [{"message": {"role": "assistant", "content": "partial summary"},
+                    "finish_reason": "stop"}]}"#,
+            )
+            .expect(3)
+            .create_async()
+            .await;
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), xai.url()));
+        let opts = RequestOptions::default();
+        let job = SummaryJob {
+            model: "grok-4.3",
+            style: SummaryStyle::Paragraph,
+            length: SummaryLength::Medium,
+            focus: None,
+            temperature: None,
+            budget: 1000,
+            preflight: false,
+            opts: &opts,
+            progress: None,
+            done: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+        };
+
+        let chunks = ["one".to_string(), "two".into(), "three".into()];
+        let Ok(summary) = server.map_reduce(&job, &chunks).await else {
+            panic!("the other parts should still be summarised");
+        };
+        assert_eq!(summary.text, "partial summary");
+        assert_eq!(summary.failed_parts.len(), 1);
+        assert_eq!(summary.failed_parts[0].0, 2);
+        assert_eq!(summary.failed_parts[0].1["code"], "invalid_request");
+        failing.assert_async().await;
+        ok.assert_async().await;
+    }
+
+    fn hit(document_id: &str, chunk: usize, text: &str) -> Hit {
+        Hit {
+            document_id: document_id.into(),
+            chunk,
+            score: 0.5,
+            text: text.into(),
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn select_context_skips_chunks_over_budget() {
+        let hits = vec![
+            hit("a", 0, &"x".repeat(40)),
+            hit("b", 0, &"x".repeat(400)),
+            hit("c", 0, &"x".repeat(20)),
+        ];
+        // 40 characters ~ 10 tokens + overhead; the 100-token chunk does not fit.
+        let kept = GrokServer::select_context(hits, 30);
+        let ids: Vec<&str> = kept.iter().map(|h| h.document_id.as_str()).collect();
+        assert_eq!(ids, ["a", "c"]);
+    }
+
+    #[test]
+    fn documents_result_lists_sources_and_citations() {
+        let sources = [hit("a.md", 0, "Paris."), hit("b.md", 3, "Lyon.")];
+        let system = GrokServer::documents_system_prompt(Some("Be brief."), &sources);
+        assert!(system.starts_with("Be brief.\n\nAnswer using"));
+        assert!(system.ends_with("[2] b.md #3\nLyon."));
+
+        let answer = completion("It is Paris [1].", "stop");
+        let result = GrokServer::documents_result(&answer, &sources);
+        let listing = result.content[1].as_text().unwrap().text.clone();
+        assert!(
+            listing.contains("[1] a.md #0 (score 0.5000)\n"),
+            "{listing}"
+        );
+        assert!(listing.ends_with("[2] b.md #3 (score 0.5000) — not cited"));
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["sources"][0]["cited"], true);
+        assert_eq!(structured["sources"][1]["cited"], false);
+        // The answer is the model's reply alone, without the rendered trailers.
+        assert_eq!(structured["answer"], "It is Paris [1].");
+    }
+
+    #[tokio::test]
+    async fn write_embeddings_orders_rows_and_labels_jsonl() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = GrokServer::new(XaiClient::with_base_url("k".into(), "http://x".into()))
+            .with_file_access(FileAccess::new([dir.path()], 1024).unwrap());
+        let req = EmbeddingRequest::new("m", serde_json::json!(["a", "b"]));
+
+        let npy = dir.path().join("out.npy");
+        let result = server
+            .write_embeddings(
+                &req,
+                embeddings(),
+                EmbeddingOutput::Npy,
+                npy.to_str().unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.structured_content.unwrap()["count"], 2);
+        let bytes = std::fs::read(&npy).unwrap();
+        assert_eq!(
+            &bytes[bytes.len() - 16..bytes.len() - 12],
+            &1.0f32.to_le_bytes()
+        );
+
+        let jsonl = dir.path().join("out.jsonl");
+        server
+            .write_embeddings(
+                &req,
+                embeddings(),
+                EmbeddingOutput::Jsonl,
+                jsonl.to_str().unwrap(),
+            )
+            .await
+            .unwrap();
+        let text = std::fs::read_to_string(&jsonl).unwrap();
+        let first: Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+        assert_eq!(first["index"], 0);
+        assert_eq!(first["text"], "a");
+
+        let outside = server
+            .write_embeddings(
+                &req,
+                embeddings(),
+                EmbeddingOutput::Npy,
+                "/tmp/../etc/x.npy",
+            )
+            .await;
+        assert!(outside.is_err());
+    }
+
     // -- search_tools ---------------------------------------------------------
 
     #[test]
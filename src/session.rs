@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use thiserror::Error;
+
+use crate::api::ChatMessage;
+
+/// Default cap on the messages kept per session.
+pub const DEFAULT_MAX_MESSAGES: usize = 100;
+
+/// Default cap on live sessions; creating one more evicts the least recently used.
+pub const DEFAULT_MAX_SESSIONS: usize = 100;
+
+/// Errors from looking up or creating a session.
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("unknown session '{0}' — create it with create_session")]
+    NotFound(String),
+
+    #[error("session '{0}' already exists")]
+    Exists(String),
+
+    #[error("session ids may only contain letters, digits, '-', '_' or '.', got '{0}'")]
+    InvalidId(String),
+}
+
+/// A conversation whose history is kept by the server between tool calls.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    /// Sent ahead of the history on every turn unless the call supplies its own.
+    pub system_prompt: Option<String>,
+    /// User and assistant turns, oldest first.
+    pub messages: Vec<ChatMessage>,
+    /// How many of the oldest messages have been dropped to stay within the limit.
+    pub trimmed: usize,
+    pub created: Instant,
+    pub last_used: Instant,
+}
+
+impl Session {
+    fn new(id: String, system_prompt: Option<String>) -> Self {
+        let now = Instant::now();
+        Self {
+            id,
+            system_prompt,
+            messages: Vec::new(),
+            trimmed: 0,
+            created: now,
+            last_used: now,
+        }
+    }
+
+    /// Drop the oldest messages until at most `max` remain and the history
+    /// starts with a user message, so tool results never lose their call.
+    fn trim(&mut self, max: usize) {
+        let excess = self.messages.len().saturating_sub(max);
+        let start = self.messages[excess..]
+            .iter()
+            .position(|m| m.role == "user")
+            .map_or(self.messages.len(), |i| excess + i);
+        self.messages.drain(..start);
+        self.trimmed += start;
+    }
+}
+
+/// In-memory conversation sessions, shared by all tool calls of one server
+/// process. History and the number of sessions are both bounded.
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    max_messages: usize,
+    max_sessions: usize,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGES, DEFAULT_MAX_SESSIONS)
+    }
+}
+
+impl SessionStore {
+    pub fn new(max_messages: usize, max_sessions: usize) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            max_messages,
+            max_sessions,
+        }
+    }
+
+    /// Start a session, with a generated id unless one is given.
+    pub fn create(
+        &self,
+        id: Option<String>,
+        system_prompt: Option<String>,
+    ) -> Result<Session, SessionError> {
+        let mut sessions = self.lock();
+        let id = match id {
+            Some(id) => {
+                if !valid_id(&id) {
+                    return Err(SessionError::InvalidId(id));
+                }
+                if sessions.contains_key(&id) {
+                    return Err(SessionError::Exists(id));
+                }
+                id
+            }
+            None => loop {
+                let id = format!("s-{:012x}", fastrand::u64(..1 << 48));
+                if !sessions.contains_key(&id) {
+                    break id;
+                }
+            },
+        };
+
+        if sessions.len() >= self.max_sessions
+            && let Some(oldest) = sessions
+                .values()
+                .min_by_key(|s| s.last_used)
+                .map(|s| s.id.clone())
+        {
+            tracing::debug!(session = %oldest, "evicting least recently used session");
+            sessions.remove(&oldest);
+        }
+
+        let session = Session::new(id.clone(), system_prompt);
+        sessions.insert(id, session.clone());
+        Ok(session)
+    }
+
+    /// A copy of the session, marking it as used.
+    pub fn get(&self, id: &str) -> Result<Session, SessionError> {
+        let mut sessions = self.lock();
+        let session = sessions
+            .get_mut(id)
+            .ok_or_else(|| SessionError::NotFound(id.into()))?;
+        session.last_used = Instant::now();
+        Ok(session.clone())
+    }
+
+    /// All sessions, most recently used first.
+    pub fn list(&self) -> Vec<Session> {
+        let mut list: Vec<_> = self.lock().values().cloned().collect();
+        list.sort_by_key(|s| std::cmp::Reverse(s.last_used));
+        list
+    }
+
+    /// Append a completed turn, trimming the oldest history past the limit.
+    pub fn append(
+        &self,
+        id: &str,
+        turn: impl IntoIterator<Item = ChatMessage>,
+    ) -> Result<(), SessionError> {
+        let mut sessions = self.lock();
+        let session = sessions
+            .get_mut(id)
+            .ok_or_else(|| SessionError::NotFound(id.into()))?;
+        session.messages.extend(turn);
+        session.trim(self.max_messages);
+        session.last_used = Instant::now();
+        Ok(())
+    }
+
+    /// Clear a session's history, keeping its id and system prompt.
+    pub fn reset(&self, id: &str) -> Result<(), SessionError> {
+        let mut sessions = self.lock();
+        let session = sessions
+            .get_mut(id)
+            .ok_or_else(|| SessionError::NotFound(id.into()))?;
+        session.messages.clear();
+        session.trimmed = 0;
+        session.last_used = Instant::now();
+        Ok(())
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), SessionError> {
+        self.lock()
+            .remove(id)
+            .map(drop)
+            .ok_or_else(|| SessionError::NotFound(id.into()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        // Sessions are replaced field by field under the lock; a poisoned lock is harmless.
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_is_trimmed_to_whole_turns() {
+        let store = SessionStore::new(3, 10);
+        let id = store.create(None, None).unwrap().id;
+        for i in 0..3 {
+            let prompt = format!("q{i}");
+            store
+                .append(
+                    &id,
+                    [ChatMessage::user(&prompt), ChatMessage::assistant("a")],
+                )
+                .unwrap();
+        }
+
+        let session = store.get(&id).unwrap();
+        // Six messages, limit three: the oldest three go, then the orphaned
+        // assistant reply so the history still opens with a user turn.
+        assert_eq!(session.messages.len(), 2);
+        assert_eq!(session.messages[0].role, "user");
+        assert_eq!(session.trimmed, 4);
+
+        store.reset(&id).unwrap();
+        assert!(store.get(&id).unwrap().messages.is_empty());
+    }
+
+    #[test]
+    fn create_validates_ids_and_evicts_least_recently_used() {
+        let store = SessionStore::new(10, 2);
+        store.create(Some("a".into()), None).unwrap();
+        store
+            .create(Some("b".into()), Some("be brief".into()))
+            .unwrap();
+        assert!(matches!(
+            store.create(Some("a".into()), None),
+            Err(SessionError::Exists(_))
+        ));
+        assert!(matches!(
+            store.create(Some("../x".into()), None),
+            Err(SessionError::InvalidId(_))
+        ));
+
+        store.get("a").unwrap();
+        store.create(Some("c".into()), None).unwrap();
+        let ids: Vec<_> = store.list().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, ["c", "a"]);
+
+        store.delete("a").unwrap();
+        assert!(matches!(store.get("a"), Err(SessionError::NotFound(_))));
+    }
+}
@@ -0,0 +1,27 @@
+/// Rough number of characters (or request-body bytes) per token.
+pub const CHARS_PER_TOKEN: usize = 4;
+
+/// Estimate the tokens in a text of `len` characters, rounding up, without
+/// a round trip to the tokenizer.
+pub fn estimate_len(len: usize) -> u32 {
+    u32::try_from(len.div_ceil(CHARS_PER_TOKEN)).unwrap_or(u32::MAX)
+}
+
+/// Estimate the tokens in `text`.
+pub fn estimate(text: &str) -> u32 {
+    estimate_len(text.chars().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_round_up_by_characters() {
+        assert_eq!(estimate_len(0), 0);
+        assert_eq!(estimate_len(9), 3);
+        assert_eq!(estimate("abcd"), 1);
+        // Multi-byte characters count once.
+        assert_eq!(estimate("éééé"), 1);
+    }
+}
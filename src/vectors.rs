@@ -0,0 +1,138 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+/// Pack a vector as little-endian `f32`s and base64-encode it, the format
+/// xAI uses for `encoding_format: "base64"`.
+pub fn to_base64(vector: &[f32]) -> String {
+    let bytes: Vec<u8> = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+    BASE64.encode(bytes)
+}
+
+/// Decode a base64 string of little-endian `f32`s.
+pub fn from_base64(encoded: &str) -> Result<Vec<f32>, String> {
+    let bytes = BASE64
+        .decode(encoded)
+        .map_err(|e| format!("invalid base64 embedding: {e}"))?;
+    if bytes.len() % 4 != 0 {
+        return Err(format!(
+            "base64 embedding is {} bytes, not a whole number of f32s",
+            bytes.len()
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// Cosine similarity of two vectors, or `None` when their lengths differ or
+/// either has zero magnitude.
+pub fn cosine(a: &[f32], b: &[f32]) -> Option<f32> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f64, 0.0f64, 0.0f64);
+    for (&x, &y) in a.iter().zip(b) {
+        let (x, y) = (f64::from(x), f64::from(y));
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return None;
+    }
+    Some((dot / (norm_a.sqrt() * norm_b.sqrt())) as f32)
+}
+
+/// Score each candidate against `query` by cosine similarity and return
+/// `(candidate index, score)` pairs, best first. Candidates that cannot be
+/// compared are left out.
+pub fn rank<'a>(
+    query: &[f32],
+    candidates: impl IntoIterator<Item = &'a [f32]>,
+) -> Vec<(usize, f32)> {
+    let mut ranked: Vec<(usize, f32)> = candidates
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, v)| cosine(query, v).map(|score| (i, score)))
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    ranked
+}
+
+/// Encode equally long vectors as a 2-D little-endian `float32` NumPy
+/// `.npy` file (format version 1.0), one row per vector.
+pub fn to_npy(vectors: &[Vec<f32>]) -> Result<Vec<u8>, String> {
+    let dim = vectors.first().map_or(0, Vec::len);
+    if let Some((i, v)) = vectors.iter().enumerate().find(|(_, v)| v.len() != dim) {
+        return Err(format!(
+            "embedding {i} has {} dimensions, expected {dim}; .npy needs equal lengths",
+            v.len()
+        ));
+    }
+
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {dim}), }}",
+        vectors.len()
+    );
+    // Magic (6) + version (2) + header length (2) + header, padded with
+    // spaces and ending in a newline, must be a multiple of 64 bytes.
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    let mut out = Vec::with_capacity(10 + header.len() + vectors.len() * dim * 4);
+    out.extend_from_slice(b"\x93NUMPY\x01\x00");
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    for v in vectors.iter().flatten() {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_round_trips() {
+        let v = vec![0.5, -1.25, 3.0];
+        assert_eq!(to_base64(&[1.0]), "AACAPw==");
+        assert_eq!(from_base64(&to_base64(&v)).unwrap(), v);
+        assert!(from_base64("AACA").unwrap_err().contains("whole number"));
+    }
+
+    #[test]
+    fn rank_orders_by_cosine_and_skips_incomparable() {
+        assert_eq!(cosine(&[1.0, 0.0], &[2.0, 0.0]), Some(1.0));
+        assert_eq!(cosine(&[1.0, 0.0], &[0.0, 0.0]), None);
+        assert_eq!(cosine(&[1.0], &[1.0, 0.0]), None);
+
+        let candidates = [
+            vec![0.0, 1.0],
+            vec![1.0, 1.0],
+            vec![0.0, 0.0],
+            vec![1.0, 0.0],
+        ];
+        let ranked = rank(&[1.0, 0.0], candidates.iter().map(Vec::as_slice));
+        let order: Vec<usize> = ranked.iter().map(|&(i, _)| i).collect();
+        assert_eq!(order, [3, 1, 0]);
+        assert!((ranked[1].1 - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn npy_header_is_aligned_and_describes_shape() {
+        let npy = to_npy(&[vec![1.0, 2.0], vec![3.0, 4.0]]).unwrap();
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert!(header.contains("'shape': (2, 2)"), "{header}");
+        assert!(header.ends_with('\n'));
+        assert_eq!(npy.len(), 10 + header_len + 16);
+        assert_eq!(&npy[npy.len() - 4..], &4.0f32.to_le_bytes());
+
+        assert!(to_npy(&[vec![1.0], vec![1.0, 2.0]]).is_err());
+    }
+}
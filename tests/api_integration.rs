@@ -1,6 +1,37 @@
-use grok_chat::api::{ChatMessage, ChatRequest, ChatResponse, ModelsResponse, XaiClient};
+use grok_chat::api::{
+    ApiError, ApiErrorKind, ChatCompletionChunk, ChatMessage, ChatRequest, ChatResponse,
+    ChatStreamAccumulator, EmbeddingRequest, EmbeddingResponse, HttpOptions,
+    ImageGenerationRequest, ImageGenerationResponse, ModelsResponse, RequestOptions,
+    ResponseDeleted, ResponsesMessage, ResponsesRequest, ResponsesResponse, RetryPolicy,
+    TokenizeRequest, TokenizeResponse, XaiClient,
+};
+use grok_chat::cassette::{Cassette, CassetteError, CassetteMode};
+use grok_chat::keypool::{KeyPool, KeySelection};
+use grok_chat::ratelimit::RateLimiter;
 use mockito::{Matcher, Server};
 use reqwest::Method;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+const OK_BODY: &str = r#"{
+    "choices": [{
+        "message": {"role": "assistant", "content": "ok"},
+        "finish_reason": "stop"
+    }]
+}"#;
+
+/// A fast retry policy so tests don't sleep for real backoff intervals.
+fn fast_retry(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_secs(5),
+        jitter: 0.0,
+        retry_unsafe: true,
+    }
+}
 
 #[tokio::test]
 async fn chat_round_trip() {
@@ -48,7 +79,8 @@ async fn http_error_returns_api_error() {
         .create_async()
         .await;
 
-    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let client = XaiClient::with_base_url("test-key".into(), server.url())
+        .with_retry_policy(RetryPolicy::none());
     let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
     let result = client
         .request::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req))
@@ -110,3 +142,1121 @@ async fn list_models() {
     assert_eq!(resp.data[1].owned_by, None);
     mock.assert_async().await;
 }
+
+#[tokio::test]
+async fn retries_transient_status_then_succeeds() {
+    let mut server = Server::new_async().await;
+    let failing = server
+        .mock("GET", "/models")
+        .with_status(503)
+        .expect(2)
+        .create_async()
+        .await;
+    let ok = server
+        .mock("GET", "/models")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": [{"id": "grok-3"}]}"#)
+        .create_async()
+        .await;
+
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(fast_retry(3));
+    let resp: ModelsResponse = client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+        .expect("third attempt should succeed");
+
+    assert_eq!(resp.data[0].id, "grok-3");
+    failing.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/models")
+        .with_status(502)
+        .expect(3)
+        .create_async()
+        .await;
+
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(fast_retry(3));
+    let result = client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Api { status, .. }) if status == 502));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn does_not_retry_post_server_errors_when_unsafe_retries_are_off() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .with_status(502)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let policy = RetryPolicy {
+        retry_unsafe: false,
+        ..fast_retry(3)
+    };
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(policy);
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let result = client
+        .request::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req))
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Api { status, .. }) if status == 502));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn retries_post_on_429_without_retry_after() {
+    let mut server = Server::new_async().await;
+    let limited = server
+        .mock("POST", "/chat/completions")
+        .with_status(429)
+        .expect(1)
+        .create_async()
+        .await;
+    let ok = server
+        .mock("POST", "/chat/completions")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(OK_BODY)
+        .create_async()
+        .await;
+
+    let policy = RetryPolicy {
+        retry_unsafe: false,
+        ..fast_retry(2)
+    };
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(policy);
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let result = client
+        .request::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req))
+        .await;
+
+    assert!(result.is_ok());
+    limited.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn does_not_retry_client_errors() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .with_status(400)
+        .with_body(r#"{"error": "bad request"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(fast_retry(3));
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let result = client
+        .request::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req))
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Api { status, .. }) if status == 400));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn honours_retry_after_header() {
+    let mut server = Server::new_async().await;
+    let limited = server
+        .mock("POST", "/chat/completions")
+        .with_status(429)
+        .with_header("retry-after", "1")
+        .expect(1)
+        .create_async()
+        .await;
+    let ok = server
+        .mock("POST", "/chat/completions")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(OK_BODY)
+        .create_async()
+        .await;
+
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(fast_retry(2));
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let start = Instant::now();
+    let result = client
+        .request::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req))
+        .await;
+
+    assert!(result.is_ok());
+    assert!(
+        start.elapsed() >= Duration::from_secs(1),
+        "should wait for Retry-After, waited {:?}",
+        start.elapsed()
+    );
+    limited.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn retry_after_beyond_max_delay_is_capped() {
+    let mut server = Server::new_async().await;
+    let limited = server
+        .mock("POST", "/chat/completions")
+        .with_status(503)
+        .with_header("retry-after", "120")
+        .expect(1)
+        .create_async()
+        .await;
+    let ok = server
+        .mock("POST", "/chat/completions")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(OK_BODY)
+        .create_async()
+        .await;
+
+    let policy = RetryPolicy {
+        max_delay: Duration::from_millis(100),
+        ..fast_retry(2)
+    };
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(policy);
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let start = Instant::now();
+    let result = client
+        .request::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req))
+        .await;
+
+    assert!(result.is_ok());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    limited.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn retries_connect_errors() {
+    // Bind then drop a listener so the port is (almost certainly) closed.
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(50),
+        ..fast_retry(3)
+    };
+    let client = XaiClient::with_base_url("test-key".into(), format!("http://{addr}"))
+        .with_retry_policy(policy);
+
+    let start = Instant::now();
+    let result = client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await;
+
+    // Two retries: 50ms + 100ms of backoff.
+    assert!(matches!(result, Err(ApiError::Reqwest(ref e)) if e.is_connect()));
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn stream_chat_events() {
+    let mut server = Server::new_async().await;
+    let body = concat!(
+        ": keep-alive\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hel\"}}]}\r\n\r\n",
+        "event: chunk\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: [DONE]\n\n",
+        "data: {\"ignored\": true}\n\n",
+    );
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .match_header("accept", "text/event-stream")
+        .match_body(Matcher::PartialJsonString(r#"{"stream": true}"#.into()))
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_body(body)
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let mut req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    req.stream = Some(true);
+    let mut stream = client
+        .request_stream(Method::POST, "/chat/completions", Some(&req))
+        .await
+        .expect("stream should open");
+
+    let mut acc = ChatStreamAccumulator::default();
+    let mut deltas = Vec::new();
+    while let Some(chunk) = stream.next_json::<ChatCompletionChunk>().await {
+        deltas.push(acc.push(chunk.expect("chunk should decode")));
+    }
+    let resp = acc.finish();
+
+    assert_eq!(deltas, ["Hel", "lo"]);
+    assert_eq!(resp.choices[0].message.content.as_deref(), Some("Hello"));
+    assert_eq!(resp.choices[0].finish_reason.as_deref(), Some("stop"));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn stream_multiline_data_and_missing_trailing_newline() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/responses")
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_body("event: note\ndata: line one\ndata: line two\n\ndata: tail")
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let mut stream = client
+        .request_stream::<()>(Method::POST, "/responses", None)
+        .await
+        .expect("stream should open");
+
+    let first = stream.next_event().await.unwrap().unwrap();
+    assert_eq!(first.event.as_deref(), Some("note"));
+    assert_eq!(first.data, "line one\nline two");
+    let second = stream.next_event().await.unwrap().unwrap();
+    assert_eq!(second.data, "tail");
+    assert!(stream.next_event().await.is_none());
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn stream_http_error_returns_api_error() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .with_status(401)
+        .with_body(r#"{"error": "bad key"}"#)
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let result = client
+        .request_stream(Method::POST, "/chat/completions", Some(&req))
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Api { status, .. }) if status == 401));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn error_body_is_decoded_and_classified() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/models")
+        .with_status(401)
+        .with_body(r#"{"code": "Client specified an invalid argument", "error": "Incorrect API key provided"}"#)
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let Err(err) = client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+    else {
+        panic!("401 should be an error");
+    };
+
+    assert_eq!(err.kind(), ApiErrorKind::Unauthorized);
+    let ApiError::Api { detail, .. } = &err else {
+        panic!("expected ApiError::Api, got {err:?}");
+    };
+    assert_eq!(detail.message, "Incorrect API key provided");
+    assert!(err.to_string().contains("Incorrect API key provided"));
+    mock.assert_async().await;
+}
+
+/// A mock that stalls for `delay` before writing the response body.
+async fn slow_mock(server: &mut mockito::ServerGuard, delay: Duration) -> mockito::Mock {
+    server
+        .mock("POST", "/chat/completions")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_chunked_body(move |w| {
+            std::thread::sleep(delay);
+            w.write_all(OK_BODY.as_bytes())
+        })
+        .create_async()
+        .await
+}
+
+#[tokio::test]
+async fn cancellation_aborts_in_flight_request() {
+    let mut server = Server::new_async().await;
+    let _mock = slow_mock(&mut server, Duration::from_secs(3)).await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let cancel = CancellationToken::new();
+    let opts = RequestOptions {
+        cancel: Some(cancel.clone()),
+        ..RequestOptions::default()
+    };
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+    });
+
+    let start = Instant::now();
+    let result = client
+        .request_with::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req), &opts)
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Cancelled)));
+    assert_eq!(result.unwrap_err().kind(), ApiErrorKind::Cancelled);
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn cancellation_interrupts_retry_wait() {
+    let mut server = Server::new_async().await;
+    let _mock = server
+        .mock("POST", "/chat/completions")
+        .with_status(503)
+        .with_header("retry-after", "5")
+        .create_async()
+        .await;
+
+    let policy = RetryPolicy {
+        base_delay: Duration::from_secs(5),
+        ..fast_retry(3)
+    };
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(policy);
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let cancel = CancellationToken::new();
+    let opts = RequestOptions {
+        cancel: Some(cancel.clone()),
+        ..RequestOptions::default()
+    };
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel.cancel();
+    });
+
+    let start = Instant::now();
+    let result = client
+        .request_with::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req), &opts)
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Cancelled)));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn per_call_timeout_overrides_default() {
+    let mut server = Server::new_async().await;
+    let _mock = slow_mock(&mut server, Duration::from_secs(2)).await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url())
+        .with_retry_policy(RetryPolicy::none());
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let opts = RequestOptions {
+        timeout: Some(Duration::from_millis(200)),
+        ..RequestOptions::default()
+    };
+
+    let start = Instant::now();
+    let result = client
+        .request_with::<_, ChatResponse>(Method::POST, "/chat/completions", Some(&req), &opts)
+        .await;
+
+    let Err(err) = result else {
+        panic!("request should time out");
+    };
+    assert_eq!(err.kind(), ApiErrorKind::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn rate_limiter_adapts_to_remaining_header() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/models")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_header("x-ratelimit-remaining-requests", "0")
+        .with_body(r#"{"data": []}"#)
+        .expect(2)
+        .create_async()
+        .await;
+
+    // 600 rpm allows a large burst, but the server says none remain, so the
+    // second request must wait for one refill (100ms at 10 requests/sec).
+    let client = XaiClient::with_base_url("test-key".into(), server.url())
+        .with_rate_limiter(RateLimiter::new(Some(600), None));
+    client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+        .expect("first request should succeed");
+
+    let start = Instant::now();
+    client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+        .expect("second request should succeed");
+
+    assert!(
+        start.elapsed() >= Duration::from_millis(90),
+        "second request should be throttled, took {:?}",
+        start.elapsed()
+    );
+    mock.assert_async().await;
+}
+
+fn two_key_pool() -> KeyPool {
+    KeyPool::new(
+        [("key-a".to_string(), 1), ("key-b".to_string(), 1)],
+        KeySelection::RoundRobin,
+    )
+}
+
+#[tokio::test]
+async fn rate_limit_headers_only_throttle_the_serving_key() {
+    let mut server = Server::new_async().await;
+    let exhausted = server
+        .mock("GET", "/models")
+        .match_header("Authorization", "Bearer key-a")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_header("x-ratelimit-limit-requests", "6")
+        .with_header("x-ratelimit-remaining-requests", "0")
+        .with_body(r#"{"data": []}"#)
+        .expect(1)
+        .create_async()
+        .await;
+    let fresh = server
+        .mock("GET", "/models")
+        .match_header("Authorization", "Bearer key-b")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": []}"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    // key-a reports no requests left (a 10s wait at 6 rpm); key-b must not
+    // inherit that.
+    let client = XaiClient::with_base_url(String::new(), server.url())
+        .with_key_pool(two_key_pool())
+        .with_rate_limiter(RateLimiter::unlimited());
+    client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+        .expect("key-a should serve the first request");
+
+    let start = Instant::now();
+    client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+        .expect("key-b should serve the second request");
+
+    assert!(start.elapsed() < Duration::from_secs(2));
+    exhausted.assert_async().await;
+    fresh.assert_async().await;
+}
+
+#[tokio::test]
+async fn fails_over_to_next_key_on_rate_limit() {
+    let mut server = Server::new_async().await;
+    let limited = server
+        .mock("GET", "/models")
+        .match_header("Authorization", "Bearer key-a")
+        .with_status(429)
+        .expect(1)
+        .create_async()
+        .await;
+    let ok = server
+        .mock("GET", "/models")
+        .match_header("Authorization", "Bearer key-b")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": []}"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    // A single attempt is enough: failover does not consume retries.
+    let client = XaiClient::with_base_url(String::new(), server.url())
+        .with_key_pool(two_key_pool())
+        .with_retry_policy(RetryPolicy::none());
+    client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+        .expect("second key should succeed");
+
+    limited.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn unauthorized_key_leaves_rotation() {
+    let mut server = Server::new_async().await;
+    let revoked = server
+        .mock("GET", "/models")
+        .match_header("Authorization", "Bearer key-a")
+        .with_status(401)
+        .with_body(r#"{"error": "Incorrect API key provided"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+    let ok = server
+        .mock("GET", "/models")
+        .match_header("Authorization", "Bearer key-b")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": []}"#)
+        .expect(3)
+        .create_async()
+        .await;
+
+    // Round-robin would alternate, but key-a is not tried again.
+    let client = XaiClient::with_base_url(String::new(), server.url())
+        .with_key_pool(two_key_pool())
+        .with_retry_policy(RetryPolicy::none());
+    for _ in 0..3 {
+        client
+            .request::<(), ModelsResponse>(Method::GET, "/models", None)
+            .await
+            .expect("key-b should serve every request");
+    }
+
+    revoked.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn unauthorized_on_every_key_returns_error() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/models")
+        .with_status(401)
+        .with_body(r#"{"error": "Incorrect API key provided"}"#)
+        .expect(2)
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url(String::new(), server.url())
+        .with_key_pool(two_key_pool())
+        .with_retry_policy(fast_retry(3));
+    let Err(err) = client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+    else {
+        panic!("all keys rejected should be an error");
+    };
+
+    assert_eq!(err.kind(), ApiErrorKind::Unauthorized);
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn image_generation_round_trip() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/images/generations")
+        .match_body(Matcher::Json(serde_json::json!({
+            "model": "grok-2-image",
+            "prompt": "a fox",
+            "n": 2,
+            "response_format": "b64_json"
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            r#"{"data": [
+                {"b64_json": "/9j/AAAA", "revised_prompt": "a red fox in snow"},
+                {"b64_json": "/9j/BBBB", "revised_prompt": "a red fox at dusk"}
+            ]}"#,
+        )
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let req = ImageGenerationRequest {
+        model: "grok-2-image".into(),
+        prompt: "a fox".into(),
+        n: Some(2),
+        response_format: Some("b64_json".into()),
+    };
+    let resp: ImageGenerationResponse = client
+        .request(Method::POST, "/images/generations", Some(&req))
+        .await
+        .expect("request should succeed");
+
+    assert_eq!(resp.data.len(), 2);
+    assert_eq!(resp.data[0].b64_json.as_deref(), Some("/9j/AAAA"));
+    assert_eq!(
+        resp.data[1].revised_prompt.as_deref(),
+        Some("a red fox at dusk")
+    );
+    assert!(resp.data[0].url.is_none());
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn tokenize_round_trip() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/tokenize-text")
+        .match_body(Matcher::Json(serde_json::json!({
+            "text": "Hello world",
+            "model": "grok-4.3"
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            r#"{"token_ids": [
+                {"token_id": 9906, "string_token": "Hello", "token_bytes": [72, 101, 108, 108, 111]},
+                {"token_id": 1917, "string_token": " world", "token_bytes": [32, 119, 111, 114, 108, 100]}
+            ]}"#,
+        )
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let req = TokenizeRequest {
+        text: "Hello world".into(),
+        model: "grok-4.3".into(),
+    };
+    let resp: TokenizeResponse = client
+        .request(Method::POST, "/tokenize-text", Some(&req))
+        .await
+        .expect("request should succeed");
+
+    assert_eq!(resp.count(), 2);
+    assert_eq!(resp.token_ids[1].string_token, " world");
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn deferred_completion_submit_then_poll() {
+    let mut server = Server::new_async().await;
+    let submit = server
+        .mock("POST", "/chat/completions")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "model": "grok-4.3",
+            "deferred": true
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"request_id": "req-123"}"#)
+        .create_async()
+        .await;
+    let pending = server
+        .mock("GET", "/chat/deferred-completion/req-123")
+        .with_status(202)
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let opts = RequestOptions::default();
+    let req = ChatRequest::new("grok-4.3", vec![ChatMessage::user("Think hard")]);
+    let id = client.submit_deferred(req, &opts).await.unwrap();
+    assert_eq!(id, "req-123");
+    assert!(client.deferred_result(&id, &opts).await.unwrap().is_none());
+    submit.assert_async().await;
+    pending.assert_async().await;
+    pending.remove_async().await;
+
+    server
+        .mock("GET", "/chat/deferred-completion/req-123")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(OK_BODY)
+        .create_async()
+        .await;
+    let resp = client.deferred_result(&id, &opts).await.unwrap().unwrap();
+    assert_eq!(resp.choices[0].message.content.as_deref(), Some("ok"));
+}
+
+#[tokio::test]
+async fn deferred_submit_is_never_retried() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .with_status(503)
+        .with_header("retry-after", "0")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let client =
+        XaiClient::with_base_url("test-key".into(), server.url()).with_retry_policy(fast_retry(3));
+    let req = ChatRequest::new("grok-4.3", vec![ChatMessage::user("Think hard")]);
+    let result = client
+        .submit_deferred(req, &RequestOptions::default())
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Api { status, .. }) if status == 503));
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn responses_continuation_round_trip() {
+    let mut server = Server::new_async().await;
+    let create = server
+        .mock("POST", "/responses")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "previous_response_id": "resp_1",
+            "store": true
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            r#"{"id": "resp_2", "output": [{"type": "message",
+                "content": [{"type": "output_text", "text": "Still Paris."}]}]}"#,
+        )
+        .create_async()
+        .await;
+    let delete = server
+        .mock("DELETE", "/responses/resp_2")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"id": "resp_2", "object": "response", "deleted": true}"#)
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let mut req = ResponsesRequest::new("grok-4.3", vec![ResponsesMessage::user("And now?")]);
+    req.previous_response_id = Some("resp_1".into());
+    req.store = Some(true);
+    let resp: ResponsesResponse = client
+        .request(Method::POST, "/responses", Some(&req))
+        .await
+        .unwrap();
+    assert_eq!(resp.id.as_deref(), Some("resp_2"));
+
+    let deleted: ResponseDeleted = client
+        .request::<(), _>(Method::DELETE, "/responses/resp_2", None)
+        .await
+        .unwrap();
+    assert!(deleted.deleted);
+    create.assert_async().await;
+    delete.assert_async().await;
+}
+
+#[tokio::test]
+async fn base64_embeddings_decode_to_floats() {
+    let mut server = Server::new_async().await;
+    // [1.0, -2.0] as little-endian f32s.
+    let mock = server
+        .mock("POST", "/embeddings")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "encoding_format": "base64",
+            "dimensions": 2
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data": [{"index": 0, "embedding": "AACAPwAAAMA="}]}"#)
+        .create_async()
+        .await;
+
+    let client = XaiClient::with_base_url("test-key".into(), server.url());
+    let mut req = EmbeddingRequest::new("grok-2-text-embedding", serde_json::json!("hi"));
+    req.encoding_format = Some("base64".into());
+    req.dimensions = Some(2);
+    let resp: EmbeddingResponse = client
+        .request(Method::POST, "/embeddings", Some(&req))
+        .await
+        .unwrap();
+    assert_eq!(resp.data[0].embedding, [1.0, -2.0]);
+    mock.assert_async().await;
+}
+
+// -- transport options ---
+//
+// The fixtures under tests/fixtures/tls are a throwaway CA plus a server
+// certificate for localhost/127.0.0.1 and a client certificate, all signed by
+// that CA. Keys are PKCS#8.
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/fixtures/tls/{name}", env!("CARGO_MANIFEST_DIR"));
+    std::fs::read(&path).unwrap_or_else(|e| panic!("read {path}: {e}"))
+}
+
+/// Read one HTTP request head, answer it with an empty model list and return
+/// the request line.
+async fn answer_models<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> String {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.expect("read request");
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let body = r#"{"data": []}"#;
+    let response = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .expect("write response");
+    stream.shutdown().await.ok();
+
+    String::from_utf8_lossy(&head)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// A local HTTPS stand-in for the xAI API serving the fixture certificate.
+/// Handles a single connection; returns its base URL.
+async fn tls_stand_in() -> String {
+    let identity = native_tls::Identity::from_pkcs8(&fixture("server.pem"), &fixture("server.key"))
+        .expect("server identity");
+    let acceptor = tokio_native_tls::TlsAcceptor::from(
+        native_tls::TlsAcceptor::new(identity).expect("tls acceptor"),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        // A client that rejects our certificate aborts the handshake.
+        if let Ok(tls) = acceptor.accept(tcp).await {
+            answer_models(tls).await;
+        }
+    });
+
+    format!("https://localhost:{port}")
+}
+
+async fn get_models(client: &XaiClient) -> Result<ModelsResponse, ApiError> {
+    client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+}
+
+#[tokio::test]
+async fn custom_ca_trusts_private_tls_endpoint() {
+    let opts = HttpOptions {
+        ca_certs_pem: vec![fixture("ca.pem")],
+        client_identity_pem: Some((fixture("client.pem"), fixture("client.key"))),
+        connect_timeout: Some(Duration::from_secs(5)),
+        ..HttpOptions::default()
+    };
+    let client = XaiClient::with_base_url("test-key".into(), tls_stand_in().await)
+        .with_retry_policy(RetryPolicy::none())
+        .with_http_options(&opts)
+        .expect("valid transport options");
+
+    let resp = get_models(&client).await.expect("CA should be trusted");
+    assert!(resp.data.is_empty());
+}
+
+#[tokio::test]
+async fn private_tls_endpoint_rejected_without_ca() {
+    let client = XaiClient::with_base_url("test-key".into(), tls_stand_in().await)
+        .with_retry_policy(RetryPolicy::none());
+
+    let Err(err) = get_models(&client).await else {
+        panic!("untrusted certificate should be rejected");
+    };
+    assert_eq!(err.kind(), ApiErrorKind::Network);
+}
+
+#[tokio::test]
+async fn invalid_tls_material_is_reported() {
+    let bad_identity = HttpOptions {
+        client_identity_pem: Some((fixture("client.pem"), b"not a key".to_vec())),
+        ..HttpOptions::default()
+    };
+    assert!(bad_identity.build_client().is_err());
+
+    let bad_ca = HttpOptions {
+        ca_certs_pem: vec![
+            b"-----BEGIN CERTIFICATE-----\ngarbage\n-----END CERTIFICATE-----\n".to_vec(),
+        ],
+        ..HttpOptions::default()
+    };
+    assert!(bad_ca.build_client().is_err());
+}
+
+#[tokio::test]
+async fn requests_go_through_configured_proxy() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_url = format!("http://{}", listener.local_addr().unwrap());
+    let proxy = tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        answer_models(tcp).await
+    });
+
+    let opts = HttpOptions {
+        proxy: Some(proxy_url),
+        ..HttpOptions::default()
+    };
+    // The upstream host never resolves; only the proxy can answer.
+    let client = XaiClient::with_base_url("test-key".into(), "http://api.x.invalid/v1".into())
+        .with_retry_policy(RetryPolicy::none())
+        .with_http_options(&opts)
+        .unwrap();
+
+    get_models(&client).await.expect("proxied request");
+    let request_line = proxy.await.unwrap();
+    assert_eq!(request_line, "GET http://api.x.invalid/v1/models HTTP/1.1");
+}
+
+#[tokio::test]
+async fn no_proxy_hosts_bypass_proxy() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/models")
+        .with_status(200)
+        .with_body(r#"{"data": []}"#)
+        .create_async()
+        .await;
+
+    // Nothing listens on the proxy port, so any proxied request would fail.
+    let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_url = format!("http://{}", dead.local_addr().unwrap());
+    drop(dead);
+
+    let opts = HttpOptions {
+        proxy: Some(proxy_url),
+        no_proxy: vec!["127.0.0.1".into()],
+        ..HttpOptions::default()
+    };
+    let client = XaiClient::with_base_url("test-key".into(), server.url())
+        .with_retry_policy(RetryPolicy::none())
+        .with_http_options(&opts)
+        .unwrap();
+
+    get_models(&client).await.expect("direct request");
+    mock.assert_async().await;
+}
+
+// -- cassettes ---
+
+/// Nothing listens here, so any request that reaches the network fails.
+const UNREACHABLE: &str = "http://127.0.0.1:9";
+
+#[tokio::test]
+async fn cassette_records_then_replays_offline() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chat.json");
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(OK_BODY)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    let recorder = XaiClient::with_base_url("xai-secret-key-1234".into(), server.url())
+        .with_cassette(Cassette::open(&path, CassetteMode::Record).unwrap());
+    let live: ChatResponse = recorder
+        .request(Method::POST, "/chat/completions", Some(&req))
+        .await
+        .expect("recorded request");
+    mock.assert_async().await;
+
+    let file = std::fs::read_to_string(&path).unwrap();
+    assert!(!file.contains("xai-secret-key"), "API key leaked: {file}");
+    assert!(file.contains("\"/chat/completions\""));
+
+    let replayer = XaiClient::with_base_url("other-key".into(), UNREACHABLE.into())
+        .with_retry_policy(RetryPolicy::none())
+        .with_cassette(Cassette::open(&path, CassetteMode::Replay).unwrap());
+    let replayed: ChatResponse = replayer
+        .request(Method::POST, "/chat/completions", Some(&req))
+        .await
+        .expect("replayed request");
+    assert_eq!(
+        replayed.choices[0].message.content,
+        live.choices[0].message.content
+    );
+}
+
+#[tokio::test]
+async fn cassette_replays_retries_and_streams() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stream.json");
+    let sse =
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\ndata: [DONE]\n\n";
+    std::fs::write(
+        &path,
+        serde_json::json!({
+            "interactions": [
+                {
+                    "method": "POST",
+                    "path": "/chat/completions",
+                    "body": {"model": "test-model", "messages": [{"role": "user", "content": "hello"}], "stream": true},
+                    "status": 503,
+                    "headers": {"retry-after": "0"},
+                    "response": {"error": "overloaded"}
+                },
+                {
+                    "method": "POST",
+                    "path": "/chat/completions",
+                    "body": {"stream": true, "messages": [{"content": "hello", "role": "user"}], "model": "test-model"},
+                    "status": 200,
+                    "headers": {"content-type": "text/event-stream"},
+                    "response": sse
+                }
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let client = XaiClient::with_base_url("test-key".into(), UNREACHABLE.into())
+        .with_retry_policy(fast_retry(2))
+        .with_cassette(Cassette::open(&path, CassetteMode::Replay).unwrap());
+    let mut req = ChatRequest::new("test-model", vec![ChatMessage::user("hello")]);
+    req.stream = Some(true);
+
+    let mut stream = client
+        .request_stream(Method::POST, "/chat/completions", Some(&req))
+        .await
+        .expect("503 is retried against the next recorded response");
+    let chunk: ChatCompletionChunk = stream.next_json().await.unwrap().unwrap();
+    assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("hi"));
+    assert!(stream.next_event().await.is_none());
+}
+
+#[tokio::test]
+async fn cassette_miss_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("empty.json");
+    std::fs::write(&path, r#"{"interactions": []}"#).unwrap();
+
+    let client = XaiClient::with_base_url("test-key".into(), UNREACHABLE.into())
+        .with_cassette(Cassette::open(&path, CassetteMode::Replay).unwrap());
+    let Err(err) = client
+        .request::<(), ModelsResponse>(Method::GET, "/models", None)
+        .await
+    else {
+        panic!("unrecorded request should fail");
+    };
+    assert!(matches!(
+        err,
+        ApiError::Cassette(CassetteError::NoMatch { .. })
+    ));
+}